@@ -3,13 +3,37 @@ mod util;
 use serde::{Deserialize, Serialize};
 use std::env;
 use std::io::{Read, Write};
+use util::agent::handle_agent;
+use util::askpass::run_askpass;
+use util::audit::handle_audit;
+use util::backup::handle_config;
 use util::completion::run_interactive_shell;
+use util::doctor::handle_doctor;
+use util::forward::handle_forward;
 use util::help::*;
+use util::inventory::handle_keys;
+use util::journal::{handle_history, handle_undo};
+use util::key::{handle_key, handle_keygen};
+use util::known_hosts::handle_known_hosts;
+use util::resolve::handle_show;
 use util::ssh::*;
+use util::tunnel::handle_tunnel;
+use util::unlock::{handle_lock, handle_status, handle_unlock, run_unlock_agent};
+use util::vault::handle_vault;
 use util::ui::launch_tui;
 use util::zellij::*;
 
 fn main() {
+    // ssh and ssh-add run velo as their askpass helper with the prompt as
+    // the only argument; answer it before anything else.
+    if let Some(status) = run_askpass() {
+        std::process::exit(status);
+    }
+    // `velo unlock` starts a copy of velo in the background as the agent.
+    if let Some(status) = run_unlock_agent() {
+        std::process::exit(status);
+    }
+
     launch_tui().unwrap();
     env::set_var("RUST_BACKTRACE", "1");
 
@@ -41,8 +65,36 @@ fn main() {
         "add" => {
             if rest_args.contains(&"-h".to_string()) {
                 print_add_help();
-            } else {
-                handle_add_connection(rest_args);
+            } else if let Err(e) = handle_add_connection(rest_args) {
+                eprintln!("Error adding connection: {}", e);
+            }
+        }
+        "edit" => {
+            if rest_args.contains(&"-h".to_string()) {
+                print_edit_help();
+            } else if let Err(e) = handle_edit_connection(rest_args) {
+                eprintln!("Error editing connection: {}", e);
+            }
+        }
+        "rename" => {
+            if rest_args.contains(&"-h".to_string()) {
+                print_rename_help();
+            } else if let Err(e) = handle_rename_connection(rest_args) {
+                eprintln!("Error renaming connection: {}", e);
+            }
+        }
+        "clone" => {
+            if rest_args.contains(&"-h".to_string()) {
+                print_clone_help();
+            } else if let Err(e) = handle_clone_connection(rest_args) {
+                eprintln!("Error cloning connection: {}", e);
+            }
+        }
+        "show" => {
+            if rest_args.contains(&"-h".to_string()) {
+                print_show_help();
+            } else if let Err(e) = handle_show(rest_args) {
+                eprintln!("Error showing connection: {}", e);
             }
         }
         "list" | "ls" => {
@@ -55,8 +107,8 @@ fn main() {
         "remove" | "rm" => {
             if rest_args.contains(&"-h".to_string()) {
                 print_remove_help();
-            } else {
-                handle_remove_connection(rest_args);
+            } else if let Err(e) = handle_remove_connection(rest_args) {
+                eprintln!("Error removing connection: {}", e);
             }
         }
         "add-key" => {
@@ -75,6 +127,132 @@ fn main() {
                 handle_zellij(rest_args);
             }
         }
+        "forward" => {
+            if rest_args.contains(&"-h".to_string()) {
+                print_forward_help();
+            } else if let Err(e) = handle_forward(rest_args) {
+                eprintln!("Error managing forwards: {}", e);
+            }
+        }
+        "tunnel" => {
+            if rest_args.contains(&"-h".to_string()) {
+                print_tunnel_help();
+            } else if let Err(e) = handle_tunnel(rest_args) {
+                eprintln!("Error managing tunnels: {}", e);
+            }
+        }
+        "config" => {
+            if rest_args.contains(&"-h".to_string()) {
+                print_config_help();
+            } else if let Err(e) = handle_config(rest_args) {
+                eprintln!("Error managing config backups: {}", e);
+            }
+        }
+        "keygen" => {
+            if rest_args.contains(&"-h".to_string()) {
+                print_keygen_help();
+            } else if let Err(e) = handle_keygen(rest_args) {
+                eprintln!("Error generating key: {}", e);
+            }
+        }
+        "copy-id" => {
+            if rest_args.contains(&"-h".to_string()) {
+                print_copy_id_help();
+            } else if let Err(e) = handle_copy_id(rest_args) {
+                eprintln!("Error copying SSH key: {}", e);
+            }
+        }
+        "audit" => {
+            if rest_args.contains(&"-h".to_string()) {
+                print_audit_help();
+            } else {
+                match handle_audit(rest_args) {
+                    Ok(true) => {}
+                    Ok(false) => std::process::exit(1),
+                    Err(e) => {
+                        eprintln!("Error auditing config: {}", e);
+                        std::process::exit(2);
+                    }
+                }
+            }
+        }
+        "known-hosts" => {
+            if rest_args.contains(&"-h".to_string()) {
+                print_known_hosts_help();
+            } else if let Err(e) = handle_known_hosts(rest_args) {
+                eprintln!("Error managing known_hosts: {}", e);
+            }
+        }
+        "agent" => {
+            if rest_args.contains(&"-h".to_string()) {
+                print_agent_help();
+            } else if let Err(e) = handle_agent(rest_args) {
+                eprintln!("Error managing ssh-agent: {}", e);
+            }
+        }
+        "doctor" => {
+            if rest_args.contains(&"-h".to_string()) {
+                print_doctor_help();
+            } else if let Err(e) = handle_doctor(rest_args) {
+                eprintln!("Error running checks: {}", e);
+            }
+        }
+        "keys" => {
+            if rest_args.contains(&"-h".to_string()) {
+                print_keys_help();
+            } else if let Err(e) = handle_keys(rest_args) {
+                eprintln!("Error listing keys: {}", e);
+            }
+        }
+        "key" => {
+            if rest_args.contains(&"-h".to_string()) {
+                print_key_help();
+            } else if let Err(e) = handle_key(rest_args) {
+                eprintln!("Error managing keys: {}", e);
+            }
+        }
+        "vault" => {
+            if rest_args.contains(&"-h".to_string()) {
+                print_vault_help();
+            } else if let Err(e) = handle_vault(rest_args) {
+                eprintln!("Error accessing the vault: {}", e);
+            }
+        }
+        "undo" => {
+            if rest_args.contains(&"-h".to_string()) {
+                print_undo_help();
+            } else if let Err(e) = handle_undo(rest_args) {
+                eprintln!("Error undoing changes: {}", e);
+            }
+        }
+        "history" => {
+            if rest_args.contains(&"-h".to_string()) {
+                print_history_help();
+            } else if let Err(e) = handle_history(rest_args) {
+                eprintln!("Error reading history: {}", e);
+            }
+        }
+        "unlock" => {
+            if rest_args.contains(&"-h".to_string()) {
+                print_unlock_help();
+            } else if let Err(e) = handle_unlock(rest_args) {
+                eprintln!("Error unlocking: {}", e);
+            }
+        }
+        "lock" => {
+            if rest_args.contains(&"-h".to_string()) {
+                print_lock_help();
+            } else if let Err(e) = handle_lock(rest_args) {
+                eprintln!("Error locking: {}", e);
+            }
+        }
+        "status" => {
+            if rest_args.contains(&"-h".to_string()) {
+                print_status_help();
+            } else if let Err(e) = handle_status(rest_args) {
+                eprintln!("Error reading the unlock agent's status: {}", e);
+            }
+        }
         _ => println!("Unknown command: {}. Use 'velo -h' for help.", command),
     }
 }
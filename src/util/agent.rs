@@ -0,0 +1,798 @@
+// The ssh-agent velo shares between its invocations. Rather than starting a
+// new agent each time it finds none, velo runs one per user on a fixed
+// socket in the runtime directory and records its pid, so later commands,
+// Zellij panes and shells (through `velo agent env`) all talk to the same
+// agent and the keys loaded into it. Keys can be added with a lifetime and
+// confirmation, set per connection by a `# velo:agent` comment in its block;
+// since ssh-agent cannot tell how long a key has left, velo notes when it
+// added each key next to the socket.
+
+use crate::util::backup::atomic_write;
+use crate::util::inventory::{
+    connection_identities, key_inventory, parse_key_listing, print_table,
+};
+use crate::util::key::{find_key, key_fingerprint, ssh_add};
+use crate::util::resolve::display_path;
+use crate::util::ssh::{velo_runtime_dir, SSHConfig};
+use crate::util::tunnel::{format_duration, now};
+use serde::{Deserialize, Serialize};
+use ssh_key::{HashAlg, PublicKey};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// The agent velo started.
+pub struct Agent {
+    pub socket: PathBuf,
+    pub pid: u32,
+}
+
+impl Agent {
+    /// Makes this process, and the commands it runs, use the agent.
+    fn export(&self) {
+        env::set_var("SSH_AUTH_SOCK", &self.socket);
+        env::set_var("SSH_AGENT_PID", self.pid.to_string());
+    }
+
+    /// Shell commands that point a shell at the agent, as `ssh-agent -s`
+    /// prints them.
+    fn shell_env(&self) -> String {
+        format!(
+            "SSH_AUTH_SOCK={}; export SSH_AUTH_SOCK;\nSSH_AGENT_PID={}; export SSH_AGENT_PID;",
+            shell_quote(&self.socket.to_string_lossy()),
+            self.pid
+        )
+    }
+}
+
+fn socket_path() -> io::Result<PathBuf> {
+    Ok(velo_runtime_dir()?.join("ssh-agent.sock"))
+}
+
+fn pid_path() -> io::Result<PathBuf> {
+    Ok(velo_runtime_dir()?.join("ssh-agent.pid"))
+}
+
+/// Whether the agent in SSH_AUTH_SOCK, if any, answers.
+fn env_agent_answers() -> bool {
+    if env::var_os("SSH_AUTH_SOCK").is_none() {
+        return false;
+    }
+    // ssh-add -l exits with 1 when the agent has no keys and 2 when there is
+    // no agent to talk to.
+    Command::new("ssh-add")
+        .arg("-l")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.code() != Some(2))
+}
+
+#[cfg(unix)]
+fn socket_answers(socket: &Path) -> bool {
+    std::os::unix::net::UnixStream::connect(socket).is_ok()
+}
+
+#[cfg(not(unix))]
+fn socket_answers(_socket: &Path) -> bool {
+    false
+}
+
+/// Whether `pid` is a running ssh-agent. The pid file could have outlived
+/// the agent and its pid been reused by something else.
+#[cfg(unix)]
+fn is_ssh_agent(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    if unsafe { libc::kill(pid, 0) } != 0 {
+        return false;
+    }
+    Command::new("ps")
+        .args(["-o", "comm=", "-p", &pid.to_string()])
+        .output()
+        .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).contains("ssh-agent"))
+}
+
+#[cfg(not(unix))]
+fn is_ssh_agent(_pid: u32) -> bool {
+    false
+}
+
+fn read_pid() -> io::Result<Option<u32>> {
+    match fs::read_to_string(pid_path()?) {
+        Ok(text) => Ok(text.trim().parse().ok()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn remove_agent_files() -> io::Result<()> {
+    for path in [pid_path()?, socket_path()?] {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// The agent velo started, if it is still running and answering. Files
+/// left behind by an agent that died are removed.
+fn velo_agent() -> io::Result<Option<Agent>> {
+    let Some(pid) = read_pid()? else {
+        return Ok(None);
+    };
+    let socket = socket_path()?;
+    if is_ssh_agent(pid) && socket_answers(&socket) {
+        return Ok(Some(Agent { socket, pid }));
+    }
+    remove_agent_files()?;
+    Ok(None)
+}
+
+/// The value `ssh-agent -s` gives `name`, from a line such as
+/// `SSH_AGENT_PID=42; export SSH_AGENT_PID;`.
+fn agent_var(output: &str, name: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let value = line.strip_prefix(name)?.strip_prefix('=')?;
+        Some(value.split(';').next()?.trim_matches('"').to_string())
+    })
+}
+
+#[cfg(unix)]
+fn start_agent() -> io::Result<Agent> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let socket = socket_path()?;
+    // Nothing answers on it, or velo_agent would have found the agent.
+    let _ = fs::remove_file(&socket);
+    let output = Command::new("ssh-agent")
+        .arg("-a")
+        .arg(&socket)
+        .arg("-s")
+        .stdin(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "Failed to start ssh-agent: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let pid = agent_var(&String::from_utf8_lossy(&output.stdout), "SSH_AGENT_PID")
+        .and_then(|pid| pid.parse().ok())
+        .ok_or_else(|| io::Error::other("ssh-agent did not say what its pid is"))?;
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .mode(0o600)
+        .open(pid_path()?)?;
+    io::Write::write_all(&mut file, format!("{}\n", pid).as_bytes())?;
+    Ok(Agent { socket, pid })
+}
+
+#[cfg(not(unix))]
+fn start_agent() -> io::Result<Agent> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "velo can only start ssh-agent on Unix",
+    ))
+}
+
+/// velo's agent, started if it is not running. Returns whether it was
+/// started.
+fn shared_agent() -> io::Result<(Agent, bool)> {
+    // Held so two velo processes do not both start an agent on the socket.
+    let mut lock = fd_lock::RwLock::new(
+        fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(velo_runtime_dir()?.join("ssh-agent.lock"))?,
+    );
+    let _guard = lock.write()?;
+    if let Some(agent) = velo_agent()? {
+        return Ok((agent, false));
+    }
+    Ok((start_agent()?, true))
+}
+
+/// Points this process at a running agent: the one in SSH_AUTH_SOCK if it
+/// answers, else the one velo started earlier. Returns false when there is
+/// neither.
+pub fn connect_agent() -> bool {
+    if env_agent_answers() {
+        return true;
+    }
+    match velo_agent() {
+        Ok(Some(agent)) => {
+            agent.export();
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Makes sure this process has an agent, starting velo's own if needed.
+pub fn ensure_agent() -> io::Result<()> {
+    if connect_agent() {
+        return Ok(());
+    }
+    let (agent, started) = shared_agent()?;
+    if started {
+        println!("Started ssh-agent (pid {}).", agent.pid);
+    }
+    agent.export();
+    Ok(())
+}
+
+/// The `ssh-add -t` and `-c` constraints a key is added with.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct KeyConstraints {
+    /// As ssh-add takes it, e.g. `8h` or `1h30m`.
+    pub lifetime: Option<String>,
+    /// Have the agent ask before each use of the key.
+    pub confirm: bool,
+}
+
+impl KeyConstraints {
+    /// Reads `--lifetime <time>` and `--confirm`.
+    fn from_args(args: &[String]) -> Result<KeyConstraints, String> {
+        let mut constraints = KeyConstraints::default();
+        let mut flags = args.iter();
+        while let Some(flag) = flags.next() {
+            match flag.as_str() {
+                "--lifetime" => match flags.next() {
+                    Some(value) => constraints.lifetime = Some(value.clone()),
+                    None => return Err("Missing value for --lifetime".to_string()),
+                },
+                "--confirm" => constraints.confirm = true,
+                other => return Err(format!("Unknown option: {}", other)),
+            }
+        }
+        constraints.check()?;
+        Ok(constraints)
+    }
+
+    /// Reads a connection's defaults from its `# velo:agent` comment, e.g.
+    /// `# velo:agent lifetime=8h confirm`.
+    fn from_tag(tag: &str) -> Result<KeyConstraints, String> {
+        let mut constraints = KeyConstraints::default();
+        for word in tag.split_whitespace() {
+            match word.split_once('=') {
+                Some(("lifetime", value)) => constraints.lifetime = Some(value.to_string()),
+                None if word == "confirm" => constraints.confirm = true,
+                _ => return Err(format!("Unknown agent setting '{}'", word)),
+            }
+        }
+        constraints.check()?;
+        Ok(constraints)
+    }
+
+    fn to_tag(&self) -> Option<String> {
+        let mut words = Vec::new();
+        if let Some(lifetime) = &self.lifetime {
+            words.push(format!("lifetime={}", lifetime));
+        }
+        if self.confirm {
+            words.push("confirm".to_string());
+        }
+        (!words.is_empty()).then(|| words.join(" "))
+    }
+
+    fn check(&self) -> Result<(), String> {
+        match &self.lifetime {
+            Some(lifetime) if parse_lifetime(lifetime).is_none() => Err(format!(
+                "'{}' is not a lifetime; use e.g. 30m, 8h or 1h30m",
+                lifetime
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match (&self.lifetime, self.confirm) {
+            (None, false) => "no lifetime".to_string(),
+            (None, true) => "no lifetime, confirm each use".to_string(),
+            (Some(lifetime), false) => format!("lifetime {}", lifetime),
+            (Some(lifetime), true) => format!("lifetime {}, confirm each use", lifetime),
+        }
+    }
+}
+
+/// Seconds in a lifetime as ssh-add -t takes it: a number of seconds, or
+/// numbers followed by s, m, h, d or w, e.g. `1h30m`.
+fn parse_lifetime(text: &str) -> Option<u64> {
+    let mut total: u64 = 0;
+    let mut number = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return None,
+        };
+        total = total.checked_add(number.parse::<u64>().ok()?.checked_mul(unit)?)?;
+        number.clear();
+    }
+    if !number.is_empty() {
+        total = total.checked_add(number.parse().ok()?)?;
+    }
+    (total > 0).then_some(total)
+}
+
+/// How long a key added with a lifetime has left, e.g. `7h59m left`.
+pub fn lifetime_left(expires: Option<u64>) -> Option<String> {
+    let expires = expires?;
+    let now = now();
+    if expires <= now {
+        return Some("expired".to_string());
+    }
+    Some(format!("{} left", format_duration(expires - now)))
+}
+
+/// What velo remembers of a key it added, since ssh-agent does not say how
+/// long a key has left.
+#[derive(Serialize, Deserialize)]
+struct AddedKey {
+    /// The agent's socket and pid, so what velo noted for one agent is not
+    /// applied to another.
+    agent: String,
+    fingerprint: String,
+    expires: Option<u64>,
+    confirm: bool,
+}
+
+fn added_keys_path() -> io::Result<PathBuf> {
+    Ok(velo_runtime_dir()?.join("ssh-agent-keys.json"))
+}
+
+fn agent_id() -> String {
+    let var = |name| env::var(name).unwrap_or_default();
+    format!("{}:{}", var("SSH_AUTH_SOCK"), var("SSH_AGENT_PID"))
+}
+
+/// The keys velo added whose lifetime has not run out.
+fn added_keys() -> Vec<AddedKey> {
+    let Ok(text) = added_keys_path().and_then(fs::read_to_string) else {
+        return Vec::new();
+    };
+    let mut keys: Vec<AddedKey> = serde_json::from_str(&text).unwrap_or_default();
+    let now = now();
+    keys.retain(|key| key.expires.is_none_or(|expires| expires > now));
+    keys
+}
+
+/// Notes how `fingerprint` was added to the agent, or with `None` that it
+/// was taken out.
+fn note_added_key(fingerprint: &str, constraints: Option<&KeyConstraints>) -> io::Result<()> {
+    let agent = agent_id();
+    let mut keys = added_keys();
+    keys.retain(|key| key.agent != agent || key.fingerprint != fingerprint);
+    if let Some(constraints) = constraints {
+        keys.push(AddedKey {
+            agent,
+            fingerprint: fingerprint.to_string(),
+            expires: constraints
+                .lifetime
+                .as_deref()
+                .and_then(parse_lifetime)
+                .map(|secs| now() + secs),
+            confirm: constraints.confirm,
+        });
+    }
+    atomic_write(
+        &added_keys_path()?,
+        serde_json::to_string_pretty(&keys)?.as_bytes(),
+    )
+}
+
+/// A key the agent holds.
+pub struct AgentKey {
+    pub key_type: String,
+    pub bits: Option<u32>,
+    pub fingerprint: String,
+    pub comment: String,
+    /// When the agent drops the key, as seconds since the epoch, if velo
+    /// added it with a lifetime.
+    pub expires: Option<u64>,
+    pub confirm: bool,
+}
+
+/// The keys in the agent this process uses, or `None` when there is no
+/// agent.
+pub fn agent_keys() -> Option<Vec<AgentKey>> {
+    if !connect_agent() {
+        return None;
+    }
+    let output = Command::new("ssh-add")
+        .args(["-l", "-E", "sha256"])
+        .output()
+        .ok()?;
+    if output.status.code() == Some(2) {
+        return None;
+    }
+    let agent = agent_id();
+    let added = added_keys();
+    let keys = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(parse_key_listing)
+        .map(|(key_type, bits, fingerprint, comment)| {
+            let noted = added
+                .iter()
+                .find(|key| key.agent == agent && key.fingerprint == fingerprint);
+            AgentKey {
+                key_type,
+                bits,
+                expires: noted.and_then(|key| key.expires),
+                confirm: noted.is_some_and(|key| key.confirm),
+                fingerprint,
+                comment,
+            }
+        })
+        .collect();
+    Some(keys)
+}
+
+/// Adds the key at `key_path` to the agent with `constraints`.
+fn add_key(key_path: &Path, constraints: &KeyConstraints) -> io::Result<()> {
+    let fingerprint = key_fingerprint(key_path)?;
+    let lifetime = constraints
+        .lifetime
+        .as_deref()
+        .and_then(parse_lifetime)
+        .map(|secs| secs.to_string());
+    let mut args = Vec::new();
+    if let Some(lifetime) = &lifetime {
+        args.extend(["-t", lifetime.as_str()]);
+    }
+    if constraints.confirm {
+        args.push("-c");
+    }
+    let output = ssh_add(key_path, &args)?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "Could not add {} to ssh-agent: {}",
+            display_path(key_path),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    note_added_key(&fingerprint, Some(constraints))
+}
+
+/// The agent defaults of `connection`, from the `# velo:agent` comment in
+/// its block.
+fn connection_constraints(config: &SSHConfig, connection: &str) -> io::Result<KeyConstraints> {
+    match config
+        .host_block(connection)
+        .and_then(|block| block.tag("agent"))
+    {
+        Some(tag) => KeyConstraints::from_tag(tag).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} in the velo:agent comment of {}", e, connection),
+            )
+        }),
+        None => Ok(KeyConstraints::default()),
+    }
+}
+
+/// Loads the keys `connection` names with IdentityFile that the agent does
+/// not hold yet, with the connection's lifetime and confirmation.
+pub fn load_connection_keys(connection: &str) -> io::Result<()> {
+    let config = SSHConfig::new()?;
+    let identities: Vec<PathBuf> = connection_identities(&config, connection)
+        .into_iter()
+        .filter(|path| path.is_file())
+        .collect();
+    if identities.is_empty() {
+        return Ok(());
+    }
+    let constraints = connection_constraints(&config, connection)?;
+    ensure_agent()?;
+
+    let loaded: Vec<String> = agent_keys()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|key| key.fingerprint)
+        .collect();
+    for path in identities {
+        let Ok(fingerprint) = key_fingerprint(&path) else {
+            continue;
+        };
+        if loaded.contains(&fingerprint) {
+            continue;
+        }
+        match add_key(&path, &constraints) {
+            Ok(()) => println!(
+                "Added {} to ssh-agent ({}).",
+                display_path(&path),
+                constraints.describe()
+            ),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+    Ok(())
+}
+
+fn list_keys() -> io::Result<()> {
+    let Some(keys) = agent_keys() else {
+        println!("No ssh-agent is running.");
+        return Ok(());
+    };
+    if keys.is_empty() {
+        println!("ssh-agent holds no keys.");
+        return Ok(());
+    }
+
+    let inventory = key_inventory()?;
+    let rows: Vec<Vec<String>> = keys
+        .iter()
+        .map(|key| {
+            let path = inventory
+                .keys
+                .iter()
+                .find(|info| info.fingerprint == key.fingerprint)
+                .map_or("-".to_string(), |info| display_path(&info.path));
+            vec![
+                path,
+                match key.bits {
+                    Some(bits) => format!("{} {}", key.key_type, bits),
+                    None => key.key_type.clone(),
+                },
+                key.fingerprint.clone(),
+                key.comment.clone(),
+                lifetime_left(key.expires).unwrap_or_else(|| "-".to_string()),
+                if key.confirm { "yes" } else { "-" }.to_string(),
+            ]
+        })
+        .collect();
+    print_table(
+        &[
+            "KEY",
+            "TYPE",
+            "FINGERPRINT",
+            "COMMENT",
+            "LIFETIME",
+            "CONFIRM",
+        ],
+        &rows,
+    );
+    Ok(())
+}
+
+fn add(args: &[String]) -> io::Result<()> {
+    let usage = "Usage: velo agent add <key_path|fingerprint> [--lifetime <time>] [--confirm]";
+    let Some(key) = args.first() else {
+        println!("{}", usage);
+        return Ok(());
+    };
+    let constraints = match KeyConstraints::from_args(&args[1..]) {
+        Ok(constraints) => constraints,
+        Err(e) => {
+            println!("{}", e);
+            println!("{}", usage);
+            return Ok(());
+        }
+    };
+    let Some(path) = find_key(key)?.filter(|path| path.is_file()) else {
+        println!("No key found for {}", key);
+        return Ok(());
+    };
+
+    ensure_agent()?;
+    add_key(&path, &constraints)?;
+    println!(
+        "Added {} to ssh-agent ({}).",
+        display_path(&path),
+        constraints.describe()
+    );
+    Ok(())
+}
+
+fn remove(args: &[String]) -> io::Result<()> {
+    let [key] = args else {
+        println!("Usage: velo agent remove <key_path|fingerprint>");
+        return Ok(());
+    };
+    let fingerprint = if key.starts_with("SHA256:") {
+        key.to_string()
+    } else {
+        key_fingerprint(Path::new(key))?
+    };
+    if !connect_agent() {
+        println!("No ssh-agent is running.");
+        return Ok(());
+    }
+
+    // ssh-add -d wants a key file; the agent's own copy of the public key
+    // works even when the key file is gone.
+    let output = Command::new("ssh-add").arg("-L").output()?;
+    let public = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find(|line| {
+            PublicKey::from_openssh(line)
+                .is_ok_and(|public| public.fingerprint(HashAlg::Sha256).to_string() == fingerprint)
+        })
+        .map(str::to_string);
+    let Some(public) = public else {
+        println!("ssh-agent does not hold {}", key);
+        return Ok(());
+    };
+
+    let temp = velo_runtime_dir()?.join(format!("remove-{}.pub", std::process::id()));
+    fs::write(&temp, format!("{}\n", public))?;
+    let output = Command::new("ssh-add").arg("-d").arg(&temp).output();
+    let _ = fs::remove_file(&temp);
+    let output = output?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "Could not remove {}: {}",
+            fingerprint,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    note_added_key(&fingerprint, None)?;
+    println!("Removed {} from ssh-agent.", fingerprint);
+    Ok(())
+}
+
+/// Shows or sets the lifetime and confirmation `velo ssh` loads a
+/// connection's keys with.
+fn defaults(args: &[String]) -> io::Result<()> {
+    let usage =
+        "Usage: velo agent defaults <connection_name> [--lifetime <time>] [--confirm] [--clear]";
+    let Some(connection) = args.first() else {
+        println!("{}", usage);
+        return Ok(());
+    };
+    let mut config = SSHConfig::new()?;
+    if config.host_block(connection).is_none() {
+        println!("Connection '{}' not found", connection);
+        return Ok(());
+    }
+
+    let options = &args[1..];
+    if options.is_empty() {
+        let constraints = connection_constraints(&config, connection)?;
+        println!(
+            "velo ssh {} loads its keys with {}.",
+            connection,
+            constraints.describe()
+        );
+        return Ok(());
+    }
+    let constraints = if options == ["--clear"] {
+        KeyConstraints::default()
+    } else {
+        match KeyConstraints::from_args(options) {
+            Ok(constraints) => constraints,
+            Err(e) => {
+                println!("{}", e);
+                println!("{}", usage);
+                return Ok(());
+            }
+        }
+    };
+    config.set_tag(connection, "agent", constraints.to_tag().as_deref())?;
+    println!(
+        "velo ssh {} now loads its keys with {}.",
+        connection,
+        constraints.describe()
+    );
+    Ok(())
+}
+
+fn shell_quote(value: &str) -> String {
+    if value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "/._-+:@%".contains(c))
+    {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', r"'\''"))
+    }
+}
+
+fn print_env() -> io::Result<()> {
+    let (agent, started) = shared_agent()?;
+    // stdout is for the shell to eval.
+    if started {
+        eprintln!("Started ssh-agent (pid {}).", agent.pid);
+    }
+    println!("{}", agent.shell_env());
+    Ok(())
+}
+
+#[cfg(unix)]
+fn stop() -> io::Result<()> {
+    let socket = socket_path()?;
+    let Some(pid) = read_pid()? else {
+        println!("velo has not started an ssh-agent.");
+        return Ok(());
+    };
+    // The pid may have been reused by another ssh-agent; only velo's answers
+    // on velo's socket.
+    if !is_ssh_agent(pid) || !socket_answers(&socket) {
+        remove_agent_files()?;
+        println!("The ssh-agent velo started (pid {}) is not running.", pid);
+        return Ok(());
+    }
+    // is_ssh_agent checked that pid fits.
+    if unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    remove_agent_files()?;
+    println!("Stopped ssh-agent (pid {}).", pid);
+    if env::var_os("SSH_AUTH_SOCK").is_some_and(|sock| Path::new(&sock) == socket) {
+        println!("Run `unset SSH_AUTH_SOCK SSH_AGENT_PID` to forget it in this shell.");
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn stop() -> io::Result<()> {
+    println!("velo has not started an ssh-agent.");
+    Ok(())
+}
+
+pub fn handle_agent(args: &[String]) -> io::Result<()> {
+    match args.first().map(String::as_str) {
+        Some("env") => print_env(),
+        Some("stop") => stop(),
+        Some("list") => list_keys(),
+        Some("add") => add(&args[1..]),
+        Some("remove") => remove(&args[1..]),
+        Some("defaults") => defaults(&args[1..]),
+        Some(other) => {
+            println!("Unknown agent command: {}", other);
+            Ok(())
+        }
+        None => {
+            println!("Usage: velo agent <command>");
+            println!("Commands: env, stop, list, add, remove, defaults");
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lifetimes() {
+        assert_eq!(parse_lifetime("90"), Some(90));
+        assert_eq!(parse_lifetime("8h"), Some(8 * 3600));
+        assert_eq!(parse_lifetime("1h30m"), Some(5400));
+        assert_eq!(parse_lifetime("1W"), Some(7 * 86400));
+        assert_eq!(parse_lifetime("0"), None);
+        assert_eq!(parse_lifetime("8 hours"), None);
+        assert_eq!(parse_lifetime(""), None);
+    }
+
+    #[test]
+    fn constraints_round_trip_through_the_tag() {
+        let constraints = KeyConstraints::from_args(&[
+            "--lifetime".to_string(),
+            "8h".to_string(),
+            "--confirm".to_string(),
+        ])
+        .unwrap();
+        let tag = constraints.to_tag().unwrap();
+        assert_eq!(tag, "lifetime=8h confirm");
+        assert_eq!(KeyConstraints::from_tag(&tag), Ok(constraints));
+        assert_eq!(KeyConstraints::default().to_tag(), None);
+        assert!(KeyConstraints::from_tag("lifetime=soon").is_err());
+    }
+}
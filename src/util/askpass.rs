@@ -0,0 +1,403 @@
+// Password logins. When a connection has a stored password, ssh is started
+// with SSH_ASKPASS pointing back at velo, which answers the password prompt
+// from the keyring or the vault. The password never appears on a command
+// line or in the environment; only the connection name is passed along.
+// ssh before OpenSSH 8.4 cannot be forced to use askpass, so there velo falls
+// back to `sshpass -e`. ssh-add is pointed at the same helper to unlock keys
+// whose passphrase is stored.
+
+use crate::util::key::stored_key_passphrase;
+use crate::util::keyring::{has_secret, keyring, lookup_secret};
+use crate::util::ssh::velo_data_dir;
+use crate::util::vault::{unlock_vault, SecretKind, Vault};
+use regex::Regex;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::Command;
+use zeroize::Zeroizing;
+
+/// Set for ssh's askpass helper to the connection being logged into. velo
+/// started with it set is answering a prompt, not running a command.
+pub const ASKPASS_ENV: &str = "VELO_ASKPASS";
+
+/// Set for ssh-add's askpass helper to the fingerprint of the key being
+/// unlocked.
+pub const ASKPASS_KEY_ENV: &str = "VELO_ASKPASS_KEY";
+
+/// The keyring service velo's secrets are stored under.
+pub const KEYRING_SERVICE: &str = "velo";
+
+// SSH_ASKPASS_REQUIRE appeared in this OpenSSH release.
+const ASKPASS_REQUIRE_VERSION: (u32, u32) = (8, 4);
+
+/// How ssh gets a connection's stored password.
+pub enum PasswordLogin {
+    /// No stored password; ssh asks the user as usual.
+    None,
+    Askpass,
+    Sshpass,
+}
+
+impl PasswordLogin {
+    /// Picks how to log into `connection`, checking only whether a password
+    /// is stored, not reading it.
+    pub fn for_connection(connection: &str) -> io::Result<Self> {
+        if !has_password(connection)? {
+            return Ok(PasswordLogin::None);
+        }
+        if askpass_supported() {
+            return Ok(PasswordLogin::Askpass);
+        }
+        if sshpass_available() {
+            return Ok(PasswordLogin::Sshpass);
+        }
+        println!("This ssh cannot use velo for passwords and sshpass is not installed; enter the password when asked.");
+        Ok(PasswordLogin::None)
+    }
+
+    /// Whether ssh can still be started inside a Zellij pane. Panes do not
+    /// inherit velo's environment, which sshpass needs.
+    pub fn works_in_zellij(&self) -> bool {
+        !matches!(self, PasswordLogin::Sshpass)
+    }
+
+    /// The ssh command for `connection` with `ssh_args`, set up to log in
+    /// with the stored password.
+    pub fn command(&self, connection: &str, ssh_args: &[String]) -> io::Result<Command> {
+        match self {
+            PasswordLogin::None => {
+                let mut command = Command::new("ssh");
+                command.args(ssh_args);
+                Ok(command)
+            }
+            PasswordLogin::Askpass => {
+                let mut command = Command::new("ssh");
+                command.args(ssh_args);
+                for (key, value) in askpass_env(ASKPASS_ENV, connection)? {
+                    command.env(key, value);
+                }
+                Ok(command)
+            }
+            PasswordLogin::Sshpass => {
+                let Some(password) = stored_password(connection)? else {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("No password stored for '{}'", connection),
+                    ));
+                };
+                let mut command = Command::new("sshpass");
+                command
+                    .arg("-e")
+                    .arg("ssh")
+                    .args(ssh_args)
+                    .env("SSHPASS", password.as_str());
+                Ok(command)
+            }
+        }
+    }
+
+    /// The program and arguments to run ssh through `env` where velo cannot
+    /// set the environment itself, such as in a Zellij pane. None of it is
+    /// secret.
+    pub fn env_prefix(&self, connection: &str) -> io::Result<Vec<String>> {
+        match self {
+            PasswordLogin::Askpass => {
+                let mut prefix = vec!["env".to_string()];
+                for (key, value) in askpass_env(ASKPASS_ENV, connection)? {
+                    prefix.push(format!("{}={}", key, value));
+                }
+                Ok(prefix)
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+}
+
+/// The environment that makes ssh or ssh-add ask velo, with `var` telling
+/// velo what the secret is for.
+pub fn askpass_env(var: &'static str, value: &str) -> io::Result<Vec<(&'static str, String)>> {
+    let exe = env::current_exe()?;
+    Ok(vec![
+        ("SSH_ASKPASS", exe.to_string_lossy().into_owned()),
+        ("SSH_ASKPASS_REQUIRE", "force".to_string()),
+        (var, value.to_string()),
+    ])
+}
+
+fn keyring_user(connection: &str) -> String {
+    format!("password:{}", connection)
+}
+
+fn has_password(connection: &str) -> io::Result<bool> {
+    if has_secret(KEYRING_SERVICE, &keyring_user(connection)) {
+        return Ok(true);
+    }
+    Vault::has_secret(connection, SecretKind::Password)
+}
+
+/// The password from the keyring, or else from the vault, which asks for
+/// the master password on the terminal.
+fn stored_password(connection: &str) -> io::Result<Option<Zeroizing<String>>> {
+    if let Some(password) = lookup_secret(KEYRING_SERVICE, &keyring_user(connection)) {
+        return Ok(Some(password));
+    }
+    if !Vault::has_secret(connection, SecretKind::Password)? {
+        return Ok(None);
+    }
+    unlock_vault()?.get(connection, SecretKind::Password)
+}
+
+/// Moves what is stored for `old`, its password in the keyring and its
+/// vault secrets, over to `new`. Anything left under `new` by an earlier
+/// connection of that name is dropped.
+pub fn move_connection_secrets(old: &str, new: &str) -> io::Result<()> {
+    let keyring = keyring()?;
+    let (old_user, new_user) = (keyring_user(old), keyring_user(new));
+    keyring.delete(KEYRING_SERVICE, &new_user)?;
+    if keyring.contains(KEYRING_SERVICE, &old_user)? {
+        if let Some(password) = keyring.get(KEYRING_SERVICE, &old_user)? {
+            keyring.set(KEYRING_SERVICE, &new_user, &password)?;
+        }
+        keyring.delete(KEYRING_SERVICE, &old_user)?;
+    }
+
+    if Vault::has_secrets(old)? || Vault::has_secrets(new)? {
+        println!("Moving the vault secrets of '{}' to '{}'.", old, new);
+        let mut vault = unlock_vault()?;
+        if !vault.rename_connection(old, new)? {
+            vault.remove_connection(new)?;
+        }
+    }
+    Ok(())
+}
+
+/// Deletes everything stored for `connection`, so a later connection of
+/// the same name is not sent its secrets.
+pub fn forget_connection_secrets(connection: &str) -> io::Result<()> {
+    keyring()?.delete(KEYRING_SERVICE, &keyring_user(connection))?;
+    if Vault::has_secrets(connection)? {
+        println!("Removing the vault secrets of '{}'.", connection);
+        unlock_vault()?.remove_connection(connection)?;
+    }
+    Ok(())
+}
+
+/// Whether the installed ssh honours SSH_ASKPASS_REQUIRE.
+pub fn askpass_supported() -> bool {
+    let Ok(output) = Command::new("ssh").arg("-V").output() else {
+        return false;
+    };
+    // ssh -V prints to stderr.
+    ssh_version(&String::from_utf8_lossy(&output.stderr))
+        .is_some_and(|version| version >= ASKPASS_REQUIRE_VERSION)
+}
+
+/// The OpenSSH release in `ssh -V` output such as
+/// "OpenSSH_9.6p1, OpenSSL 3.0.13".
+fn ssh_version(output: &str) -> Option<(u32, u32)> {
+    let pattern = Regex::new(r"OpenSSH_(?:for_Windows_)?(\d+)\.(\d+)").unwrap();
+    let captures = pattern.captures(output)?;
+    Some((captures[1].parse().ok()?, captures[2].parse().ok()?))
+}
+
+fn sshpass_available() -> bool {
+    Command::new("sshpass").arg("-V").output().is_ok()
+}
+
+/// If velo was started as an askpass helper, answers the prompt and returns
+/// the exit status to end with.
+pub fn run_askpass() -> Option<i32> {
+    let prompt = env::args().nth(1).unwrap_or_default();
+    if let Ok(connection) = env::var(ASKPASS_ENV) {
+        return Some(handle_askpass(&prompt, is_password_prompt, || {
+            stored_password(&connection)
+        }));
+    }
+    if let Ok(fingerprint) = env::var(ASKPASS_KEY_ENV) {
+        return Some(handle_askpass(&prompt, is_passphrase_prompt, || {
+            stored_key_passphrase(&fingerprint)
+        }));
+    }
+    None
+}
+
+/// Answers one prompt as an askpass helper: the answer goes to stdout and
+/// the exit status tells the caller whether there is one. Prompts for which
+/// `wants_secret` holds are answered with `stored` once per calling process,
+/// so a wrong secret is not retried; everything else is asked on the
+/// terminal.
+fn handle_askpass(
+    prompt: &str,
+    wants_secret: fn(&str) -> bool,
+    stored: impl FnOnce() -> io::Result<Option<Zeroizing<String>>>,
+) -> i32 {
+    let kind = env::var("SSH_ASKPASS_PROMPT").unwrap_or_default();
+    let answer = match kind.as_str() {
+        // Informational; ssh only wants it shown.
+        "none" => read_tty_line(&format!("{} [Enter]", prompt)).map(|_| None),
+        "confirm" => read_tty_line(prompt).map(|line| {
+            let yes = matches!(line.trim().to_lowercase().as_str(), "y" | "yes");
+            yes.then(|| Zeroizing::new(String::new()))
+        }),
+        _ if wants_secret(prompt) && first_answer() => stored().and_then(|secret| match secret {
+            Some(secret) => Ok(Some(secret)),
+            None => ask_hidden(prompt).map(Some),
+        }),
+        _ if prompt.contains("(yes/no") => {
+            read_tty_line(prompt).map(|line| Some(Zeroizing::new(line)))
+        }
+        _ => ask_hidden(prompt).map(Some),
+    };
+
+    match answer {
+        Ok(Some(answer)) => {
+            let mut stdout = io::stdout();
+            if writeln!(stdout, "{}", answer.as_str()).is_err() {
+                return 1;
+            }
+            0
+        }
+        Ok(None) => 1,
+        Err(e) => {
+            eprintln!("velo: {}", e);
+            1
+        }
+    }
+}
+
+fn is_password_prompt(prompt: &str) -> bool {
+    prompt.to_lowercase().contains("password")
+}
+
+fn is_passphrase_prompt(prompt: &str) -> bool {
+    prompt.to_lowercase().contains("passphrase")
+}
+
+/// True the first time it is called for the running ssh process. Each call
+/// leaves a marker named after ssh's pid; markers of ssh processes that have
+/// exited are cleared.
+#[cfg(unix)]
+fn first_answer() -> bool {
+    let Ok(dir) = velo_data_dir().map(|dir| dir.join("askpass")) else {
+        return true;
+    };
+    first_answer_in(&dir, std::os::unix::process::parent_id())
+}
+
+/// `first_answer` with the markers kept in `dir`, for the ssh process `pid`.
+#[cfg(unix)]
+fn first_answer_in(dir: &Path, pid: u32) -> bool {
+    if fs::create_dir_all(dir).is_err() {
+        return true;
+    }
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let pid = entry.file_name().to_string_lossy().parse::<libc::pid_t>();
+            if pid.is_ok_and(|pid| unsafe { libc::kill(pid, 0) } != 0) {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+    let marker = dir.join(pid.to_string());
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(marker)
+        .is_ok()
+}
+
+#[cfg(not(unix))]
+fn first_answer() -> bool {
+    true
+}
+
+fn ask_hidden(prompt: &str) -> io::Result<Zeroizing<String>> {
+    rpassword::prompt_password(prompt).map(Zeroizing::new)
+}
+
+/// Reads a visible line from the terminal; askpass's stdout belongs to ssh.
+#[cfg(unix)]
+fn read_tty_line(prompt: &str) -> io::Result<String> {
+    let mut tty = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")?;
+    write!(tty, "{}", prompt)?;
+    tty.flush()?;
+    let mut line = String::new();
+    BufReader::new(tty).read_line(&mut line)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+#[cfg(not(unix))]
+fn read_tty_line(prompt: &str) -> io::Result<String> {
+    eprint!("{}", prompt);
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::testing::temp_path;
+
+    #[test]
+    fn password_and_passphrase_prompts() {
+        assert!(is_password_prompt("alice@web's password: "));
+        assert!(is_password_prompt("Password for alice@web: "));
+        assert!(!is_password_prompt(
+            "Enter passphrase for key '/home/a/.ssh/id': "
+        ));
+        assert!(is_passphrase_prompt(
+            "Enter passphrase for key '/home/a/.ssh/id': "
+        ));
+        assert!(is_passphrase_prompt(
+            "Enter PASSPHRASE for /home/a/.ssh/id_ed25519:"
+        ));
+        assert!(!is_passphrase_prompt("alice@web's password: "));
+        assert!(!is_password_prompt(
+            "Are you sure you want to continue connecting (yes/no/[fingerprint])?"
+        ));
+    }
+
+    #[test]
+    fn ssh_version_from_banner() {
+        assert_eq!(
+            ssh_version("OpenSSH_9.6p1, OpenSSL 3.0.13 30 Jan 2024"),
+            Some((9, 6))
+        );
+        assert_eq!(
+            ssh_version("OpenSSH_8.4p1 Debian-5, OpenSSL 1.1.1k"),
+            Some((8, 4))
+        );
+        assert_eq!(
+            ssh_version("OpenSSH_for_Windows_8.1p1, LibreSSL 3.0.2"),
+            Some((8, 1))
+        );
+        assert_eq!(ssh_version("ssh: command not found"), None);
+        assert!(ssh_version("OpenSSH_8.4p1").unwrap() >= ASKPASS_REQUIRE_VERSION);
+        assert!(ssh_version("OpenSSH_8.3p1").unwrap() < ASKPASS_REQUIRE_VERSION);
+        assert!(ssh_version("OpenSSH_10.0p2").unwrap() >= ASKPASS_REQUIRE_VERSION);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn answers_once_per_ssh_process() {
+        let dir = temp_path("askpass");
+
+        // A marker left by an ssh that has exited is cleared.
+        let mut exited = Command::new("true").spawn().unwrap();
+        exited.wait().unwrap();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(exited.id().to_string()), "").unwrap();
+
+        let ssh = std::process::id();
+        assert!(first_answer_in(&dir, ssh));
+        assert!(!first_answer_in(&dir, ssh));
+        assert!(!dir.join(exited.id().to_string()).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
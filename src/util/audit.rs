@@ -0,0 +1,518 @@
+// Security lint for the ssh config: `velo audit` walks every file the way
+// ssh reads it and flags settings that weaken host authentication, forward
+// the agent too widely or use broken crypto, each with a fix hint.
+
+use crate::util::inventory::{block_label, expand_identity_path, read_key};
+use crate::util::resolve::display_path;
+use crate::util::ssh::{ssh_dir, ConfigEvent, SSHConfig};
+use crate::util::ssh_config::{
+    is_literal_pattern, match_pattern_list, Block, BlockKind, Directive,
+};
+use serde::Serialize;
+use serde_json::json;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// RSA keys below this many bits can be factored with serious effort; below
+// the second they fall short of current recommendations.
+const MIN_RSA_BITS: u32 = 2048;
+const RECOMMENDED_RSA_BITS: u32 = 3072;
+
+// Tag names that mark a host block as production, as `# velo:tag prod`.
+const PRODUCTION_TAGS: [&str; 2] = ["prod", "production"];
+
+const WEAK_CIPHERS: [&str; 10] = [
+    "3des-cbc",
+    "aes128-cbc",
+    "aes192-cbc",
+    "aes256-cbc",
+    "arcfour",
+    "arcfour128",
+    "arcfour256",
+    "blowfish-cbc",
+    "cast128-cbc",
+    "rijndael-cbc@lysator.liu.se",
+];
+
+const WEAK_KEX: [&str; 3] = [
+    "diffie-hellman-group1-sha1",
+    "diffie-hellman-group14-sha1",
+    "diffie-hellman-group-exchange-sha1",
+];
+
+const WEAK_MACS: [&str; 6] = [
+    "hmac-md5",
+    "hmac-md5-96",
+    "hmac-sha1-96",
+    "hmac-md5-etm@openssh.com",
+    "hmac-md5-96-etm@openssh.com",
+    "hmac-sha1-96-etm@openssh.com",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+}
+
+impl Severity {
+    fn parse(text: &str) -> Option<Self> {
+        match text {
+            "low" => Some(Severity::Low),
+            "medium" => Some(Severity::Medium),
+            "high" => Some(Severity::High),
+            _ => None,
+        }
+    }
+
+    fn sarif_level(self) -> &'static str {
+        match self {
+            Severity::Low => "note",
+            Severity::Medium => "warning",
+            Severity::High => "error",
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+        })
+    }
+}
+
+/// A kind of finding, described once for the SARIF rule list.
+pub struct Rule {
+    pub id: &'static str,
+    pub description: &'static str,
+    pub hint: &'static str,
+}
+
+pub const RULES: [Rule; 6] = [
+    Rule {
+        id: "strict-host-key-checking",
+        description: "StrictHostKeyChecking is off, so ssh accepts any host key, including a changed one",
+        hint: "Use `StrictHostKeyChecking accept-new`, which still trusts new hosts on first use but refuses changed keys",
+    },
+    Rule {
+        id: "known-hosts-discarded",
+        description: "UserKnownHostsFile is /dev/null, so no host key is ever remembered or checked",
+        hint: "Remove the line, or point it at a real file such as ~/.ssh/known_hosts",
+    },
+    Rule {
+        id: "wildcard-agent-forwarding",
+        description: "ForwardAgent is on for every host a pattern matches, so any of them can use your keys while you are connected",
+        hint: "Set `ForwardAgent yes` only on the hosts that need it, or use ProxyJump instead",
+    },
+    Rule {
+        id: "production-password-auth",
+        description: "PasswordAuthentication is on for a production host",
+        hint: "Log in with a key and set `PasswordAuthentication no` on the host",
+    },
+    Rule {
+        id: "weak-algorithms",
+        description: "Ciphers, KexAlgorithms or MACs enable algorithms that are broken or deprecated",
+        hint: "Remove the listed algorithms; OpenSSH's defaults are safe",
+    },
+    Rule {
+        id: "weak-identity",
+        description: "An IdentityFile is a DSA key or a short RSA key",
+        hint: "Generate an Ed25519 key with `velo keygen` and switch hosts over with `velo key rotate`",
+    },
+];
+
+fn rule(id: &str) -> &'static Rule {
+    RULES
+        .iter()
+        .find(|rule| rule.id == id)
+        .expect("unknown audit rule")
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    pub rule: &'static str,
+    pub severity: Severity,
+    /// The block the setting is in: a Host block's patterns, a Match line,
+    /// or `(global)`.
+    pub host: String,
+    pub path: PathBuf,
+    pub line: usize,
+    pub message: String,
+    pub hint: &'static str,
+}
+
+/// What applies to the lines being walked.
+#[derive(Clone)]
+struct Scope {
+    label: String,
+    /// Lines apply to more than one named host: the global section, a Match
+    /// block or a Host line with a wildcard.
+    wildcard: bool,
+    production: bool,
+}
+
+impl Scope {
+    fn global() -> Self {
+        Scope {
+            label: "(global)".to_string(),
+            wildcard: true,
+            production: false,
+        }
+    }
+
+    fn of(block: &Block, production: Option<&str>) -> Self {
+        let wildcard = match block.kind() {
+            BlockKind::Match => true,
+            BlockKind::Host => block
+                .patterns()
+                .iter()
+                .any(|pattern| !pattern.starts_with('!') && !is_literal_pattern(pattern)),
+        };
+        let tagged = block.lines.iter().any(
+            |line| matches!(line.tag(), Some(("tag", name)) if PRODUCTION_TAGS.contains(&name)),
+        );
+        let matched = production.is_some_and(|list| {
+            block
+                .aliases()
+                .iter()
+                .any(|alias| match_pattern_list(alias, list) == Some(true))
+        });
+        Scope {
+            label: block_label(block),
+            wildcard,
+            production: tagged || matched,
+        }
+    }
+}
+
+/// Audits the config. `production` is a pattern list of hosts to treat as
+/// production on top of those tagged in the config.
+pub fn audit(config: &SSHConfig, production: Option<&str>) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut scope = Scope::global();
+    let mut stack = Vec::new();
+
+    config.walk(&mut |event| match event {
+        ConfigEvent::Block { block, .. } => scope = Scope::of(block, production),
+        ConfigEvent::Directive {
+            path,
+            line,
+            directive,
+        } => {
+            if let Some((id, severity, message)) = check_directive(directive, &scope) {
+                findings.push(Finding {
+                    rule: id,
+                    severity,
+                    host: scope.label.clone(),
+                    path: path.to_path_buf(),
+                    line,
+                    message,
+                    hint: rule(id).hint,
+                });
+            }
+        }
+        ConfigEvent::IncludeStart => stack.push(scope.clone()),
+        ConfigEvent::IncludeEnd => {
+            if let Some(saved) = stack.pop() {
+                scope = saved;
+            }
+        }
+    });
+    findings
+}
+
+fn check_directive(
+    directive: &Directive,
+    scope: &Scope,
+) -> Option<(&'static str, Severity, String)> {
+    let value = directive.value().trim_matches('"');
+    let enabled = value.eq_ignore_ascii_case("yes");
+
+    if directive.is("StrictHostKeyChecking")
+        && (value.eq_ignore_ascii_case("no") || value.eq_ignore_ascii_case("off"))
+    {
+        Some((
+            "strict-host-key-checking",
+            Severity::High,
+            format!("StrictHostKeyChecking {} accepts any host key", value),
+        ))
+    } else if directive.is("UserKnownHostsFile")
+        && directive.args().iter().any(|file| file == "/dev/null")
+    {
+        Some((
+            "known-hosts-discarded",
+            Severity::High,
+            "UserKnownHostsFile /dev/null forgets every host key".to_string(),
+        ))
+    } else if directive.is("ForwardAgent") && scope.wildcard && !value.eq_ignore_ascii_case("no") {
+        Some((
+            "wildcard-agent-forwarding",
+            Severity::High,
+            format!(
+                "ForwardAgent {} applies to every host {} matches",
+                value, scope.label
+            ),
+        ))
+    } else if directive.is("PasswordAuthentication") && enabled && scope.production {
+        Some((
+            "production-password-auth",
+            Severity::Medium,
+            format!(
+                "PasswordAuthentication yes on production host {}",
+                scope.label
+            ),
+        ))
+    } else if let Some(weak) = weak_algorithms(directive) {
+        Some((
+            "weak-algorithms",
+            Severity::Medium,
+            format!("{} enables {}", directive.keyword(), weak.join(", ")),
+        ))
+    } else if directive.is("IdentityFile") {
+        check_identity(value).map(|(severity, message)| ("weak-identity", severity, message))
+    } else {
+        None
+    }
+}
+
+/// Weak algorithms a Ciphers, KexAlgorithms or MACs line enables. A list
+/// starting with `-` only removes algorithms.
+fn weak_algorithms(directive: &Directive) -> Option<Vec<String>> {
+    let weak: &[&str] = if directive.is("Ciphers") {
+        &WEAK_CIPHERS
+    } else if directive.is("KexAlgorithms") {
+        &WEAK_KEX
+    } else if directive.is("MACs") {
+        &WEAK_MACS
+    } else {
+        return None;
+    };
+    let list = directive.value().trim_matches('"');
+    if list.starts_with('-') {
+        return None;
+    }
+    let enabled: Vec<String> = list
+        .trim_start_matches(['+', '^'])
+        .split(',')
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| weak.contains(&name.as_str()))
+        .collect();
+    (!enabled.is_empty()).then_some(enabled)
+}
+
+fn check_identity(value: &str) -> Option<(Severity, String)> {
+    let path = expand_identity_path(value)?;
+    let key = read_key(&path)?;
+    let shown = display_path(&path);
+    match (key.key_type.as_str(), key.bits) {
+        ("DSA", _) => Some((
+            Severity::High,
+            format!("{} is a DSA key, which OpenSSH no longer accepts", shown),
+        )),
+        ("RSA", Some(bits)) if bits < MIN_RSA_BITS => Some((
+            Severity::High,
+            format!("{} is a {}-bit RSA key", shown, bits),
+        )),
+        ("RSA", Some(bits)) if bits < RECOMMENDED_RSA_BITS => Some((
+            Severity::Low,
+            format!(
+                "{} is a {}-bit RSA key, below the recommended {}",
+                shown, bits, RECOMMENDED_RSA_BITS
+            ),
+        )),
+        _ => None,
+    }
+}
+
+fn print_text(findings: &[Finding]) {
+    if findings.is_empty() {
+        println!("No findings.");
+        return;
+    }
+    for finding in findings {
+        println!(
+            "{}:{}: [{}] {} ({})",
+            display_path(&finding.path),
+            finding.line,
+            finding.severity,
+            finding.message,
+            finding.host
+        );
+        println!("    {}", rule(finding.rule).description);
+        println!("    Fix: {}", finding.hint);
+    }
+    let count = |severity| findings.iter().filter(|f| f.severity == severity).count();
+    println!(
+        "\n{} finding(s): {} high, {} medium, {} low",
+        findings.len(),
+        count(Severity::High),
+        count(Severity::Medium),
+        count(Severity::Low)
+    );
+}
+
+/// A SARIF 2.1.0 log with one run, for code scanning tools.
+fn sarif(findings: &[Finding]) -> serde_json::Value {
+    let rules: Vec<serde_json::Value> = RULES
+        .iter()
+        .map(|rule| {
+            json!({
+                "id": rule.id,
+                "shortDescription": { "text": rule.description },
+                "help": { "text": rule.hint },
+            })
+        })
+        .collect();
+    let results: Vec<serde_json::Value> = findings
+        .iter()
+        .map(|finding| {
+            json!({
+                "ruleId": finding.rule,
+                "ruleIndex": RULES.iter().position(|rule| rule.id == finding.rule),
+                "level": finding.severity.sarif_level(),
+                "message": { "text": format!("{}. {}", finding.message, finding.hint) },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": artifact_uri(&finding.path) },
+                        "region": { "startLine": finding.line },
+                    },
+                }],
+                "properties": { "severity": finding.severity, "host": finding.host },
+            })
+        })
+        .collect();
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "velo",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                },
+            },
+            "results": results,
+        }],
+    })
+}
+
+/// Paths under the working directory stay relative, so results line up
+/// with files in a checked-out repository; anything else is a file URI.
+fn artifact_uri(path: &Path) -> String {
+    let relative = if path.is_relative() {
+        Some(path.to_path_buf())
+    } else {
+        std::env::current_dir()
+            .ok()
+            .and_then(|dir| path.strip_prefix(dir).ok().map(Path::to_path_buf))
+    };
+    match relative {
+        Some(relative) => relative.to_string_lossy().replace('\\', "/"),
+        None => format!("file://{}", path.to_string_lossy().replace('\\', "/")),
+    }
+}
+
+/// Runs `velo audit`. Returns false when a finding reaches the `--fail-on`
+/// severity, so scripts can fail on it.
+pub fn handle_audit(args: &[String]) -> io::Result<bool> {
+    const USAGE: &str = "Usage: velo audit [--format text|json|sarif] [--fail-on low|medium|high] [--production <patterns>] [--config <file>]";
+    let mut format = &"text".to_string();
+    let mut fail_on = Severity::Low;
+    let mut production = None;
+    let mut config_path = ssh_dir().join("config");
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args.next();
+        let parsed = match arg.as_str() {
+            "--format" => value
+                .filter(|value| ["text", "json", "sarif"].contains(&value.as_str()))
+                .map(|value| format = value),
+            "--fail-on" => value
+                .and_then(|value| Severity::parse(value))
+                .map(|severity| fail_on = severity),
+            "--production" => value.map(|value| production = Some(value.as_str())),
+            "--config" => value.map(|value| config_path = PathBuf::from(value)),
+            _ => None,
+        };
+        if parsed.is_none() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, USAGE));
+        }
+    }
+
+    if !config_path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} does not exist", display_path(&config_path)),
+        ));
+    }
+    let config = SSHConfig::load(&config_path)?;
+    let findings = audit(&config, production);
+    match format.as_str() {
+        "json" => println!("{}", serde_json::to_string_pretty(&findings)?),
+        "sarif" => println!("{}", serde_json::to_string_pretty(&sarif(&findings))?),
+        _ => print_text(&findings),
+    }
+    Ok(!findings.iter().any(|finding| finding.severity >= fail_on))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::ssh_config::ConfigDocument;
+
+    /// Rule ids found in the first block of `text`.
+    fn rules_in(text: &str, production: Option<&str>) -> Vec<&'static str> {
+        let document = ConfigDocument::parse(text);
+        let block = &document.blocks[0];
+        let scope = Scope::of(block, production);
+        block
+            .directives()
+            .filter_map(|directive| check_directive(directive, &scope))
+            .map(|(id, _, _)| id)
+            .collect()
+    }
+
+    #[test]
+    fn agent_forwarding_is_flagged_only_on_wildcards() {
+        assert_eq!(
+            rules_in("Host *.example.com !bastion\n  ForwardAgent yes\n", None),
+            ["wildcard-agent-forwarding"]
+        );
+        assert!(rules_in("Host bastion\n  ForwardAgent yes\n", None).is_empty());
+        assert!(rules_in("Host *\n  ForwardAgent no\n", None).is_empty());
+    }
+
+    #[test]
+    fn production_hosts_come_from_tags_or_patterns() {
+        let tagged = "Host db\n  # velo:tag prod\n  PasswordAuthentication yes\n";
+        assert_eq!(rules_in(tagged, None), ["production-password-auth"]);
+
+        let untagged = "Host db-prod\n  PasswordAuthentication yes\n";
+        assert!(rules_in(untagged, None).is_empty());
+        assert_eq!(
+            rules_in(untagged, Some("*-prod,!staging")),
+            ["production-password-auth"]
+        );
+    }
+
+    #[test]
+    fn weak_algorithms_ignore_removals() {
+        let weak =
+            |keyword: &str, value: &str| weak_algorithms(&Directive::new("", keyword, value, "\n"));
+        assert_eq!(
+            weak("Ciphers", "+aes256-cbc,aes128-ctr,3DES-CBC"),
+            Some(vec!["aes256-cbc".to_string(), "3des-cbc".to_string()])
+        );
+        assert_eq!(weak("KexAlgorithms", "-diffie-hellman-group1-sha1"), None);
+        assert_eq!(weak("MACs", "hmac-sha2-256-etm@openssh.com"), None);
+        assert_eq!(weak("HostKeyAlgorithms", "ssh-dss"), None);
+    }
+}
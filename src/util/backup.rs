@@ -0,0 +1,296 @@
+// Crash-safe writes for ssh config files, plus the timestamped backups taken
+// before each save and the `velo config backups|restore` commands.
+
+use crate::util::journal::{self, FileChange};
+use crate::util::resolve::display_path;
+use crate::util::ssh::{config_lock, prompt_yes_no, velo_data_dir};
+use serde::{Deserialize, Serialize};
+use similar::TextDiff;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+// Older backups beyond this many are deleted after each save.
+const MAX_BACKUPS: usize = 20;
+
+const TIMESTAMP_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+/// The contents of every file one save touched, as they were before it.
+#[derive(Serialize, Deserialize)]
+struct Backup {
+    files: Vec<BackupFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BackupFile {
+    path: PathBuf,
+    contents: String,
+}
+
+/// Replaces `path` with `contents` so that a crash leaves either the old or
+/// the new file, never a partial one: the data goes to a temp file in the
+/// same directory, is fsynced, takes over the original's mode and owner and
+/// is then renamed over it. Symlinks are followed so the link survives.
+pub fn atomic_write(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let temp = dir.join(format!(".{}.velo-{}", name, std::process::id()));
+
+    let result = write_temp(&path, &temp, contents).and_then(|_| fs::rename(&temp, &path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result?;
+
+    // Make the rename itself durable.
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+fn write_temp(path: &Path, temp: &Path, contents: &[u8]) -> io::Result<()> {
+    let original = fs::metadata(path).ok();
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(temp)?;
+    file.write_all(contents)?;
+
+    if let Some(original) = original {
+        file.set_permissions(original.permissions())?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let current = file.metadata()?;
+            if (current.uid(), current.gid()) != (original.uid(), original.gid()) {
+                std::os::unix::fs::fchown(&file, Some(original.uid()), Some(original.gid()))
+                    .map_err(|e| {
+                        io::Error::new(
+                            e.kind(),
+                            format!("Cannot keep the owner of {}: {}", path.display(), e),
+                        )
+                    })?;
+            }
+        }
+    }
+
+    file.sync_all()
+}
+
+fn backup_dir() -> io::Result<PathBuf> {
+    let dir = velo_data_dir()?.join("backups");
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// Saves the current on-disk contents of `paths` as one timestamped backup
+/// and drops the oldest ones past `MAX_BACKUPS`. Files that do not exist
+/// yet are skipped.
+pub fn backup_files(paths: &[&Path]) -> io::Result<()> {
+    let files: Vec<BackupFile> = paths
+        .iter()
+        .filter_map(|path| {
+            let contents = fs::read_to_string(path).ok()?;
+            Some(BackupFile {
+                path: path.to_path_buf(),
+                contents,
+            })
+        })
+        .collect();
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    let dir = backup_dir()?;
+    let stamp = chrono::Local::now().format(TIMESTAMP_FORMAT).to_string();
+    let mut name = stamp.clone();
+    let mut n = 1;
+    while dir.join(format!("{}.json", name)).exists() {
+        name = format!("{}-{}", stamp, n);
+        n += 1;
+    }
+
+    let content = serde_json::to_string_pretty(&Backup { files })?;
+    atomic_write(&dir.join(format!("{}.json", name)), content.as_bytes())?;
+
+    let backups = list_backups()?;
+    for old in &backups[..backups.len().saturating_sub(MAX_BACKUPS)] {
+        fs::remove_file(dir.join(format!("{}.json", old)))?;
+    }
+    Ok(())
+}
+
+/// Backup timestamps, oldest first.
+fn list_backups() -> io::Result<Vec<String>> {
+    let mut names: Vec<String> = fs::read_dir(backup_dir()?)?
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name().to_string_lossy().into_owned();
+            name.strip_suffix(".json").map(str::to_string)
+        })
+        .collect();
+    sort_backups(&mut names);
+    Ok(names)
+}
+
+/// Sorts backup names oldest first. Backups taken in the same second are
+/// named `<stamp>-1`, `<stamp>-2`, ..., which must not sort as text, or
+/// `-10` would come before `-2`.
+fn sort_backups(names: &mut [String]) {
+    // The stamp itself has one dash, between the date and the time.
+    let order = |name: &str| -> (String, u32) {
+        match name.rsplit_once('-') {
+            Some((stamp, n)) if stamp.contains('-') => (stamp.to_string(), n.parse().unwrap_or(0)),
+            _ => (name.to_string(), 0),
+        }
+    };
+    names.sort_by_cached_key(|name| order(name));
+}
+
+fn load_backup(timestamp: &str) -> io::Result<Backup> {
+    let path = backup_dir()?.join(format!("{}.json", timestamp));
+    if !path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No backup '{}'; see 'velo config backups'", timestamp),
+        ));
+    }
+    let content = fs::read_to_string(path)?;
+    serde_json::from_str(&content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn handle_config(args: &[String]) -> io::Result<()> {
+    if args.is_empty() {
+        println!("Usage: velo config <subcommand> [args...]");
+        println!("Subcommands: backups, restore");
+        return Ok(());
+    }
+
+    match args[0].as_str() {
+        "backups" => {
+            let backups = list_backups()?;
+            if backups.is_empty() {
+                println!("No backups yet.");
+            }
+            for timestamp in backups.iter().rev() {
+                let backup = load_backup(timestamp)?;
+                let files: Vec<String> = backup
+                    .files
+                    .iter()
+                    .map(|file| display_path(&file.path))
+                    .collect();
+                println!("{}  {}", timestamp, files.join(", "));
+            }
+            Ok(())
+        }
+        "restore" => {
+            let Some(timestamp) = args.get(1) else {
+                println!("Usage: velo config restore <timestamp>");
+                return Ok(());
+            };
+            restore_backup(timestamp)
+        }
+        _ => {
+            println!("Unknown config subcommand: {}", args[0]);
+            Ok(())
+        }
+    }
+}
+
+fn restore_backup(timestamp: &str) -> io::Result<()> {
+    let backup = match load_backup(timestamp) {
+        Ok(backup) => backup,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            println!("{}", e);
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
+
+    let mut changed = Vec::new();
+    for file in &backup.files {
+        let current = fs::read_to_string(&file.path).unwrap_or_default();
+        if current == file.contents {
+            continue;
+        }
+        let shown = display_path(&file.path);
+        print!(
+            "{}",
+            TextDiff::from_lines(&current, &file.contents)
+                .unified_diff()
+                .header(
+                    &format!("{} (current)", shown),
+                    &format!("{} ({})", shown, timestamp)
+                )
+        );
+        changed.push(file);
+    }
+
+    if changed.is_empty() {
+        println!("The config already matches backup {}.", timestamp);
+        return Ok(());
+    }
+    if !prompt_yes_no("Restore these files? (y/n): ") {
+        println!("Nothing restored.");
+        return Ok(());
+    }
+
+    let mut lock = config_lock()?;
+    let _guard = lock.write()?;
+
+    // Back up what is there now so the restore can itself be reverted.
+    let paths: Vec<&Path> = changed.iter().map(|file| file.path.as_path()).collect();
+    backup_files(&paths)?;
+    let mut changes = Vec::new();
+    for file in changed {
+        let before = fs::read_to_string(&file.path).ok();
+        atomic_write(&file.path, file.contents.as_bytes())?;
+        println!("Restored {}", display_path(&file.path));
+        changes.push(FileChange {
+            path: file.path.clone(),
+            before,
+            after: file.contents.clone(),
+        });
+    }
+    journal::record(&format!("config restore {}", timestamp), changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_second_backups_sort_by_number() {
+        let mut names: Vec<String> = [
+            "20261018-054252-10",
+            "20261018-054253",
+            "20261018-054252-2",
+            "20261018-054252",
+            "20261018-054252-1",
+            "20261017-235959-3",
+        ]
+        .iter()
+        .map(|name| name.to_string())
+        .collect();
+        sort_backups(&mut names);
+        assert_eq!(
+            names,
+            [
+                "20261017-235959-3",
+                "20261018-054252",
+                "20261018-054252-1",
+                "20261018-054252-2",
+                "20261018-054252-10",
+                "20261018-054253",
+            ]
+        );
+    }
+}
@@ -1,5 +1,18 @@
+use crate::util::agent::handle_agent;
+use crate::util::audit::handle_audit;
+use crate::util::backup::handle_config;
+use crate::util::doctor::handle_doctor;
+use crate::util::journal::{handle_history, handle_undo};
+use crate::util::key::{handle_key, handle_keygen};
+use crate::util::known_hosts::handle_known_hosts;
+use crate::util::forward::handle_forward;
 use crate::util::help::*;
+use crate::util::inventory::handle_keys;
+use crate::util::resolve::handle_show;
 use crate::util::ssh::*;
+use crate::util::tunnel::handle_tunnel;
+use crate::util::unlock::{handle_lock, handle_status, handle_unlock};
+use crate::util::vault::handle_vault;
 use crate::util::zellij::handle_zellij;
 use rustyline::completion::{Completer, Pair};
 use rustyline::error::ReadlineError;
@@ -14,6 +27,8 @@ use std::io::Error as IoError;
 pub struct VeloCompleter {
     commands: Vec<String>,
     zellij_subcommands: Vec<String>,
+    forward_subcommands: Vec<String>,
+    tunnel_subcommands: Vec<String>,
 }
 
 impl VeloCompleter {
@@ -22,10 +37,31 @@ impl VeloCompleter {
             commands: vec![
                 "ssh".to_string(),
                 "add".to_string(),
+                "edit".to_string(),
+                "rename".to_string(),
+                "clone".to_string(),
+                "show".to_string(),
                 "list".to_string(),
                 "remove".to_string(),
                 "add-key".to_string(),
+                "copy-id".to_string(),
+                "keygen".to_string(),
+                "keys".to_string(),
+                "key".to_string(),
+                "agent".to_string(),
                 "zellij".to_string(),
+                "forward".to_string(),
+                "tunnel".to_string(),
+                "config".to_string(),
+                "vault".to_string(),
+                "unlock".to_string(),
+                "lock".to_string(),
+                "status".to_string(),
+                "doctor".to_string(),
+                "audit".to_string(),
+                "known-hosts".to_string(),
+                "undo".to_string(),
+                "history".to_string(),
             ],
             zellij_subcommands: vec![
                 // Add this block
@@ -36,6 +72,18 @@ impl VeloCompleter {
                 "create-layout".to_string(),
                 "list-layouts".to_string(),
             ],
+            forward_subcommands: vec![
+                "add".to_string(),
+                "list".to_string(),
+                "remove".to_string(),
+                "validate".to_string(),
+            ],
+            tunnel_subcommands: vec![
+                "start".to_string(),
+                "stop".to_string(),
+                "list".to_string(),
+                "logs".to_string(),
+            ],
         }
     }
 
@@ -95,7 +143,30 @@ impl Completer for VeloCompleter {
                     });
                 }
             }
-        } else if words[0] == "ssh" && words.len() == 2 {
+        } else if words[0] == "forward" && words.len() == 2 {
+            // Complete forward subcommands
+            for subcommand in &self.forward_subcommands {
+                if subcommand.starts_with(word_to_complete) {
+                    completions.push(Pair {
+                        display: subcommand.clone(),
+                        replacement: subcommand.clone(),
+                    });
+                }
+            }
+        } else if words[0] == "tunnel" && words.len() == 2 {
+            // Complete tunnel subcommands
+            for subcommand in &self.tunnel_subcommands {
+                if subcommand.starts_with(word_to_complete) {
+                    completions.push(Pair {
+                        display: subcommand.clone(),
+                        replacement: subcommand.clone(),
+                    });
+                }
+            }
+        } else if (["ssh", "edit", "rename", "clone", "show"].contains(&words[0])
+            && words.len() == 2)
+            || (["forward", "tunnel"].contains(&words[0]) && words.len() == 3)
+        {
             // Complete SSH connections
             for connection in self.get_ssh_connections() {
                 if connection.starts_with(word_to_complete) {
@@ -189,10 +260,31 @@ fn handle_command(args: &[String]) -> Result<(), IoError> {
         "exit" => std::process::exit(0),
         "ssh" => handle_ssh(&args[1..]),
         "zellij" => handle_zellij(&args[1..]), // Add this line
+        "forward" => handle_forward(&args[1..]),
+        "tunnel" => handle_tunnel(&args[1..]),
+        "config" => handle_config(&args[1..]),
+        "vault" => handle_vault(&args[1..]),
+        "unlock" => handle_unlock(&args[1..]),
+        "lock" => handle_lock(&args[1..]),
+        "status" => handle_status(&args[1..]),
+        "undo" => handle_undo(&args[1..]),
+        "history" => handle_history(&args[1..]),
         "add" => handle_add_connection(&args[1..]),
+        "edit" => handle_edit_connection(&args[1..]),
+        "rename" => handle_rename_connection(&args[1..]),
+        "clone" => handle_clone_connection(&args[1..]),
+        "show" => handle_show(&args[1..]),
         "list" | "ls" => handle_list_connections(),
         "remove" | "rm" => handle_remove_connection(&args[1..]),
         "add-key" => handle_add_key(),
+        "copy-id" => handle_copy_id(&args[1..]),
+        "keygen" => handle_keygen(&args[1..]),
+        "keys" => handle_keys(&args[1..]),
+        "doctor" => handle_doctor(&args[1..]),
+        "audit" => handle_audit(&args[1..]).map(|_| ()),
+        "known-hosts" => handle_known_hosts(&args[1..]),
+        "key" => handle_key(&args[1..]),
+        "agent" => handle_agent(&args[1..]),
         "help" => {
             print_main_help();
             Ok(())
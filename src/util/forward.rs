@@ -0,0 +1,483 @@
+// Named port forwards. Each one is an ordinary LocalForward, RemoteForward
+// or DynamicForward line in the connection's Host block, labelled with a
+// `# velo:forward <name>` comment so plain ssh still picks it up.
+
+use crate::util::ssh::{create_private_dir, velo_data_dir, SSHConfig};
+use crate::util::ssh_config::{split_args, Block};
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+
+pub const FORWARD_TAG: &str = "forward";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForwardKind {
+    Local,
+    Remote,
+    Dynamic,
+}
+
+impl ForwardKind {
+    pub fn keyword(self) -> &'static str {
+        match self {
+            ForwardKind::Local => "LocalForward",
+            ForwardKind::Remote => "RemoteForward",
+            ForwardKind::Dynamic => "DynamicForward",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ForwardKind::Local => "local",
+            ForwardKind::Remote => "remote",
+            ForwardKind::Dynamic => "dynamic",
+        }
+    }
+
+    fn from_keyword(keyword: &str) -> Option<Self> {
+        [
+            ForwardKind::Local,
+            ForwardKind::Remote,
+            ForwardKind::Dynamic,
+        ]
+        .into_iter()
+        .find(|kind| kind.keyword().eq_ignore_ascii_case(keyword))
+    }
+}
+
+/// A forward directive in a Host block; `name` is `None` for forwards
+/// written by hand without a velo tag.
+#[derive(Debug, Clone)]
+pub struct Forward {
+    pub name: Option<String>,
+    pub kind: ForwardKind,
+    pub value: String,
+}
+
+impl Forward {
+    /// The port this forward listens on locally. Remote forwards listen on
+    /// the server, so they have none.
+    pub fn local_port(&self) -> Option<u16> {
+        match self.kind {
+            ForwardKind::Remote => None,
+            _ => listen_port(split_args(&self.value).first()?),
+        }
+    }
+
+    /// Checks the value the way ssh will parse it.
+    pub fn validate(&self) -> Result<(), String> {
+        let args = split_args(&self.value);
+        let expected = match self.kind {
+            ForwardKind::Dynamic => 1,
+            _ => 2,
+        };
+        if args.len() != expected {
+            return Err(format!(
+                "{} takes {} argument(s), got '{}'",
+                self.kind.keyword(),
+                expected,
+                self.value
+            ));
+        }
+        if listen_port(&args[0]).is_none() && !args[0].starts_with('/') {
+            return Err(format!("'{}' is not a valid listen port", args[0]));
+        }
+        if let Some(target) = args.get(1) {
+            if !is_target(target) {
+                return Err(format!("'{}' is not host:port or a socket path", target));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or("(unnamed)")
+    }
+
+    /// `connection (name)`, or just the connection for unnamed forwards.
+    fn label(&self, connection: &str) -> String {
+        match &self.name {
+            Some(name) => format!("{} ({})", connection, name),
+            None => connection.to_string(),
+        }
+    }
+}
+
+/// Every forward in a Host block, in file order.
+pub fn block_forwards(block: &Block) -> Vec<Forward> {
+    let tags = block.tagged(FORWARD_TAG);
+    block
+        .lines
+        .iter()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let directive = line.as_directive()?;
+            let kind = ForwardKind::from_keyword(directive.keyword())?;
+            let name = tags
+                .iter()
+                .find(|(_, index)| *index == i)
+                .map(|(name, _)| name.clone());
+            Some(Forward {
+                name,
+                kind,
+                value: directive.value().to_string(),
+            })
+        })
+        .collect()
+}
+
+/// `[bind_address:]port`, where the address may be bracketed.
+fn listen_port(spec: &str) -> Option<u16> {
+    let port = match spec.rsplit_once(':') {
+        Some((_, port)) => port,
+        None => spec,
+    };
+    port.parse().ok().filter(|&port| port != 0)
+}
+
+fn is_target(spec: &str) -> bool {
+    if spec.starts_with('/') {
+        return true;
+    }
+    match spec.rsplit_once(':') {
+        Some((host, port)) => !host.is_empty() && port.parse::<u16>().is_ok(),
+        None => false,
+    }
+}
+
+fn is_forward_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+/// Local ports claimed by forwards across all connections, with the
+/// forwards that use each.
+fn local_ports(config: &SSHConfig) -> BTreeMap<u16, Vec<String>> {
+    let mut ports: BTreeMap<u16, Vec<String>> = BTreeMap::new();
+    for connection in config.list_connections() {
+        let Some(block) = config.host_block(&connection) else {
+            continue;
+        };
+        for forward in block_forwards(block) {
+            if let Some(port) = forward.local_port() {
+                ports
+                    .entry(port)
+                    .or_default()
+                    .push(forward.label(&connection));
+            }
+        }
+    }
+    ports
+}
+
+pub fn handle_forward(args: &[String]) -> io::Result<()> {
+    if args.is_empty() {
+        println!("Usage: velo forward <subcommand> [args...]");
+        println!("Subcommands: add, list, remove, validate");
+        return Ok(());
+    }
+
+    let subcommand = &args[0];
+    let rest_args = &args[1..];
+
+    match subcommand.as_str() {
+        "add" => add_forward(rest_args),
+        "list" | "ls" => list_forwards(rest_args),
+        "remove" | "rm" => {
+            if rest_args.len() != 2 {
+                println!("Usage: velo forward remove <connection> <name>");
+                return Ok(());
+            }
+            let mut ssh_config = SSHConfig::new()?;
+            if ssh_config.remove_tagged(&rest_args[0], FORWARD_TAG, &rest_args[1])? {
+                println!(
+                    "Forward '{}' removed from '{}'.",
+                    rest_args[1], rest_args[0]
+                );
+            } else {
+                println!("Forward '{}' not found on '{}'", rest_args[1], rest_args[0]);
+            }
+            Ok(())
+        }
+        "validate" => validate_forwards(rest_args),
+        _ => {
+            println!("Unknown forward subcommand: {}", subcommand);
+            Ok(())
+        }
+    }
+}
+
+fn add_forward(args: &[String]) -> io::Result<()> {
+    let usage = "Usage: velo forward add <connection> <name> (--local <port> --remote <host:port> | --dynamic <port> | --reverse --remote <port> --local <host:port>)";
+    if args.len() < 3 {
+        println!("{}", usage);
+        return Ok(());
+    }
+
+    let (connection, name) = (&args[0], &args[1]);
+    let mut local = None;
+    let mut remote = None;
+    let mut dynamic = None;
+    let mut reverse = false;
+    let mut options = args[2..].iter();
+    while let Some(option) = options.next() {
+        let slot = match option.as_str() {
+            "--reverse" => {
+                reverse = true;
+                continue;
+            }
+            "--local" => &mut local,
+            "--remote" => &mut remote,
+            "--dynamic" => &mut dynamic,
+            _ => {
+                println!("Unknown option: {}", option);
+                return Ok(());
+            }
+        };
+        match options.next() {
+            Some(value) => *slot = Some(value.clone()),
+            None => {
+                println!("Missing value for {}", option);
+                return Ok(());
+            }
+        }
+    }
+
+    // --local and --remote name the two ends; --reverse makes the remote
+    // end the one that listens.
+    let forward = match (dynamic, local, remote, reverse) {
+        (Some(port), None, None, false) => Forward {
+            name: Some(name.clone()),
+            kind: ForwardKind::Dynamic,
+            value: port,
+        },
+        (None, Some(local), Some(remote), false) => Forward {
+            name: Some(name.clone()),
+            kind: ForwardKind::Local,
+            value: format!("{} {}", local, remote),
+        },
+        (None, Some(local), Some(remote), true) => Forward {
+            name: Some(name.clone()),
+            kind: ForwardKind::Remote,
+            value: format!("{} {}", remote, local),
+        },
+        _ => {
+            println!("{}", usage);
+            return Ok(());
+        }
+    };
+
+    if !is_forward_name(name) {
+        println!("Invalid forward name: '{}'", name);
+        return Ok(());
+    }
+    if let Err(e) = forward.validate() {
+        println!("{}", e);
+        return Ok(());
+    }
+
+    let mut ssh_config = SSHConfig::new()?;
+    let Some(block) = ssh_config.host_block(connection) else {
+        println!("Connection '{}' not found", connection);
+        return Ok(());
+    };
+    if block_forwards(block)
+        .iter()
+        .any(|f| f.name.as_deref() == Some(name))
+    {
+        println!("Forward '{}' already exists on '{}'.", name, connection);
+        return Ok(());
+    }
+    if let Some(users) = forward
+        .local_port()
+        .and_then(|port| local_ports(&ssh_config).remove(&port))
+    {
+        println!(
+            "Warning: local port {} is also used by {}",
+            forward.local_port().unwrap_or_default(),
+            users.join(", ")
+        );
+    }
+
+    ssh_config.push_tagged(
+        connection,
+        FORWARD_TAG,
+        name,
+        forward.kind.keyword(),
+        &forward.value,
+    )?;
+    println!(
+        "Forward '{}' added to '{}': {} {}",
+        name,
+        connection,
+        forward.kind.keyword(),
+        forward.value
+    );
+    Ok(())
+}
+
+fn list_forwards(args: &[String]) -> io::Result<()> {
+    let ssh_config = SSHConfig::new()?;
+    let connections = match args.first() {
+        Some(connection) => vec![connection.clone()],
+        None => ssh_config.list_connections(),
+    };
+
+    let mut found = false;
+    for connection in connections {
+        let Some(block) = ssh_config.host_block(&connection) else {
+            println!("Connection '{}' not found", connection);
+            continue;
+        };
+        let forwards = block_forwards(block);
+        if forwards.is_empty() {
+            continue;
+        }
+        found = true;
+        println!("{}", connection);
+        for forward in forwards {
+            println!(
+                "  {:<12} {:<8} {}",
+                forward.display_name(),
+                forward.kind.label(),
+                forward.value
+            );
+        }
+    }
+    if !found {
+        println!("No forwards defined.");
+    }
+    Ok(())
+}
+
+fn validate_forwards(args: &[String]) -> io::Result<()> {
+    let ssh_config = SSHConfig::new()?;
+    let connections = match args.first() {
+        Some(connection) => vec![connection.clone()],
+        None => ssh_config.list_connections(),
+    };
+    let ports = local_ports(&ssh_config);
+
+    let mut problems = 0;
+    for connection in connections {
+        let Some(block) = ssh_config.host_block(&connection) else {
+            println!("Connection '{}' not found", connection);
+            continue;
+        };
+        for forward in block_forwards(block) {
+            let label = forward.label(&connection);
+            if let Err(e) = forward.validate() {
+                println!("{}: {}", label, e);
+                problems += 1;
+                continue;
+            }
+            let Some(port) = forward.local_port() else {
+                continue;
+            };
+            let others: Vec<&String> = ports[&port].iter().filter(|u| **u != label).collect();
+            if !others.is_empty() {
+                println!(
+                    "{}: local port {} is also used by {}",
+                    label,
+                    port,
+                    others
+                        .iter()
+                        .map(|u| u.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+                problems += 1;
+            }
+            if TcpListener::bind(("127.0.0.1", port)).is_err() {
+                println!(
+                    "{}: local port {} is in use on this machine right now",
+                    label, port
+                );
+            }
+        }
+    }
+
+    if problems == 0 {
+        println!("No problems found.");
+    }
+    Ok(())
+}
+
+/// Writes a config for `ssh -F` to `file_name` in velo's data dir that
+/// keeps only the `selected` named forwards. Forwards without a velo name
+/// are kept if `keep_unnamed` is set.
+/// `name` with every byte a forward name cannot contain written as `%XX`,
+/// so it can go into a file name next to a forward name.
+pub fn file_safe_name(name: &str) -> String {
+    let mut safe = String::new();
+    for byte in name.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' => safe.push(byte as char),
+            _ => safe.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    safe
+}
+
+pub fn forward_session_config(
+    connection: &str,
+    selected: &[String],
+    keep_unnamed: bool,
+    file_name: &str,
+) -> io::Result<PathBuf> {
+    let ssh_config = SSHConfig::new()?;
+    let Some(block) = ssh_config.host_block(connection) else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Connection '{}' not found", connection),
+        ));
+    };
+    let known: Vec<String> = block
+        .tagged(FORWARD_TAG)
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    if let Some(unknown) = selected.iter().find(|name| !known.contains(name)) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "No forward '{}' on '{}' (available: {})",
+                unknown,
+                connection,
+                known.join(", ")
+            ),
+        ));
+    }
+
+    let text = ssh_config.flatten(&|block, i| {
+        let tags = block.tagged(FORWARD_TAG);
+        // The tag comment sits right above its directive.
+        if let Some((name, _)) = tags
+            .iter()
+            .find(|(_, index)| i == *index || i + 1 == *index)
+        {
+            return !selected.contains(name);
+        }
+        !keep_unnamed
+            && block.lines[i]
+                .as_directive()
+                .is_some_and(|directive| ForwardKind::from_keyword(directive.keyword()).is_some())
+    });
+
+    let dir = velo_data_dir()?.join("sessions");
+    create_private_dir(&dir)?;
+    let path = dir.join(file_name);
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(&path)?.write_all(text.as_bytes())?;
+    Ok(path)
+}
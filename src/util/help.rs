@@ -3,7 +3,28 @@ pub fn print_main_help() {
     println!("Available commands:");
     println!("  ssh      Connect via SSH");
     println!("  zellij   Manage Zellij sessions");
+    println!("  forward  Manage named port forwards");
+    println!("  tunnel   Run forwards as background tunnels");
+    println!("  config   List and restore ssh config backups");
+    println!("  vault    Manage secrets in the encrypted vault");
+    println!("  unlock   Keep the vault unlocked for a while");
+    println!("  lock     Forget the unlocked vault key");
+    println!("  status   Show whether the vault is unlocked");
+    println!("  keygen   Generate a new SSH key pair");
+    println!("  keys     List SSH keys and the connections that use them");
+    println!("  copy-id  Install a public key on one or more connections");
+    println!("  key      Manage key passphrases, revoke and rotate keys");
+    println!("  agent    Share one ssh-agent and manage the keys it holds");
+    println!("  doctor   Check ~/.ssh permissions");
+    println!("  audit    Flag risky settings in the ssh config");
+    println!("  known-hosts  List, remove, scan or verify known_hosts entries for a connection");
+    println!("  undo     Undo the last changes velo made to the ssh config");
+    println!("  history  List the changes velo made to the ssh config");
     println!("  add      Add a new SSH connection");
+    println!("  edit     Change settings of an SSH connection");
+    println!("  rename   Rename an SSH connection");
+    println!("  clone    Copy an SSH connection under a new name");
+    println!("  show     Show the effective settings of an SSH connection");
     println!("  list     List all SSH connections");
     println!("  remove   Remove an SSH connection");
     println!();
@@ -43,8 +64,10 @@ pub fn print_zellij_list_layouts_help() {
 }
 
 pub fn print_ssh_help() {
-    println!("Usage: velo ssh <connection_name>");
+    println!("Usage: velo ssh [--forward <name>[,<name>...]] <connection_name>");
     println!("Connect to a stored SSH connection.");
+    println!("  --forward N   Open only these named forwards for this session");
+    println!("A password stored with 'velo vault set <conn> password' is entered for you.");
     println!("You can manage SSH connections using 'velo add', 'velo remove', or 'velo list'.");
 }
 
@@ -59,10 +82,203 @@ pub fn print_zellij_help() {
     println!("  create-layout <layout_name>    Create a Zellij layout");
 }
 
+pub fn print_forward_help() {
+    println!("Usage: velo forward <subcommand> [args...]");
+    println!("Available subcommands:");
+    println!("  add <conn> <name> --local <port> --remote <host:port>     Forward a local port");
+    println!(
+        "  add <conn> <name> --dynamic <port>                        SOCKS proxy on a local port"
+    );
+    println!("  add <conn> <name> --reverse --remote <port> --local <host:port>");
+    println!("                                                            Forward a port on the server back here");
+    println!("  list [conn]                                               List forwards");
+    println!("  remove <conn> <name>                                      Remove a forward");
+    println!("  validate [conn]                                           Check forwards and port collisions");
+}
+
+pub fn print_tunnel_help() {
+    println!("Usage: velo tunnel <subcommand> [args...]");
+    println!("Available subcommands:");
+    println!("  start <conn> [forward]   Open the connection's forwards (or one of them) in the background");
+    println!("  stop <conn> [forward]    Stop a background tunnel");
+    println!("  list                     List running tunnels and clean up ones that have exited");
+    println!("  logs <conn> [forward]    Show the end of a tunnel's ssh log");
+}
+
+pub fn print_key_help() {
+    println!("Usage: velo key <subcommand> [args...]");
+    println!("Available subcommands:");
+    println!("  forget <key>   Delete the stored passphrase of a key (path or SHA256 fingerprint)");
+    println!("  revoke <key> [--hosts <pattern>|all]");
+    println!("                 Remove a key (path or SHA256 fingerprint) from authorized_keys on the");
+    println!("                 hosts given, or on the connections that use it");
+    println!("  rotate <connection|pattern> [--type ed25519|ecdsa|rsa] [--bits <n>]");
+    println!("                 Give connections a new key: install it, check that it logs in,");
+    println!("                 switch IdentityFile to it and remove the old key from the host");
+}
+
+pub fn print_keygen_help() {
+    println!("Usage: velo keygen [options]");
+    println!("Generate an SSH key pair, optionally protected by a passphrase.");
+    println!("Options:");
+    println!("  --type <type>      ed25519 (default), ecdsa or rsa");
+    println!("  --bits <n>         256, 384 or 521 for ecdsa; at least 2048 for rsa (default 3072)");
+    println!("  --comment <text>   Comment stored with the key (default user@host)");
+    println!("  --for <conn>       Use the key for a connection and offer to copy it there");
+    println!("  --file <path>      Where to write the private key (default ~/.ssh/id_<type>[_<conn>])");
+}
+
+pub fn print_keys_help() {
+    println!("Usage: velo keys [--json]");
+    println!("List every SSH key pair in ~/.ssh or named by an IdentityFile: type and size,");
+    println!("SHA256 fingerprint, comment, whether it is encrypted or loaded in ssh-agent,");
+    println!("and which hosts use it. Also lists keys nothing uses and IdentityFile entries");
+    println!("whose key is missing.");
+    println!("Options:");
+    println!("  --json   Print the same report as JSON");
+}
+
+pub fn print_agent_help() {
+    println!("Usage: velo agent <command>");
+    println!("Unless SSH_AUTH_SOCK names a working agent, velo starts one ssh-agent per user");
+    println!("on a fixed socket in the runtime directory and reuses it while it runs.");
+    println!("Available commands:");
+    println!("  env    Print shell commands that point a shell at velo's agent, starting it if");
+    println!("         needed, e.g. eval \"$(velo agent env)\"");
+    println!("  stop   Stop the agent velo started; other agents are left alone");
+    println!("  list   List the keys in the agent with the lifetime they have left");
+    println!("  add <key> [--lifetime <time>] [--confirm]");
+    println!("         Add a key (path or SHA256 fingerprint) for a while, e.g. --lifetime 8h,");
+    println!("         and/or have the agent ask before each use (ssh-add -t and -c)");
+    println!("  remove <key>");
+    println!("         Take a key (path or SHA256 fingerprint) out of the agent");
+    println!("  defaults <connection_name> [--lifetime <time>] [--confirm] [--clear]");
+    println!("         Show or set how `velo ssh` adds the connection's IdentityFile keys when");
+    println!("         the agent does not hold them yet");
+}
+
+pub fn print_doctor_help() {
+    println!("Usage: velo doctor <check>");
+    println!("Available checks:");
+    println!("  perms   Check the modes and owners of ~/.ssh, the ssh config files, keys,");
+    println!("          authorized_keys and known_hosts, and offer to fix the modes");
+}
+
+pub fn print_audit_help() {
+    println!("Usage: velo audit [options]");
+    println!("Check the ssh config for risky settings: StrictHostKeyChecking no, UserKnownHostsFile");
+    println!("/dev/null, ForwardAgent on wildcard hosts, PasswordAuthentication on production hosts,");
+    println!("weak Ciphers, KexAlgorithms or MACs, and DSA or short RSA keys in IdentityFile.");
+    println!("Hosts are production when their block has a `# velo:tag prod` comment.");
+    println!("Exits with status 1 if anything is found, and 2 on a bad option or an unreadable config.");
+    println!("Options:");
+    println!("  --format <text|json|sarif>     Output format (default: text)");
+    println!("  --fail-on <low|medium|high>    Lowest severity that fails (default: low)");
+    println!("  --production <patterns>        Also treat hosts matching these patterns as production");
+    println!("  --config <file>                Audit this file instead of ~/.ssh/config");
+}
+
+pub fn print_known_hosts_help() {
+    println!("Usage: velo known-hosts <command> [connection_name]");
+    println!("Work with the known_hosts entries ssh checks for a connection, found by its");
+    println!("HostKeyAlias or HostName and port, hashed entries included.");
+    println!("Available commands:");
+    println!("  list [connection_name]    List entries with their markers and fingerprints");
+    println!("  remove <connection_name>  Remove the connection's entries (a copy is kept in known_hosts.old)");
+    println!("  scan <connection_name>    Show the key the server presents and offer to trust it");
+    println!("  verify <connection_name>  Check the server's key against known_hosts and offer to");
+    println!("                            replace a changed key");
+}
+
+pub fn print_vault_help() {
+    println!("Usage: velo vault <subcommand> [args...]");
+    println!("Available subcommands:");
+    println!("  list                     List stored secrets (names only)");
+    println!("  set <conn> <kind>        Prompt for a secret and store it");
+    println!("  get <conn> <kind>        Print a stored secret");
+    println!("  remove <conn> <kind>     Delete a stored secret");
+    println!("  passwd                   Change the master password and re-encrypt every secret");
+    println!("Kinds: password, passphrase, totp");
+}
+
+pub fn print_config_help() {
+    println!("Usage: velo config <subcommand> [args...]");
+    println!("Available subcommands:");
+    println!("  backups               List the backups taken before each change to the ssh config");
+    println!(
+        "  restore <timestamp>   Show a diff against a backup and restore it after confirming"
+    );
+}
+
+pub fn print_undo_help() {
+    println!("Usage: velo undo [n]");
+    println!("Reverse the last n changes velo made to the ssh config (default 1).");
+    println!("If a file was edited outside velo since, the differences are shown and you are asked first.");
+}
+
+pub fn print_unlock_help() {
+    println!("Usage: velo unlock [--timeout <minutes>]");
+    println!("Ask for the master password once and keep the vault and file keyring unlocked");
+    println!("until nothing uses them for the timeout (default 15 minutes).");
+}
+
+pub fn print_lock_help() {
+    println!("Usage: velo lock");
+    println!("Make the unlock agent forget its keys and exit.");
+}
+
+pub fn print_status_help() {
+    println!("Usage: velo status");
+    println!("Show what the unlock agent holds and how long until it locks.");
+}
+
+pub fn print_history_help() {
+    println!("Usage: velo history [n]");
+    println!("List the changes velo made to the ssh config, newest first, with the lines each one touched.");
+}
+
 pub fn print_add_help() {
-    println!("Usage: velo add <name> <host> <user> <port>");
+    println!(
+        "Usage: velo add <name> <host> <user> <port> [--jump <conn>[,<conn>...]] [--password]"
+    );
     println!("Add a new SSH connection.");
     println!("Optionally, you can store the SSH password for automatic login.");
+    println!("  --jump J     Reach the host through these velo connections (ProxyJump)");
+    println!("  --password   Prompt for the SSH password and keep it in the encrypted vault");
+}
+
+pub fn print_edit_help() {
+    println!(
+        "Usage: velo edit <name> [--host H] [--user U] [--port P] [--jump J] [--set Key=Value] [--unset Key]"
+    );
+    println!("Change settings of an existing SSH connection in place.");
+    println!("Only the named directives are touched; everything else in the Host block is kept.");
+    println!("  --host H          Set HostName");
+    println!("  --user U          Set User");
+    println!("  --port P          Set Port");
+    println!("  --jump J          Set ProxyJump to one or more velo connections, comma-separated");
+    println!("  --set Key=Value   Set any ssh_config directive (repeatable)");
+    println!("  --unset Key       Remove a directive (repeatable)");
+}
+
+pub fn print_rename_help() {
+    println!("Usage: velo rename <old> <new>");
+    println!("Rename an SSH connection, keeping every directive in its Host block.");
+    println!(
+        "ProxyJump lines and the 'ssh-<name>' Zellij session that refer to it are updated too."
+    );
+}
+
+pub fn print_clone_help() {
+    println!("Usage: velo clone <src> <dst> [--host H]");
+    println!("Create a new connection with a copy of every directive in <src>.");
+    println!("  --host H   Use a different HostName for the copy");
+}
+
+pub fn print_show_help() {
+    println!("Usage: velo show <name>");
+    println!("Print the connection's Host block as written, then every setting ssh will use");
+    println!("for it with the block and file line it comes from, checked against 'ssh -G'.");
 }
 
 pub fn print_list_help() {
@@ -78,11 +294,14 @@ pub fn print_remove_help() {
 pub fn print_add_key_help() {
     println!("Usage: velo add-key");
     println!("Add an SSH private key to the keyring for automatic login.");
+    println!("For an encrypted key, offers to store its passphrase so ssh-add can unlock it later.");
 }
 
 pub fn print_copy_id_help() {
-    println!("Usage: velo copy-id <connection_name> <key_path>");
-    println!("Copy an SSH public key to a remote server.");
-    println!("  <connection_name>  The name of the SSH connection (as defined in your SSH config)");
-    println!("  <key_path>         The path to the public key file to copy");
+    println!("Usage: velo copy-id <connection|pattern> <key_path>");
+    println!("Copy an SSH public key to a remote server's authorized_keys, unless it is already there.");
+    println!("  <connection>  The name of the SSH connection (as defined in your SSH config)");
+    println!("  <pattern>     Comma-separated ssh patterns, e.g. 'web-*,!web-old', to copy to");
+    println!("                every matching connection in parallel");
+    println!("  <key_path>    The key to copy (its private or public file)");
 }
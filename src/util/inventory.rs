@@ -0,0 +1,616 @@
+// Key inventory: every key pair in ~/.ssh or named by an IdentityFile, with
+// what velo can tell about it without a passphrase, which ssh-agent holds
+// it and which blocks of the ssh config use it.
+
+use crate::util::agent::{agent_keys, lifetime_left, AgentKey};
+use crate::util::key::{is_encrypted, private_keys, public_key_path};
+use crate::util::resolve::{display_path, resolve};
+use crate::util::ssh::{ssh_dir, ConfigEvent, SSHConfig};
+use crate::util::ssh_config::{Block, BlockKind};
+use serde::Serialize;
+use ssh_key::public::KeyData;
+use ssh_key::{EcdsaCurve, HashAlg, PrivateKey, PublicKey};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Identities ssh tries when a host names none.
+const DEFAULT_IDENTITIES: [&str; 7] = [
+    "id_rsa",
+    "id_ecdsa",
+    "id_ecdsa_sk",
+    "id_ed25519",
+    "id_ed25519_sk",
+    "id_xmss",
+    "id_dsa",
+];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyInfo {
+    /// The private key.
+    pub path: PathBuf,
+    #[serde(rename = "type")]
+    pub key_type: String,
+    pub bits: Option<u32>,
+    pub fingerprint: String,
+    pub comment: String,
+    pub encrypted: bool,
+    pub in_agent: bool,
+    /// When the agent drops the key, as seconds since the epoch, if velo
+    /// added it with a lifetime.
+    pub agent_expires: Option<u64>,
+    /// velo added the key to the agent with `--confirm`.
+    pub agent_confirm: bool,
+    /// Blocks with an IdentityFile for this key: a Host block's patterns, a
+    /// Match line, or `(global)`.
+    pub hosts: Vec<String>,
+    /// One of the names ssh tries by default, so used even when no block
+    /// names it.
+    pub default_identity: bool,
+    /// Neither named by any block nor tried by default.
+    pub orphaned: bool,
+}
+
+impl KeyInfo {
+    pub fn type_text(&self) -> String {
+        match self.bits {
+            Some(bits) => format!("{} {}", self.key_type, bits),
+            None => self.key_type.clone(),
+        }
+    }
+
+    /// Whether the agent holds the key, with the lifetime it has left.
+    pub fn agent_text(&self) -> String {
+        if !self.in_agent {
+            return "no".to_string();
+        }
+        let mut text = "yes".to_string();
+        if let Some(left) = lifetime_left(self.agent_expires) {
+            text.push_str(&format!(", {}", left));
+        }
+        if self.agent_confirm {
+            text.push_str(", confirm");
+        }
+        text
+    }
+
+    pub fn hosts_text(&self) -> String {
+        if !self.hosts.is_empty() {
+            self.hosts.join(", ")
+        } else if self.default_identity {
+            "(default)".to_string()
+        } else {
+            "(orphaned)".to_string()
+        }
+    }
+}
+
+/// An IdentityFile pointing at a file that does not exist.
+#[derive(Debug, Clone, Serialize)]
+pub struct DanglingIdentity {
+    pub path: PathBuf,
+    pub block: String,
+    /// Where the IdentityFile line is, as `file:line`.
+    pub origin: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct KeyInventory {
+    pub keys: Vec<KeyInfo>,
+    pub dangling: Vec<DanglingIdentity>,
+    pub agent_running: bool,
+}
+
+impl KeyInventory {
+    pub fn orphaned(&self) -> impl Iterator<Item = &KeyInfo> {
+        self.keys.iter().filter(|key| key.orphaned)
+    }
+}
+
+/// One IdentityFile line.
+struct Reference {
+    path: PathBuf,
+    block: String,
+    origin: String,
+}
+
+pub fn key_inventory() -> io::Result<KeyInventory> {
+    let config = SSHConfig::new()?;
+    let references = identity_references(&config);
+    let agent = agent_keys();
+    let agent_running = agent.is_some();
+    let loaded = agent.unwrap_or_default();
+
+    let paths = match private_keys(&ssh_dir()) {
+        Ok(paths) => paths,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    let (keys, dangling) = classify_keys(paths, &references, &loaded);
+    Ok(KeyInventory {
+        keys,
+        dangling,
+        agent_running,
+    })
+}
+
+/// Reads the keys at `paths` and any others the config names, noting which
+/// blocks use each and whether `loaded` holds it. IdentityFiles naming
+/// missing files come back as dangling.
+fn classify_keys(
+    mut paths: Vec<PathBuf>,
+    references: &[Reference],
+    loaded: &[AgentKey],
+) -> (Vec<KeyInfo>, Vec<DanglingIdentity>) {
+    let mut dangling = Vec::new();
+    for reference in references {
+        if !reference.path.exists() {
+            dangling.push(DanglingIdentity {
+                path: reference.path.clone(),
+                block: reference.block.clone(),
+                origin: reference.origin.clone(),
+            });
+        } else if !paths.iter().any(|path| same_file(path, &reference.path)) {
+            paths.push(reference.path.clone());
+        }
+    }
+
+    let mut keys = Vec::new();
+    for path in paths {
+        let Some(mut key) = read_key(&path) else {
+            continue;
+        };
+        if let Some(agent_key) = loaded.iter().find(|k| k.fingerprint == key.fingerprint) {
+            key.in_agent = true;
+            key.agent_expires = agent_key.expires;
+            key.agent_confirm = agent_key.confirm;
+        }
+        for reference in references {
+            if same_file(&path, &reference.path) && !key.hosts.contains(&reference.block) {
+                key.hosts.push(reference.block.clone());
+            }
+        }
+        key.orphaned = key.hosts.is_empty() && !key.default_identity;
+        keys.push(key);
+    }
+    (keys, dangling)
+}
+
+/// What can be read of the key at `path` without its passphrase: from the
+/// `.pub` file when there is one, else from the public half stored in an
+/// OpenSSH private key, else from `ssh-keygen -l`.
+pub fn read_key(path: &Path) -> Option<KeyInfo> {
+    let private = PrivateKey::read_openssh_file(path).ok();
+    let public = PublicKey::read_openssh_file(&public_key_path(path))
+        .ok()
+        .or_else(|| private.as_ref().map(|key| key.public_key().clone()));
+    let encrypted = match &private {
+        Some(private) => private.is_encrypted(),
+        None => is_encrypted(path),
+    };
+    let default_identity = path.parent() == Some(ssh_dir().as_path())
+        && path
+            .file_name()
+            .is_some_and(|name| DEFAULT_IDENTITIES.iter().any(|default| name == *default));
+
+    let (key_type, bits, fingerprint, comment) = match public {
+        Some(public) => (
+            key_type_name(public.key_data()),
+            key_bits(public.key_data()),
+            public.fingerprint(HashAlg::Sha256).to_string(),
+            public.comment().to_string(),
+        ),
+        None => keygen_listing(path)?,
+    };
+    Some(KeyInfo {
+        path: path.to_path_buf(),
+        key_type,
+        bits,
+        fingerprint,
+        comment,
+        encrypted,
+        in_agent: false,
+        agent_expires: None,
+        agent_confirm: false,
+        hosts: Vec::new(),
+        default_identity,
+        orphaned: false,
+    })
+}
+
+/// Type, size, fingerprint and comment from `ssh-keygen -l`, for key formats
+/// velo does not parse itself, such as PEM.
+fn keygen_listing(path: &Path) -> Option<(String, Option<u32>, String, String)> {
+    let output = Command::new("ssh-keygen")
+        .arg("-lf")
+        .arg(path)
+        .output()
+        .ok()?;
+    parse_key_listing(String::from_utf8_lossy(&output.stdout).trim())
+}
+
+/// Type, size, fingerprint and comment from a line of `ssh-keygen -l` or
+/// `ssh-add -l`, e.g. "2048 SHA256:... comment words (RSA)".
+pub fn parse_key_listing(listing: &str) -> Option<(String, Option<u32>, String, String)> {
+    let (rest, key_type) = listing.rsplit_once(" (")?;
+    let mut fields = rest.splitn(3, ' ');
+    let bits = fields.next()?.parse().ok();
+    let fingerprint = fields.next()?.to_string();
+    let comment = fields.next().unwrap_or_default().to_string();
+    Some((
+        key_type.trim_end_matches(')').to_string(),
+        bits,
+        fingerprint,
+        comment,
+    ))
+}
+
+/// The key type as `ssh-keygen -l` names it.
+fn key_type_name(key: &KeyData) -> String {
+    match key {
+        KeyData::Dsa(_) => "DSA",
+        KeyData::Ecdsa(_) => "ECDSA",
+        KeyData::Ed25519(_) => "ED25519",
+        KeyData::Rsa(_) => "RSA",
+        KeyData::SkEcdsaSha2NistP256(_) => "ECDSA-SK",
+        KeyData::SkEd25519(_) => "ED25519-SK",
+        _ => "UNKNOWN",
+    }
+    .to_string()
+}
+
+fn key_bits(key: &KeyData) -> Option<u32> {
+    match key {
+        KeyData::Dsa(dsa) => mpint_bits(dsa.p.as_positive_bytes()?),
+        KeyData::Ecdsa(ecdsa) => match ecdsa.curve() {
+            EcdsaCurve::NistP256 => Some(256),
+            EcdsaCurve::NistP384 => Some(384),
+            EcdsaCurve::NistP521 => Some(521),
+        },
+        KeyData::Rsa(rsa) => mpint_bits(rsa.n.as_positive_bytes()?),
+        KeyData::Ed25519(_) | KeyData::SkEd25519(_) | KeyData::SkEcdsaSha2NistP256(_) => Some(256),
+        _ => None,
+    }
+}
+
+fn mpint_bits(bytes: &[u8]) -> Option<u32> {
+    let first = bytes.first()?;
+    Some(bytes.len() as u32 * 8 - first.leading_zeros())
+}
+
+/// The existing key files named by any IdentityFile.
+pub fn referenced_identities(config: &SSHConfig) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for reference in identity_references(config) {
+        if reference.path.exists() && !paths.contains(&reference.path) {
+            paths.push(reference.path);
+        }
+    }
+    paths
+}
+
+/// Every IdentityFile in the config, with the block it is in.
+fn identity_references(config: &SSHConfig) -> Vec<Reference> {
+    let mut references = Vec::new();
+    let mut label = "(global)".to_string();
+    let mut stack = Vec::new();
+
+    config.walk(&mut |event| match event {
+        ConfigEvent::Block { block, .. } => label = block_label(block),
+        ConfigEvent::Directive {
+            path,
+            line,
+            directive,
+        } => {
+            if !directive.is("IdentityFile") {
+                return;
+            }
+            let Some(identity) = expand_identity_path(directive.value()) else {
+                return;
+            };
+            references.push(Reference {
+                path: identity,
+                block: label.clone(),
+                origin: format!("{}:{}", display_path(path), line),
+            });
+        }
+        ConfigEvent::IncludeStart => stack.push(label.clone()),
+        ConfigEvent::IncludeEnd => {
+            if let Some(saved) = stack.pop() {
+                label = saved;
+            }
+        }
+    });
+    references
+}
+
+pub fn block_label(block: &Block) -> String {
+    match block.kind() {
+        BlockKind::Host => block.patterns().join(" "),
+        BlockKind::Match => block.header.to_string().trim().to_string(),
+    }
+}
+
+/// Expands `~`, `%d` and `%u` in an IdentityFile the way ssh does. Paths
+/// with tokens that depend on the host being connected to, and `none`, give
+/// `None`.
+pub fn expand_identity_path(value: &str) -> Option<PathBuf> {
+    let value = value.trim_matches('"');
+    if value.eq_ignore_ascii_case("none") {
+        return None;
+    }
+    let home = dirs::home_dir().unwrap_or_default();
+    let (mut expanded, rest) = match value.strip_prefix("~/") {
+        Some(relative) => (format!("{}/", home.display()), relative),
+        None => (String::new(), value),
+    };
+    // One pass, so `%%d` stays a literal `%d`.
+    let mut chars = rest.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '%' => expanded.push('%'),
+            'd' => expanded.push_str(&home.to_string_lossy()),
+            'u' => expanded.push_str(&whoami::username()),
+            _ => return None,
+        }
+    }
+    Some(PathBuf::from(expanded))
+}
+
+/// The key files `connection` is set to use, in the order ssh tries them.
+pub fn connection_identities(config: &SSHConfig, connection: &str) -> Vec<PathBuf> {
+    resolve(config, connection)
+        .get_all("IdentityFile")
+        .into_iter()
+        .filter_map(expand_identity_path)
+        .collect()
+}
+
+/// Connections with an IdentityFile for the key at `key_path`.
+pub fn connections_using(config: &SSHConfig, key_path: &Path) -> Vec<String> {
+    config
+        .list_connections()
+        .into_iter()
+        .filter(|connection| {
+            connection_identities(config, connection)
+                .iter()
+                .any(|identity| same_file(identity, key_path))
+        })
+        .collect()
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+pub fn handle_keys(args: &[String]) -> io::Result<()> {
+    let json = match args.first().map(String::as_str) {
+        None => false,
+        Some("--json") => true,
+        Some(_) => {
+            println!("Usage: velo keys [--json]");
+            return Ok(());
+        }
+    };
+
+    let inventory = key_inventory()?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&inventory)?);
+        return Ok(());
+    }
+
+    if inventory.keys.is_empty() {
+        println!("No SSH keys found.");
+    } else {
+        let rows: Vec<Vec<String>> = inventory
+            .keys
+            .iter()
+            .map(|key| {
+                vec![
+                    display_path(&key.path),
+                    key.type_text(),
+                    key.fingerprint.clone(),
+                    key.comment.clone(),
+                    if key.encrypted { "yes" } else { "no" }.to_string(),
+                    key.agent_text(),
+                    key.hosts_text(),
+                ]
+            })
+            .collect();
+        print_table(
+            &[
+                "KEY",
+                "TYPE",
+                "FINGERPRINT",
+                "COMMENT",
+                "ENCRYPTED",
+                "AGENT",
+                "HOSTS",
+            ],
+            &rows,
+        );
+    }
+
+    if !inventory.agent_running {
+        println!();
+        println!("ssh-agent is not running; no key is loaded.");
+    }
+    let orphaned: Vec<&KeyInfo> = inventory.orphaned().collect();
+    if !orphaned.is_empty() {
+        println!();
+        println!("Keys no connection uses:");
+        for key in orphaned {
+            println!("  {}", display_path(&key.path));
+        }
+    }
+    if !inventory.dangling.is_empty() {
+        println!();
+        println!("IdentityFile entries pointing at missing keys:");
+        for identity in &inventory.dangling {
+            println!(
+                "  {} in {} ({})",
+                display_path(&identity.path),
+                identity.block,
+                identity.origin
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Prints `rows` under `header` in left-aligned columns.
+pub fn print_table(header: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = header.iter().map(|title| title.len()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let header: Vec<String> = header.iter().map(|title| title.to_string()).collect();
+    for row in std::iter::once(&header).chain(rows) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::testing::temp_dir;
+    use ssh_key::private::{Ed25519Keypair, KeypairData};
+    use ssh_key::rand_core::OsRng;
+    use ssh_key::LineEnding;
+
+    fn write_key(path: &Path, comment: &str) -> String {
+        let key = PrivateKey::new(
+            KeypairData::from(Ed25519Keypair::random(&mut OsRng)),
+            comment,
+        )
+        .unwrap();
+        key.write_openssh_file(path, LineEnding::LF).unwrap();
+        key.public_key()
+            .write_openssh_file(&public_key_path(path))
+            .unwrap();
+        key.fingerprint(HashAlg::Sha256).to_string()
+    }
+
+    #[test]
+    fn key_listings() {
+        assert_eq!(
+            parse_key_listing("3072 SHA256:abc me@host with spaces (RSA)"),
+            Some((
+                "RSA".to_string(),
+                Some(3072),
+                "SHA256:abc".to_string(),
+                "me@host with spaces".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_key_listing("256 SHA256:xyz no comment (ED25519-SK)").map(|key| key.0),
+            Some("ED25519-SK".to_string())
+        );
+        let (_, bits, fingerprint, comment) =
+            parse_key_listing("unknown SHA256:def (ECDSA)").unwrap();
+        assert_eq!(
+            (bits, fingerprint.as_str(), comment.as_str()),
+            (None, "SHA256:def", "")
+        );
+        assert_eq!(parse_key_listing("The agent has no identities."), None);
+    }
+
+    #[test]
+    fn identity_paths() {
+        let home = dirs::home_dir().unwrap_or_default();
+        let user = whoami::username();
+        assert_eq!(
+            expand_identity_path("~/.ssh/id_ed25519"),
+            Some(home.join(".ssh/id_ed25519"))
+        );
+        assert_eq!(
+            expand_identity_path("\"%d/keys/%u\""),
+            Some(PathBuf::from(format!("{}/keys/{}", home.display(), user)))
+        );
+        assert_eq!(
+            expand_identity_path("/keys/100%%d"),
+            Some(PathBuf::from("/keys/100%d"))
+        );
+        assert_eq!(expand_identity_path("/keys/%h"), None);
+        assert_eq!(expand_identity_path("/keys/trailing%"), None);
+        assert_eq!(expand_identity_path("None"), None);
+    }
+
+    #[test]
+    fn mpint_sizes() {
+        assert_eq!(mpint_bits(&[]), None);
+        assert_eq!(mpint_bits(&[0x80]), Some(8));
+        assert_eq!(mpint_bits(&[0x01, 0x00]), Some(9));
+        assert_eq!(mpint_bits(&[0xff; 256]), Some(2048));
+    }
+
+    #[test]
+    fn orphaned_and_dangling_keys() {
+        let dir = temp_dir("inventory");
+        let used = dir.join("id_used");
+        let unused = dir.join("id_unused");
+        let outside = temp_dir("inventory-outside").join("id_outside");
+        let used_fingerprint = write_key(&used, "used");
+        write_key(&unused, "unused");
+        write_key(&outside, "outside");
+
+        let config_path = dir.join("config");
+        fs::write(
+            &config_path,
+            format!(
+                "Host web\n  IdentityFile {}\n\nHost db *.db\n  IdentityFile {}\n  \
+                 IdentityFile {}\n  IdentityFile {}/missing\n",
+                used.display(),
+                used.display(),
+                outside.display(),
+                dir.display()
+            ),
+        )
+        .unwrap();
+        let config = SSHConfig::load(&config_path).unwrap();
+        let loaded = vec![AgentKey {
+            key_type: "ED25519".to_string(),
+            bits: Some(256),
+            fingerprint: used_fingerprint,
+            comment: "used".to_string(),
+            expires: None,
+            confirm: true,
+        }];
+
+        let paths = private_keys(&dir).unwrap();
+        let (keys, dangling) = classify_keys(paths, &identity_references(&config), &loaded);
+        let key = |path: &Path| keys.iter().find(|key| key.path == path).unwrap();
+
+        assert_eq!(keys.len(), 3);
+        assert_eq!(key(&used).hosts, vec!["web", "db *.db"]);
+        assert!(key(&used).in_agent && key(&used).agent_confirm);
+        assert!(!key(&used).orphaned);
+        assert!(key(&unused).orphaned);
+        assert_eq!(key(&unused).hosts_text(), "(orphaned)");
+        assert_eq!(key(&outside).hosts, vec!["db *.db"]);
+        assert!(!key(&outside).in_agent);
+
+        assert_eq!(dangling.len(), 1);
+        assert_eq!(dangling[0].path, dir.join("missing"));
+        assert_eq!(dangling[0].block, "db *.db");
+        assert!(dangling[0].origin.ends_with(":7"));
+
+        fs::remove_dir_all(outside.parent().unwrap()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,377 @@
+// The undo journal: every change velo makes to the ssh config is recorded
+// with both sides of each file it wrote, so `velo undo` can reverse it
+// exactly and `velo history` can list what happened.
+
+use crate::util::backup::{atomic_write, backup_files};
+use crate::util::resolve::display_path;
+use crate::util::ssh::{config_lock, prompt_yes_no, velo_data_dir};
+use serde::{Deserialize, Serialize};
+use similar::{ChangeTag, TextDiff};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Entries older than this many are dropped.
+const MAX_ENTRIES: usize = 100;
+
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+#[derive(Serialize, Deserialize)]
+pub struct Entry {
+    /// The velo command that made the change, e.g. `add web`.
+    pub command: String,
+    pub timestamp: String,
+    pub files: Vec<FileChange>,
+}
+
+/// One file's contents before and after a change. `before` is `None` when
+/// the change created the file.
+#[derive(Serialize, Deserialize)]
+pub struct FileChange {
+    pub path: PathBuf,
+    pub before: Option<String>,
+    pub after: String,
+}
+
+impl FileChange {
+    /// Lines added and removed by the change.
+    fn line_counts(&self) -> (usize, usize) {
+        let before = self.before.as_deref().unwrap_or_default();
+        let diff = TextDiff::from_lines(before, &self.after);
+        let mut counts = (0, 0);
+        for change in diff.iter_all_changes() {
+            match change.tag() {
+                ChangeTag::Insert => counts.0 += 1,
+                ChangeTag::Delete => counts.1 += 1,
+                ChangeTag::Equal => {}
+            }
+        }
+        counts
+    }
+
+    /// Whether the file on disk is still exactly what the change wrote.
+    fn is_current(&self) -> bool {
+        fs::read_to_string(&self.path).is_ok_and(|current| current == self.after)
+    }
+}
+
+fn journal_path() -> io::Result<PathBuf> {
+    Ok(velo_data_dir()?.join("journal.json"))
+}
+
+/// Journal entries, oldest first.
+pub fn load_journal() -> io::Result<Vec<Entry>> {
+    load_journal_at(&journal_path()?)
+}
+
+fn load_journal_at(path: &Path) -> io::Result<Vec<Entry>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(path)?;
+    serde_json::from_str(&content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn save_journal(entries: &[Entry]) -> io::Result<()> {
+    save_journal_at(&journal_path()?, entries)
+}
+
+fn save_journal_at(path: &Path, entries: &[Entry]) -> io::Result<()> {
+    let content = serde_json::to_string_pretty(entries)?;
+    atomic_write(path, content.as_bytes())
+}
+
+/// Appends an entry for `command`. Files whose contents did not change are
+/// left out, and nothing is recorded if none did.
+pub fn record(command: &str, files: Vec<FileChange>) -> io::Result<()> {
+    record_at(&journal_path()?, command, files)
+}
+
+fn record_at(path: &Path, command: &str, files: Vec<FileChange>) -> io::Result<()> {
+    let files: Vec<FileChange> = files
+        .into_iter()
+        .filter(|file| file.before.as_deref() != Some(file.after.as_str()))
+        .collect();
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    let mut entries = load_journal_at(path)?;
+    entries.push(Entry {
+        command: command.to_string(),
+        timestamp: chrono::Local::now().format(TIMESTAMP_FORMAT).to_string(),
+        files,
+    });
+    let excess = entries.len().saturating_sub(MAX_ENTRIES);
+    entries.drain(..excess);
+    save_journal_at(path, &entries)
+}
+
+pub fn handle_history(args: &[String]) -> io::Result<()> {
+    let entries = load_journal()?;
+    if entries.is_empty() {
+        println!("No changes recorded yet.");
+        return Ok(());
+    }
+
+    let limit = match args.first() {
+        Some(n) => match n.parse::<usize>() {
+            Ok(n) => n,
+            Err(_) => {
+                println!("Usage: velo history [n]");
+                return Ok(());
+            }
+        },
+        None => entries.len(),
+    };
+
+    for (i, entry) in entries.iter().rev().take(limit).enumerate() {
+        println!("{:>3}  {}  {}", i + 1, entry.timestamp, entry.command);
+        for file in &entry.files {
+            let (added, removed) = file.line_counts();
+            println!(
+                "         +{} -{}  {}",
+                added,
+                removed,
+                display_path(&file.path)
+            );
+        }
+    }
+    Ok(())
+}
+
+pub fn handle_undo(args: &[String]) -> io::Result<()> {
+    let count = match args.first() {
+        Some(n) => match n.parse::<usize>() {
+            Ok(n) if n > 0 => n,
+            _ => {
+                println!("Usage: velo undo [n]");
+                return Ok(());
+            }
+        },
+        None => 1,
+    };
+
+    let mut lock = config_lock()?;
+    let _guard = lock.write()?;
+
+    let mut entries = load_journal()?;
+    if entries.is_empty() {
+        println!("Nothing to undo.");
+        return Ok(());
+    }
+
+    for _ in 0..count {
+        let Some(entry) = entries.last() else {
+            println!("No more changes to undo.");
+            break;
+        };
+        if !undo_entry(entry)? {
+            println!("Stopped; '{}' was not undone.", entry.command);
+            break;
+        }
+        println!("Undid '{}' ({}).", entry.command, entry.timestamp);
+        entries.pop();
+        save_journal(&entries)?;
+    }
+    Ok(())
+}
+
+/// Puts back the `before` side of every file in `entry`. If a file was
+/// edited outside velo since, shows how and asks first. Returns false if
+/// the user declined.
+fn undo_entry(entry: &Entry) -> io::Result<bool> {
+    let edited = edited_files(entry);
+    if !edited.is_empty() {
+        println!(
+            "The config was edited outside velo after '{}' ({}):",
+            entry.command, entry.timestamp
+        );
+        for file in &edited {
+            print_three_way(file);
+        }
+        if !prompt_yes_no("Undo anyway, discarding those edits? (y/n): ") {
+            return Ok(false);
+        }
+    }
+
+    let existing: Vec<&Path> = entry
+        .files
+        .iter()
+        .map(|file| file.path.as_path())
+        .filter(|path| path.exists())
+        .collect();
+    backup_files(&existing)?;
+    restore(entry)?;
+    Ok(true)
+}
+
+/// The files in `entry` that no longer hold what velo wrote.
+fn edited_files(entry: &Entry) -> Vec<&FileChange> {
+    entry
+        .files
+        .iter()
+        .filter(|file| !file.is_current())
+        .collect()
+}
+
+/// Writes back the `before` side of every file in `entry`, removing the
+/// ones it created.
+fn restore(entry: &Entry) -> io::Result<()> {
+    for file in &entry.files {
+        match &file.before {
+            Some(before) => atomic_write(&file.path, before.as_bytes())?,
+            None => match fs::remove_file(&file.path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Shows the file as velo left it against both what is on disk now and
+/// what undoing would write.
+fn print_three_way(file: &FileChange) {
+    let shown = display_path(&file.path);
+    let current = fs::read_to_string(&file.path).unwrap_or_default();
+    let before = file.before.as_deref().unwrap_or_default();
+    let outcome = match file.before {
+        Some(_) => "after undo",
+        None => "removed by undo",
+    };
+
+    println!("Edited outside velo:");
+    print!(
+        "{}",
+        TextDiff::from_lines(file.after.as_str(), current.as_str())
+            .unified_diff()
+            .header(
+                &format!("{} (as velo left it)", shown),
+                &format!("{} (on disk now)", shown)
+            )
+    );
+    println!("What undo would write:");
+    print!(
+        "{}",
+        TextDiff::from_lines(current.as_str(), before)
+            .unified_diff()
+            .header(
+                &format!("{} (on disk now)", shown),
+                &format!("{} ({})", shown, outcome)
+            )
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::testing::{temp_dir, temp_path};
+
+    fn change(path: &Path, before: Option<&str>, after: &str) -> FileChange {
+        FileChange {
+            path: path.to_path_buf(),
+            before: before.map(str::to_string),
+            after: after.to_string(),
+        }
+    }
+
+    #[test]
+    fn unchanged_files_are_not_recorded() {
+        let path = temp_path("journal-unchanged");
+        let config = Path::new("/nonexistent/config");
+        record_at(&path, "edit web", vec![change(config, Some("a\n"), "a\n")]).unwrap();
+        assert!(!path.exists());
+
+        let files = vec![
+            change(config, Some("a\n"), "a\n"),
+            change(config, Some("a\n"), "b\n"),
+        ];
+        record_at(&path, "edit web", files).unwrap();
+        let entries = load_journal_at(&path).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].command, "edit web");
+        assert_eq!(entries[0].files.len(), 1);
+        assert_eq!(entries[0].files[0].line_counts(), (1, 1));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn history_keeps_the_newest_entries() {
+        let path = temp_path("journal-trim");
+        let config = Path::new("/nonexistent/config");
+        for n in 0..MAX_ENTRIES + 5 {
+            let after = format!("{}\n", n);
+            record_at(
+                &path,
+                &format!("add {}", n),
+                vec![change(config, None, &after)],
+            )
+            .unwrap();
+        }
+        let entries = load_journal_at(&path).unwrap();
+        assert_eq!(entries.len(), MAX_ENTRIES);
+        assert_eq!(entries[0].command, "add 5");
+        assert_eq!(
+            entries[MAX_ENTRIES - 1].command,
+            format!("add {}", MAX_ENTRIES + 4)
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn undo_restores_and_removes_files() {
+        let dir = temp_dir("journal-undo");
+        let config = dir.join("config");
+        let created = dir.join("config.d-web");
+        fs::write(&config, "Host web\n  User new\n").unwrap();
+        fs::write(&created, "Host db\n").unwrap();
+        let entry = Entry {
+            command: "edit web".to_string(),
+            timestamp: String::new(),
+            files: vec![
+                change(
+                    &config,
+                    Some("Host web\n  User old\n"),
+                    "Host web\n  User new\n",
+                ),
+                change(&created, None, "Host db\n"),
+            ],
+        };
+
+        assert!(edited_files(&entry).is_empty());
+        restore(&entry).unwrap();
+        assert_eq!(
+            fs::read_to_string(&config).unwrap(),
+            "Host web\n  User old\n"
+        );
+        assert!(!created.exists());
+        // Undoing again finds the files changed since and leaves a
+        // missing file missing.
+        assert_eq!(edited_files(&entry).len(), 2);
+        restore(&entry).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn edits_outside_velo_are_detected() {
+        let dir = temp_dir("journal-edited");
+        let config = dir.join("config");
+        let other = dir.join("other");
+        fs::write(&config, "Host web\n  User new\n  Port 2222\n").unwrap();
+        fs::write(&other, "Host db\n").unwrap();
+        let entry = Entry {
+            command: "edit web".to_string(),
+            timestamp: String::new(),
+            files: vec![
+                change(&config, Some("Host web\n"), "Host web\n  User new\n"),
+                change(&other, Some(""), "Host db\n"),
+            ],
+        };
+
+        let edited = edited_files(&entry);
+        assert_eq!(edited.len(), 1);
+        assert_eq!(edited[0].path, config);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
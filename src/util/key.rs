@@ -0,0 +1,784 @@
+// SSH keys. velo generates key pairs itself, installs, revokes and rotates
+// them on remote hosts, and a key's passphrase can be kept in the keyring,
+// filed under the key's fingerprint, so ssh-add unlocks it through velo's
+// askpass helper without asking.
+
+use crate::util::agent::connect_agent;
+use crate::util::askpass::{askpass_env, askpass_supported, ASKPASS_KEY_ENV, KEYRING_SERVICE};
+use crate::util::doctor::{fix_permissions, PathKind};
+use crate::util::inventory::{connection_identities, connections_using, key_inventory};
+use crate::util::keyring::{delete_passphrase, has_secret, lookup_secret, store_passphrase};
+use crate::util::resolve::display_path;
+use crate::util::ssh::{
+    authorized_key_line, create_private_dir, for_each_connection, install_public_key,
+    prompt_yes_no, remove_public_key, select_connections, ssh_dir, SSHConfig,
+};
+use ssh_key::private::{EcdsaKeypair, Ed25519Keypair, KeypairData, RsaKeypair};
+use ssh_key::rand_core::OsRng;
+use ssh_key::{EcdsaCurve, HashAlg, LineEnding, PrivateKey};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use zeroize::Zeroizing;
+
+/// The SHA256 fingerprint of a key, e.g. `SHA256:Xy1...`, from its public
+/// half when there is one.
+pub fn key_fingerprint(key_path: &Path) -> io::Result<String> {
+    let public = public_key_path(key_path);
+    let path = if public.exists() { &public } else { key_path };
+    let output = Command::new("ssh-keygen").arg("-lf").arg(path).output()?;
+    if !output.status.success() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Cannot read the fingerprint of {}: {}",
+                path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ));
+    }
+    // "256 SHA256:... comment (ED25519)"
+    String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .nth(1)
+        .map(str::to_string)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Unexpected ssh-keygen output"))
+}
+
+pub fn public_key_path(key_path: &Path) -> PathBuf {
+    if key_path.extension().is_some_and(|ext| ext == "pub") {
+        key_path.to_path_buf()
+    } else {
+        let mut path = key_path.as_os_str().to_owned();
+        path.push(".pub");
+        PathBuf::from(path)
+    }
+}
+
+/// Whether the private key at `key_path` needs a passphrase to load.
+pub fn is_encrypted(key_path: &Path) -> bool {
+    // Reading the public half with an empty passphrase only fails for
+    // encrypted keys.
+    Command::new("ssh-keygen")
+        .args(["-y", "-P", "", "-f"])
+        .arg(key_path)
+        .stdin(Stdio::null())
+        .output()
+        .map(|output| !output.status.success())
+        .unwrap_or(false)
+}
+
+fn keyring_user(fingerprint: &str) -> String {
+    format!("passphrase:{}", fingerprint)
+}
+
+pub fn store_key_passphrase(fingerprint: &str, passphrase: &str) -> io::Result<()> {
+    store_passphrase(KEYRING_SERVICE, &keyring_user(fingerprint), passphrase)
+}
+
+/// The stored passphrase for a key, if any. The file keyring may ask for
+/// its master password on the terminal.
+pub fn stored_key_passphrase(fingerprint: &str) -> io::Result<Option<Zeroizing<String>>> {
+    Ok(lookup_secret(KEYRING_SERVICE, &keyring_user(fingerprint)))
+}
+
+pub fn has_key_passphrase(fingerprint: &str) -> bool {
+    has_secret(KEYRING_SERVICE, &keyring_user(fingerprint))
+}
+
+/// For an encrypted key without a stored passphrase, offers to store one.
+pub fn offer_to_store_passphrase(key_path: &Path) -> io::Result<()> {
+    if !is_encrypted(key_path) {
+        return Ok(());
+    }
+    let fingerprint = key_fingerprint(key_path)?;
+    if has_key_passphrase(&fingerprint) {
+        return Ok(());
+    }
+    if !prompt_yes_no(
+        "Store this key's passphrase in the keyring so velo can unlock it for you? (y/n): ",
+    ) {
+        return Ok(());
+    }
+    let passphrase = Zeroizing::new(rpassword::prompt_password(format!(
+        "Passphrase for {}: ",
+        key_path.display()
+    ))?);
+    store_key_passphrase(&fingerprint, &passphrase)
+}
+
+/// Runs `ssh-add` for `key_path`, answering the passphrase prompt with the
+/// stored passphrase through velo's askpass helper.
+pub fn ssh_add(key_path: &Path, extra_args: &[&str]) -> io::Result<Output> {
+    connect_agent();
+    let fingerprint = key_fingerprint(key_path)?;
+    let mut command = Command::new("ssh-add");
+    if cfg!(target_os = "macos") {
+        command.arg("--apple-use-keychain");
+    }
+    command.args(extra_args).arg(key_path);
+    for (key, value) in askpass_env(ASKPASS_KEY_ENV, &fingerprint)? {
+        command.env(key, value);
+    }
+    // Before SSH_ASKPASS_REQUIRE, ssh-add only used askpass when it had no
+    // terminal and DISPLAY was set.
+    if !askpass_supported() {
+        command.stdin(Stdio::null());
+        if env::var_os("DISPLAY").is_none() {
+            command.env("DISPLAY", ":0");
+        }
+    }
+    command.output()
+}
+
+/// Adds every key in `~/.ssh` that has a stored passphrase and is not in
+/// the agent yet.
+pub fn load_stored_keys() -> io::Result<()> {
+    let loaded = Command::new("ssh-add").arg("-l").output()?;
+    let loaded = String::from_utf8_lossy(&loaded.stdout).into_owned();
+
+    for key_path in private_keys(&ssh_dir())? {
+        let Ok(fingerprint) = key_fingerprint(&key_path) else {
+            continue;
+        };
+        if loaded.contains(&fingerprint) || !has_key_passphrase(&fingerprint) {
+            continue;
+        }
+        let output = ssh_add(&key_path, &[])?;
+        if output.status.success() {
+            println!("Added {} to ssh-agent.", key_path.display());
+        } else {
+            eprintln!(
+                "Could not add {}: {}",
+                key_path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Private keys in `dir`: files with a matching `.pub` next to them.
+pub fn private_keys(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut keys: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.is_file()
+                && path.extension().is_none_or(|ext| ext != "pub")
+                && public_key_path(path).exists()
+        })
+        .collect();
+    keys.sort();
+    Ok(keys)
+}
+
+/// Removes the stored passphrase for a key given by path or fingerprint.
+/// Returns false if none was stored.
+pub fn forget_key_passphrase(key: &str) -> io::Result<bool> {
+    let fingerprint = if key.starts_with("SHA256:") {
+        key.to_string()
+    } else {
+        key_fingerprint(Path::new(key))?
+    };
+
+    let forgotten = delete_passphrase(KEYRING_SERVICE, &keyring_user(&fingerprint))?;
+    if forgotten {
+        println!("Passphrase for {} removed.", fingerprint);
+    }
+    Ok(forgotten)
+}
+
+pub fn handle_key(args: &[String]) -> io::Result<()> {
+    if args.is_empty() {
+        println!("Usage: velo key <subcommand> [args...]");
+        println!("Subcommands: forget, revoke, rotate");
+        return Ok(());
+    }
+
+    match args[0].as_str() {
+        "forget" => {
+            let Some(key) = args.get(1) else {
+                println!("Usage: velo key forget <key_path|fingerprint>");
+                return Ok(());
+            };
+            if !forget_key_passphrase(key)? {
+                println!("No passphrase stored for {}", key);
+            }
+        }
+        "revoke" => revoke_key(&args[1..])?,
+        "rotate" => rotate_keys(&args[1..])?,
+        _ => println!("Unknown key subcommand: {}", args[0]),
+    }
+    Ok(())
+}
+
+/// The key file given by path, or by the fingerprint of a key velo can see.
+pub fn find_key(key: &str) -> io::Result<Option<PathBuf>> {
+    if !key.starts_with("SHA256:") {
+        return Ok(Some(PathBuf::from(key)));
+    }
+    Ok(key_inventory()?
+        .keys
+        .into_iter()
+        .find(|info| info.fingerprint == key)
+        .map(|info| info.path))
+}
+
+/// Removes a public key from authorized_keys on the hosts picked by
+/// `--hosts`, or else on the connections whose IdentityFile is that key.
+fn revoke_key(args: &[String]) -> io::Result<()> {
+    let (key, hosts) = match args {
+        [key] => (key, None),
+        [key, flag, hosts] if flag == "--hosts" => (key, Some(hosts.as_str())),
+        _ => {
+            println!("Usage: velo key revoke <key_path|fingerprint> [--hosts <pattern>|all]");
+            return Ok(());
+        }
+    };
+    let Some(key_path) = find_key(key)? else {
+        println!("No key with fingerprint {} found", key);
+        return Ok(());
+    };
+    let line = authorized_key_line(&key_path)?;
+
+    let config = SSHConfig::new()?;
+    let connections = match hosts {
+        Some("all") => config.list_connections(),
+        Some(pattern) => select_connections(&config, pattern),
+        None => connections_using(&config, &key_path),
+    };
+    if connections.is_empty() {
+        match hosts {
+            Some(pattern) => println!("No connection matches '{}'", pattern),
+            None => println!(
+                "No connection uses {}; pick hosts with --hosts <pattern> or --hosts all.",
+                display_path(&key_path)
+            ),
+        }
+        return Ok(());
+    }
+
+    println!(
+        "Revoking {} on {} connection(s)...",
+        display_path(&key_path),
+        connections.len()
+    );
+    let batch = connections.len() > 1;
+    let results = for_each_connection(&connections, |connection| {
+        remove_public_key(connection, &line, batch)
+    });
+    let mut removed = 0;
+    for (connection, result) in connections.iter().zip(results) {
+        match result {
+            Ok(true) => {
+                removed += 1;
+                println!("  {}: removed", connection);
+            }
+            Ok(false) => println!("  {}: not installed", connection),
+            Err(e) => println!("  {}: failed: {}", connection, e),
+        }
+    }
+    println!(
+        "Removed from {} of {} connection(s).",
+        removed,
+        connections.len()
+    );
+    Ok(())
+}
+
+/// Gives each connection picked by the first argument a new key, one host
+/// at a time, and reports how each went.
+fn rotate_keys(args: &[String]) -> io::Result<()> {
+    let usage =
+        "Usage: velo key rotate <connection|pattern> [--type ed25519|ecdsa|rsa] [--bits <n>]";
+    let Some(target) = args.first() else {
+        println!("{}", usage);
+        return Ok(());
+    };
+    let mut key_type = "ed25519".to_string();
+    let mut bits = None;
+    let mut flags = args[1..].iter();
+    while let Some(flag) = flags.next() {
+        match (flag.as_str(), flags.next()) {
+            ("--type", Some(value)) => key_type = value.to_lowercase(),
+            ("--bits", Some(value)) => match value.parse::<usize>() {
+                Ok(n) => bits = Some(n),
+                Err(_) => {
+                    println!("--bits takes a number, not '{}'", value);
+                    return Ok(());
+                }
+            },
+            _ => {
+                println!("{}", usage);
+                return Ok(());
+            }
+        }
+    }
+    let spec = match KeySpec::parse(&key_type, bits) {
+        Ok(spec) => spec,
+        Err(e) => {
+            println!("{}", e);
+            return Ok(());
+        }
+    };
+
+    let connections = select_connections(&SSHConfig::new()?, target);
+    if connections.is_empty() {
+        println!("No connection matches '{}'", target);
+        return Ok(());
+    }
+
+    println!("Choose a passphrase for the new key(s).");
+    let passphrase = prompt_new_passphrase()?;
+    let store = !passphrase.is_empty()
+        && prompt_yes_no(
+            "Store the passphrase in the keyring so velo can unlock the new keys for you? (y/n): ",
+        );
+
+    let mut report = Vec::new();
+    for connection in &connections {
+        println!("Rotating the key for '{}'...", connection);
+        let result = rotate_connection(connection, &spec, &key_type, &passphrase, store);
+        report.push((connection, result));
+    }
+
+    println!();
+    println!("Rotation report:");
+    for (connection, result) in report {
+        match result {
+            Ok(summary) => println!("  {}: {}", connection, summary),
+            Err(e) => println!("  {}: failed: {}", connection, e),
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the key `connection` logs in with. The new key is installed
+/// and a login with it alone is tried before the config is switched over
+/// and the old key is removed from the host, so a failure at any step
+/// leaves the old key working. Returns a summary of what changed.
+fn rotate_connection(
+    connection: &str,
+    spec: &KeySpec,
+    key_type: &str,
+    passphrase: &str,
+    store: bool,
+) -> io::Result<String> {
+    let mut config = SSHConfig::new()?;
+    let Some(old_path) = connection_identities(&config, connection)
+        .into_iter()
+        .find(|path| path.exists())
+    else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no IdentityFile to rotate; set one with 'velo keygen --for' first",
+        ));
+    };
+    let old_line = authorized_key_line(&old_path)?;
+
+    let new_path = unused_key_path(&ssh_dir().join(format!("id_{}_{}", key_type, connection)));
+    let fingerprint = write_key_pair(spec, default_comment(), &new_path, passphrase)?;
+    println!("New key saved to {}", new_path.display());
+    if store {
+        store_key_passphrase(&fingerprint, passphrase)?;
+    }
+
+    if let Err(e) = try_new_key(connection, &new_path, &fingerprint, !passphrase.is_empty()) {
+        // Nothing uses the new key; leave no trace of it.
+        let _ = fs::remove_file(&new_path);
+        let _ = fs::remove_file(public_key_path(&new_path));
+        if store {
+            let _ = delete_passphrase(KEYRING_SERVICE, &keyring_user(&fingerprint));
+        }
+        return Err(io::Error::new(
+            e.kind(),
+            format!("{}; still using {}", e, display_path(&old_path)),
+        ));
+    }
+
+    config.update_config_with_key(connection, &new_path)?;
+    let removed = remove_public_key(connection, &old_line, false)?;
+    Ok(format!(
+        "now uses {} ({}); old key {}",
+        display_path(&new_path),
+        fingerprint,
+        if removed {
+            "removed from the host"
+        } else {
+            "was not in authorized_keys"
+        }
+    ))
+}
+
+/// Installs the new key on `connection` and checks that it logs in,
+/// taking it off the host again if it does not.
+fn try_new_key(
+    connection: &str,
+    key_path: &Path,
+    fingerprint: &str,
+    encrypted: bool,
+) -> io::Result<()> {
+    let line = authorized_key_line(key_path)?;
+    install_public_key(connection, &line, false)?;
+    if encrypted {
+        // The test login cannot ask for the passphrase.
+        let output = ssh_add(key_path, &[])?;
+        if !output.status.success() {
+            let _ = remove_public_key(connection, &line, false);
+            return Err(io::Error::other(
+                "could not load the new key into ssh-agent to test it",
+            ));
+        }
+    }
+    if !logs_in_with(connection, key_path, fingerprint)? {
+        let _ = remove_public_key(connection, &line, false);
+        return Err(io::Error::other("logging in with the new key did not work"));
+    }
+    Ok(())
+}
+
+/// Whether `connection` lets `key_path` log in, offered first and with
+/// every other way to authenticate turned off. `ssh -v` names the key the
+/// server accepted.
+fn logs_in_with(connection: &str, key_path: &Path, fingerprint: &str) -> io::Result<bool> {
+    let output = Command::new("ssh")
+        .arg("-v")
+        .args(["-o", "BatchMode=yes", "-o", "IdentitiesOnly=yes"])
+        .args(["-o", "PreferredAuthentications=publickey", "-i"])
+        .arg(key_path)
+        .args([connection, "true"])
+        .stdin(Stdio::null())
+        .output()?;
+    let log = String::from_utf8_lossy(&output.stderr);
+    Ok(output.status.success()
+        && log
+            .lines()
+            .any(|line| line.contains("Server accepts key:") && line.contains(fingerprint)))
+}
+
+/// `path`, or if a key is there already, `path` with today's date and, if
+/// need be, a counter appended.
+fn unused_key_path(path: &Path) -> PathBuf {
+    let taken = |path: &Path| path.exists() || public_key_path(path).exists();
+    if !taken(path) {
+        return path.to_path_buf();
+    }
+    let dated = format!(
+        "{}-{}",
+        path.display(),
+        chrono::Local::now().format("%Y%m%d")
+    );
+    let mut candidate = PathBuf::from(&dated);
+    let mut counter = 2;
+    while taken(&candidate) {
+        candidate = PathBuf::from(format!("{}-{}", dated, counter));
+        counter += 1;
+    }
+    candidate
+}
+
+const DEFAULT_RSA_BITS: usize = 3072;
+const MIN_RSA_BITS: usize = 2048;
+
+/// The kind of key pair to generate.
+enum KeySpec {
+    Ed25519,
+    Ecdsa(EcdsaCurve),
+    Rsa(usize),
+}
+
+impl KeySpec {
+    /// Checks `key_type` ("ed25519", "ecdsa" or "rsa") against `bits`, which
+    /// picks the curve for ECDSA and the modulus size for RSA.
+    fn parse(key_type: &str, bits: Option<usize>) -> Result<Self, String> {
+        match (key_type, bits) {
+            ("ed25519", None) => Ok(KeySpec::Ed25519),
+            ("ed25519", Some(_)) => Err("ed25519 keys have a fixed size; drop --bits".into()),
+            ("ecdsa", bits) => match bits.unwrap_or(256) {
+                256 => Ok(KeySpec::Ecdsa(EcdsaCurve::NistP256)),
+                384 => Ok(KeySpec::Ecdsa(EcdsaCurve::NistP384)),
+                521 => Ok(KeySpec::Ecdsa(EcdsaCurve::NistP521)),
+                other => Err(format!(
+                    "ECDSA keys are 256, 384 or 521 bits, not {}",
+                    other
+                )),
+            },
+            ("rsa", bits) => {
+                let bits = bits.unwrap_or(DEFAULT_RSA_BITS);
+                if bits < MIN_RSA_BITS {
+                    return Err(format!("RSA keys must be at least {} bits", MIN_RSA_BITS));
+                }
+                Ok(KeySpec::Rsa(bits))
+            }
+            (other, _) => Err(format!(
+                "Unknown key type '{}'; use ed25519, ecdsa or rsa",
+                other
+            )),
+        }
+    }
+
+    fn generate(&self) -> io::Result<KeypairData> {
+        let keypair = match self {
+            KeySpec::Ed25519 => KeypairData::from(Ed25519Keypair::random(&mut OsRng)),
+            KeySpec::Ecdsa(curve) => KeypairData::from(
+                EcdsaKeypair::random(&mut OsRng, *curve).map_err(io::Error::other)?,
+            ),
+            KeySpec::Rsa(bits) => {
+                KeypairData::from(RsaKeypair::random(&mut OsRng, *bits).map_err(io::Error::other)?)
+            }
+        };
+        Ok(keypair)
+    }
+}
+
+/// `user@host`, as ssh-keygen comments its keys.
+fn default_comment() -> String {
+    match whoami::fallible::hostname() {
+        Ok(host) => format!("{}@{}", whoami::username(), host),
+        Err(_) => whoami::username(),
+    }
+}
+
+/// Asks for a new passphrase twice; empty means none.
+fn prompt_new_passphrase() -> io::Result<Zeroizing<String>> {
+    loop {
+        let passphrase = Zeroizing::new(rpassword::prompt_password(
+            "Passphrase (empty for no passphrase): ",
+        )?);
+        let confirm = Zeroizing::new(rpassword::prompt_password("Repeat the passphrase: ")?);
+        if *passphrase == *confirm {
+            return Ok(passphrase);
+        }
+        println!("The passphrases do not match.");
+    }
+}
+
+/// Generates a key pair and writes the private key to `private_path` with
+/// mode 0600, encrypted unless `passphrase` is empty, and the public key
+/// next to it with 0644. Returns the SHA256 fingerprint.
+fn write_key_pair(
+    spec: &KeySpec,
+    comment: String,
+    private_path: &Path,
+    passphrase: &str,
+) -> io::Result<String> {
+    let key = PrivateKey::new(spec.generate()?, comment).map_err(io::Error::other)?;
+    let encrypted = if passphrase.is_empty() {
+        key.clone()
+    } else {
+        key.encrypt(&mut OsRng, passphrase.as_bytes())
+            .map_err(io::Error::other)?
+    };
+
+    if let Some(dir) = private_path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+    {
+        create_private_dir(dir)?;
+    }
+    encrypted
+        .write_openssh_file(private_path, LineEnding::LF)
+        .map_err(io::Error::other)?;
+    fix_permissions(private_path, PathKind::PrivateKey)?;
+    let public_path = public_key_path(private_path);
+    key.public_key()
+        .write_openssh_file(&public_path)
+        .map_err(io::Error::other)?;
+    fix_permissions(&public_path, PathKind::PublicKey)?;
+
+    Ok(key.fingerprint(HashAlg::Sha256).to_string())
+}
+
+pub fn handle_keygen(args: &[String]) -> io::Result<()> {
+    let usage = "Usage: velo keygen [--type ed25519|ecdsa|rsa] [--bits <n>] [--comment <text>] [--for <conn>] [--file <path>]";
+    let mut key_type = "ed25519".to_string();
+    let mut bits = None;
+    let mut comment = None;
+    let mut connection: Option<String> = None;
+    let mut file = None;
+
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let Some(value) = args.next() else {
+            println!("{}", usage);
+            return Ok(());
+        };
+        match flag.as_str() {
+            "--type" => key_type = value.to_lowercase(),
+            "--bits" => match value.parse::<usize>() {
+                Ok(n) => bits = Some(n),
+                Err(_) => {
+                    println!("--bits takes a number, not '{}'", value);
+                    return Ok(());
+                }
+            },
+            "--comment" => comment = Some(value.clone()),
+            "--for" => connection = Some(value.clone()),
+            "--file" => file = Some(PathBuf::from(value)),
+            _ => {
+                println!("{}", usage);
+                return Ok(());
+            }
+        }
+    }
+
+    let spec = match KeySpec::parse(&key_type, bits) {
+        Ok(spec) => spec,
+        Err(e) => {
+            println!("{}", e);
+            return Ok(());
+        }
+    };
+
+    let private_path = file.unwrap_or_else(|| {
+        ssh_dir().join(match &connection {
+            Some(connection) => format!("id_{}_{}", key_type, connection),
+            None => format!("id_{}", key_type),
+        })
+    });
+    let public_path = public_key_path(&private_path);
+    if private_path.exists() || public_path.exists() {
+        let prompt = format!(
+            "{} already exists. Overwrite it? (y/n): ",
+            private_path.display()
+        );
+        if !prompt_yes_no(&prompt) {
+            return Ok(());
+        }
+    }
+
+    let passphrase = prompt_new_passphrase()?;
+    println!("Generating a new {} key...", key_type);
+    let fingerprint = write_key_pair(
+        &spec,
+        comment.unwrap_or_else(default_comment),
+        &private_path,
+        &passphrase,
+    )?;
+
+    println!("Private key saved to {}", private_path.display());
+    println!("Public key saved to {}", public_path.display());
+    println!("Fingerprint: {}", fingerprint);
+
+    if !passphrase.is_empty()
+        && prompt_yes_no(
+            "Store the passphrase in the keyring so velo can unlock the key for you? (y/n): ",
+        )
+    {
+        store_key_passphrase(&fingerprint, &passphrase)?;
+    }
+
+    let Some(connection) = connection else {
+        return Ok(());
+    };
+    let mut ssh_config = SSHConfig::new()?;
+    ssh_config.update_config_with_key(&connection, &private_path)?;
+    if prompt_yes_no(&format!(
+        "Copy the public key to '{}' now? (y/n): ",
+        connection
+    )) {
+        ssh_config.copy_id(&connection, &public_path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::testing::temp_dir;
+
+    #[test]
+    fn public_key_path_adds_pub_once() {
+        assert_eq!(
+            public_key_path(Path::new("/home/a/.ssh/id_ed25519")),
+            PathBuf::from("/home/a/.ssh/id_ed25519.pub")
+        );
+        assert_eq!(
+            public_key_path(Path::new("/home/a/.ssh/id_ed25519.pub")),
+            PathBuf::from("/home/a/.ssh/id_ed25519.pub")
+        );
+        assert_eq!(
+            public_key_path(Path::new("work.key")),
+            PathBuf::from("work.key.pub")
+        );
+    }
+
+    #[test]
+    fn key_specs_check_type_and_bits() {
+        assert!(matches!(
+            KeySpec::parse("ed25519", None),
+            Ok(KeySpec::Ed25519)
+        ));
+        assert!(KeySpec::parse("ed25519", Some(256)).is_err());
+        assert!(matches!(
+            KeySpec::parse("ecdsa", None),
+            Ok(KeySpec::Ecdsa(EcdsaCurve::NistP256))
+        ));
+        assert!(matches!(
+            KeySpec::parse("ecdsa", Some(521)),
+            Ok(KeySpec::Ecdsa(EcdsaCurve::NistP521))
+        ));
+        assert!(KeySpec::parse("ecdsa", Some(512)).is_err());
+        assert!(matches!(
+            KeySpec::parse("rsa", None),
+            Ok(KeySpec::Rsa(DEFAULT_RSA_BITS))
+        ));
+        assert!(matches!(
+            KeySpec::parse("rsa", Some(4096)),
+            Ok(KeySpec::Rsa(4096))
+        ));
+        assert!(KeySpec::parse("rsa", Some(1024)).is_err());
+        assert!(KeySpec::parse("dsa", None).is_err());
+    }
+
+    #[test]
+    fn finds_key_pairs_and_unused_paths() {
+        let dir = temp_dir("keys");
+        let key = dir.join("id_ed25519");
+        let fingerprint = write_key_pair(&KeySpec::Ed25519, "test".into(), &key, "").unwrap();
+        assert!(fingerprint.starts_with("SHA256:"));
+        // Neither half on its own counts as a key pair.
+        fs::write(dir.join("orphan"), "").unwrap();
+        fs::write(dir.join("lonely.pub"), "").unwrap();
+        assert_eq!(private_keys(&dir).unwrap(), vec![key.clone()]);
+
+        assert_eq!(unused_key_path(&dir.join("id_rsa")), dir.join("id_rsa"));
+        let dated = unused_key_path(&key);
+        assert_ne!(dated, key);
+        assert!(dated.to_string_lossy().starts_with(&*key.to_string_lossy()));
+        fs::write(&dated, "").unwrap();
+        assert_eq!(
+            unused_key_path(&key),
+            PathBuf::from(format!("{}-2", dated.display()))
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn written_key_pairs_are_encrypted_and_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = temp_dir("keygen");
+        let key = dir.join("id_ecdsa");
+        let spec = KeySpec::Ecdsa(EcdsaCurve::NistP256);
+        let fingerprint = write_key_pair(&spec, "a@b".into(), &key, "secret").unwrap();
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&key), 0o600);
+        assert_eq!(mode(&public_key_path(&key)), 0o644);
+
+        let private = PrivateKey::read_openssh_file(&key).unwrap();
+        assert!(private.is_encrypted());
+        assert!(private.decrypt(b"wrong").is_err());
+        let private = private.decrypt(b"secret").unwrap();
+        assert_eq!(private.comment(), "a@b");
+        assert_eq!(
+            private.fingerprint(HashAlg::Sha256).to_string(),
+            fingerprint
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,37 +1,483 @@
-use keyring::{Entry, Result};
+// Credential storage. Secrets are kept by one of several backends: the OS
+// keyring (Keychain, Credential Manager or the Secret Service), an encrypted
+// file in velo's data dir for machines without one, such as CI runners and
+// containers, or memory. The OS keyring is used when it answers and the file
+// otherwise; VELO_KEYRING picks one explicitly.
+
+use crate::util::backup::atomic_write;
+use crate::util::ssh::velo_data_dir;
+use crate::util::vault::{
+    invalid, prompt_new_password, prompt_password, Header, Sealed, SecretKey,
+};
+use keyring::Entry;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, OnceLock, PoisonError};
+use zeroize::Zeroizing;
+
+/// Forces a backend: `os`, `file` or `memory`.
+pub const BACKEND_ENV: &str = "VELO_KEYRING";
+
+/// The file keyring's master password, for when nobody is there to type it.
+pub const KEY_ENV: &str = "VELO_KEYRING_KEY";
+
+const FILE_FORMAT: &str = "velo-keyring";
+
+/// Somewhere secrets can be kept, addressed by service and user name.
+pub trait KeyringBackend {
+    /// What the backend is called in messages, e.g. "OS keyring".
+    fn name(&self) -> &'static str;
+
+    /// Whether a secret is stored, without unlocking anything to find out.
+    fn contains(&self, service: &str, username: &str) -> io::Result<bool>;
+
+    fn get(&self, service: &str, username: &str) -> io::Result<Option<Zeroizing<String>>>;
+
+    /// Stores `secret`, replacing any existing one.
+    fn set(&self, service: &str, username: &str, secret: &str) -> io::Result<()>;
+
+    /// Returns false if there was no such secret.
+    fn delete(&self, service: &str, username: &str) -> io::Result<bool>;
+}
+
+/// The platform's keyring through the `keyring` crate.
+pub struct OsKeyring;
+
+impl OsKeyring {
+    /// Whether the keyring answers. On Linux the Secret Service is spoken to
+    /// over the session D-Bus, which headless sessions lack; elsewhere a
+    /// lookup is tried. The answer is worked out once per run.
+    pub fn available() -> bool {
+        static AVAILABLE: OnceLock<bool> = OnceLock::new();
+        *AVAILABLE.get_or_init(|| {
+            if cfg!(target_os = "linux") && env::var_os("DBUS_SESSION_BUS_ADDRESS").is_none() {
+                return false;
+            }
+            OsKeyring.get("velo", "probe").is_ok()
+        })
+    }
+}
+
+fn os_error(error: keyring::Error) -> io::Error {
+    io::Error::other(format!("OS keyring: {}", error))
+}
+
+impl KeyringBackend for OsKeyring {
+    fn name(&self) -> &'static str {
+        "OS keyring"
+    }
+
+    fn contains(&self, service: &str, username: &str) -> io::Result<bool> {
+        Ok(self.get(service, username)?.is_some())
+    }
+
+    fn get(&self, service: &str, username: &str) -> io::Result<Option<Zeroizing<String>>> {
+        let entry = Entry::new(service, username).map_err(os_error)?;
+        match entry.get_password() {
+            Ok(secret) => Ok(Some(Zeroizing::new(secret))),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(os_error(e)),
+        }
+    }
+
+    fn set(&self, service: &str, username: &str, secret: &str) -> io::Result<()> {
+        let entry = Entry::new(service, username).map_err(os_error)?;
+        entry.set_password(secret).map_err(os_error)
+    }
+
+    fn delete(&self, service: &str, username: &str) -> io::Result<bool> {
+        let entry = Entry::new(service, username).map_err(os_error)?;
+        match entry.delete_credential() {
+            Ok(()) => Ok(true),
+            Err(keyring::Error::NoEntry) => Ok(false),
+            Err(e) => Err(os_error(e)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyringFile {
+    header: Header,
+    check: Sealed,
+    entries: Vec<FileEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FileEntry {
+    service: String,
+    username: String,
+    #[serde(flatten)]
+    sealed: Sealed,
+}
+
+/// Secrets in an encrypted file, sealed the same way as the vault. The
+/// master password comes from VELO_KEYRING_KEY or is asked for the first
+/// time a secret is read or written. Which secrets exist can be seen, and
+/// removed, without it.
+pub struct FileKeyring {
+    path: PathBuf,
+    password: Option<Zeroizing<String>>,
+    key: RefCell<Option<SecretKey>>,
+}
+
+impl FileKeyring {
+    /// The keyring file in velo's data dir.
+    pub fn new() -> io::Result<FileKeyring> {
+        let password = env::var(KEY_ENV).ok().map(Zeroizing::new);
+        Ok(FileKeyring::at(
+            &velo_data_dir()?.join("keyring.json"),
+            password,
+        ))
+    }
+
+    /// A keyring file at `path`, unlocked with `password` if given and by
+    /// asking otherwise.
+    pub fn at(path: &Path, password: Option<Zeroizing<String>>) -> FileKeyring {
+        FileKeyring {
+            path: path.to_path_buf(),
+            password,
+            key: RefCell::new(None),
+        }
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    /// Unlocks the file so its key reaches the unlock agent, trying
+    /// `password` before asking for one.
+    pub fn unlock(&self, password: Option<&str>) -> io::Result<()> {
+        let Some(file) = self.load()? else {
+            return Ok(());
+        };
+        if let Some(password) = password {
+            if let Ok(key) =
+                SecretKey::unlock(&self.path, FILE_FORMAT, &file.header, &file.check, password)
+            {
+                key.cache();
+                return Ok(());
+            }
+        }
+        self.with_key(&file, |_| Ok(()))
+    }
+
+    fn load(&self) -> io::Result<Option<KeyringFile>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&self.path)?;
+        serde_json::from_str(&content).map(Some).map_err(invalid)
+    }
+
+    fn save(&self, file: &KeyringFile) -> io::Result<()> {
+        let content = serde_json::to_string_pretty(file)?;
+        atomic_write(&self.path, content.as_bytes())
+    }
+
+    /// Runs `f` with the key for `file`, deriving it on first use.
+    fn with_key<T>(
+        &self,
+        file: &KeyringFile,
+        f: impl FnOnce(&SecretKey) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let mut key = self.key.borrow_mut();
+        if key.as_ref().is_none_or(|key| *key.header() != file.header) {
+            *key = Some(match &self.password {
+                Some(password) => {
+                    SecretKey::unlock(&self.path, FILE_FORMAT, &file.header, &file.check, password)?
+                }
+                None => SecretKey::unlock_cached(
+                    &self.path,
+                    FILE_FORMAT,
+                    &file.header,
+                    &file.check,
+                    || prompt_password("Keyring master password: "),
+                )?,
+            });
+        }
+        f(key.as_ref().unwrap())
+    }
+
+    /// Creates an empty keyring file, asking for a new master password
+    /// unless one was given.
+    fn create(&self) -> io::Result<KeyringFile> {
+        let password = match &self.password {
+            Some(password) => password.clone(),
+            None => {
+                println!("Creating the velo keyring at {}.", self.path.display());
+                prompt_new_password("New keyring master password: ")?
+            }
+        };
+        let key = SecretKey::generate(FILE_FORMAT, &password)?;
+        if self.password.is_none() {
+            key.cache();
+        }
+        let file = KeyringFile {
+            header: key.header().clone(),
+            check: key.seal_check()?,
+            entries: Vec::new(),
+        };
+        *self.key.borrow_mut() = Some(key);
+        Ok(file)
+    }
+}
+
+impl KeyringBackend for FileKeyring {
+    fn name(&self) -> &'static str {
+        "file keyring"
+    }
+
+    fn contains(&self, service: &str, username: &str) -> io::Result<bool> {
+        Ok(self.load()?.is_some_and(|file| {
+            file.entries
+                .iter()
+                .any(|e| e.service == service && e.username == username)
+        }))
+    }
+
+    fn get(&self, service: &str, username: &str) -> io::Result<Option<Zeroizing<String>>> {
+        let Some(file) = self.load()? else {
+            return Ok(None);
+        };
+        let Some(entry) = file
+            .entries
+            .iter()
+            .find(|e| e.service == service && e.username == username)
+        else {
+            return Ok(None);
+        };
+        let plaintext = self.with_key(&file, |key| key.open(service, username, &entry.sealed))?;
+        let secret = String::from_utf8(plaintext.to_vec()).map_err(invalid)?;
+        Ok(Some(Zeroizing::new(secret)))
+    }
+
+    fn set(&self, service: &str, username: &str, secret: &str) -> io::Result<()> {
+        let mut file = match self.load()? {
+            Some(file) => file,
+            None => self.create()?,
+        };
+        let sealed = self.with_key(&file, |key| key.seal(service, username, secret.as_bytes()))?;
+        file.entries
+            .retain(|e| !(e.service == service && e.username == username));
+        file.entries.push(FileEntry {
+            service: service.to_string(),
+            username: username.to_string(),
+            sealed,
+        });
+        self.save(&file)
+    }
+
+    fn delete(&self, service: &str, username: &str) -> io::Result<bool> {
+        let Some(mut file) = self.load()? else {
+            return Ok(false);
+        };
+        let before = file.entries.len();
+        file.entries
+            .retain(|e| !(e.service == service && e.username == username));
+        if file.entries.len() == before {
+            return Ok(false);
+        }
+        self.save(&file)?;
+        Ok(true)
+    }
+}
+
+/// Secrets that last as long as the process; for tests and throwaway runs.
+/// All instances share one store, since `keyring()` hands out a new
+/// backend on every call.
+#[derive(Default)]
+pub struct MemoryKeyring;
+
+type SecretMap = HashMap<(String, String), Zeroizing<String>>;
+
+impl MemoryKeyring {
+    fn secrets() -> MutexGuard<'static, SecretMap> {
+        static SECRETS: OnceLock<Mutex<SecretMap>> = OnceLock::new();
+        SECRETS
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl KeyringBackend for MemoryKeyring {
+    fn name(&self) -> &'static str {
+        "in-memory keyring"
+    }
+
+    fn contains(&self, service: &str, username: &str) -> io::Result<bool> {
+        Ok(Self::secrets().contains_key(&(service.to_string(), username.to_string())))
+    }
+
+    fn get(&self, service: &str, username: &str) -> io::Result<Option<Zeroizing<String>>> {
+        Ok(Self::secrets()
+            .get(&(service.to_string(), username.to_string()))
+            .cloned())
+    }
+
+    fn set(&self, service: &str, username: &str, secret: &str) -> io::Result<()> {
+        Self::secrets().insert(
+            (service.to_string(), username.to_string()),
+            Zeroizing::new(secret.to_string()),
+        );
+        Ok(())
+    }
+
+    fn delete(&self, service: &str, username: &str) -> io::Result<bool> {
+        Ok(Self::secrets()
+            .remove(&(service.to_string(), username.to_string()))
+            .is_some())
+    }
+}
+
+/// The backend named by VELO_KEYRING, or else the OS keyring if it answers
+/// and the file keyring if not.
+pub fn keyring() -> io::Result<Box<dyn KeyringBackend>> {
+    match env::var(BACKEND_ENV).ok().as_deref() {
+        Some("os") => Ok(Box::new(OsKeyring)),
+        Some("file") => Ok(Box::new(FileKeyring::new()?)),
+        Some("memory") => Ok(Box::new(MemoryKeyring)),
+        Some(other) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Unknown keyring backend '{}' in {}; use os, file or memory",
+                other, BACKEND_ENV
+            ),
+        )),
+        None if OsKeyring::available() => Ok(Box::new(OsKeyring)),
+        None => Ok(Box::new(FileKeyring::new()?)),
+    }
+}
 
 // Store passphrase securely
-pub fn store_passphrase(service: &str, username: &str, passphrase: &str) -> Result<()> {
-    let entry = Entry::new(service, username)?;
-    entry.set_password(passphrase)?;
-    println!("Passphrase securely stored.");
+pub fn store_passphrase(service: &str, username: &str, passphrase: &str) -> io::Result<()> {
+    let keyring = keyring()?;
+    keyring.set(service, username, passphrase)?;
+    println!("Passphrase securely stored in the {}.", keyring.name());
     Ok(())
 }
 
-// Retrieve passphrase securely
-pub fn retrieve_passphrase(service: &str, username: &str) -> Option<String> {
-    match Entry::new(service, username) {
-        Ok(entry) => match entry.get_password() {
-            Ok(pass) => Some(pass),
-            Err(_) => {
-                println!("No passphrase found, or error retrieving it.");
-                None
-            }
-        },
-        Err(_) => {
-            println!("Error initializing keyring entry.");
+// Delete passphrase securely; false if there was none
+pub fn delete_passphrase(service: &str, username: &str) -> io::Result<bool> {
+    keyring()?.delete(service, username)
+}
+
+// Look up a secret without printing to stdout, for callers whose output is
+// read by another program. Failures are reported on stderr.
+pub fn lookup_secret(service: &str, username: &str) -> Option<Zeroizing<String>> {
+    match keyring().and_then(|keyring| keyring.get(service, username)) {
+        Ok(secret) => secret,
+        Err(e) => {
+            eprintln!("velo: {}", e);
             None
         }
     }
 }
 
-// Delete passphrase securely
-pub fn delete_passphrase(service: &str, username: &str) {
-    match Entry::new(service, username) {
-        Ok(entry) => match entry.delete_credential() {
-            Ok(_) => println!("Passphrase deleted successfully."),
-            Err(_) => println!("Failed to delete passphrase or it doesn't exist."),
-        },
-        Err(_) => println!("Error initializing keyring entry."),
+// Whether a secret is stored, without unlocking anything
+pub fn has_secret(service: &str, username: &str) -> bool {
+    keyring()
+        .and_then(|keyring| keyring.contains(service, username))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::testing::temp_path;
+
+    fn password(value: &str) -> Option<Zeroizing<String>> {
+        Some(Zeroizing::new(value.to_string()))
+    }
+
+    #[test]
+    fn memory_keyring_round_trip() {
+        let keyring = MemoryKeyring;
+        assert!(!keyring.contains("velo", "password:web").unwrap());
+        keyring.set("velo", "password:web", "hunter2").unwrap();
+        assert!(keyring.contains("velo", "password:web").unwrap());
+        assert_eq!(
+            keyring
+                .get("velo", "password:web")
+                .unwrap()
+                .as_deref()
+                .map(String::as_str),
+            Some("hunter2")
+        );
+        assert!(keyring.get("velo", "password:db").unwrap().is_none());
+        assert!(keyring.delete("velo", "password:web").unwrap());
+        assert!(!keyring.delete("velo", "password:web").unwrap());
+    }
+
+    #[test]
+    fn memory_keyring_outlives_instance() {
+        MemoryKeyring
+            .set("velo", "password:shared", "hunter2")
+            .unwrap();
+        let secret = MemoryKeyring.get("velo", "password:shared").unwrap();
+        assert_eq!(secret.as_deref().map(String::as_str), Some("hunter2"));
+        assert!(MemoryKeyring.delete("velo", "password:shared").unwrap());
+    }
+
+    #[test]
+    fn file_keyring_round_trip() {
+        let path = temp_path("keyring-round-trip");
+        let keyring = FileKeyring::at(&path, password("master"));
+        keyring.set("velo", "password:web", "hunter2").unwrap();
+        keyring.set("velo", "password:web", "hunter3").unwrap();
+        keyring.set("velo", "passphrase:SHA256:abc", "pp").unwrap();
+
+        // A fresh instance has to derive the key again.
+        let reopened = FileKeyring::at(&path, password("master"));
+        assert_eq!(
+            reopened
+                .get("velo", "password:web")
+                .unwrap()
+                .as_deref()
+                .map(String::as_str),
+            Some("hunter3")
+        );
+        assert!(reopened.delete("velo", "password:web").unwrap());
+        assert!(!reopened.contains("velo", "password:web").unwrap());
+        assert!(reopened.contains("velo", "passphrase:SHA256:abc").unwrap());
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(!content.contains("hunter") && !content.contains("\"pp\""));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn file_keyring_refuses_wrong_password() {
+        let path = temp_path("keyring-wrong-password");
+        FileKeyring::at(&path, password("master"))
+            .set("velo", "password:web", "hunter2")
+            .unwrap();
+
+        let keyring = FileKeyring::at(&path, password("wrong"));
+        let error = keyring.get("velo", "password:web").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        // Listing and removing need no password.
+        assert!(keyring.contains("velo", "password:web").unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn file_keyring_entries_cannot_be_swapped() {
+        let path = temp_path("keyring-swap");
+        let keyring = FileKeyring::at(&path, password("master"));
+        keyring.set("velo", "password:web", "hunter2").unwrap();
+        keyring.set("velo", "password:db", "letmein").unwrap();
+
+        let mut file = keyring.load().unwrap().unwrap();
+        file.entries[0].username = "password:db".to_string();
+        file.entries[1].username = "password:web".to_string();
+        keyring.save(&file).unwrap();
+        assert!(keyring.get("velo", "password:web").is_err());
+        fs::remove_file(&path).unwrap();
     }
 }
@@ -0,0 +1,667 @@
+// known_hosts management: reading the user's known_hosts files (hashed
+// names, markers and `[host]:port` entries included), finding the entries
+// ssh checks for a velo connection, and replacing a host key that changed
+// after a server was rebuilt.
+
+use crate::util::backup::atomic_write;
+use crate::util::inventory::{expand_identity_path, print_table};
+use crate::util::resolve::{display_path, resolve};
+use crate::util::ssh::{prompt_yes_no, ssh_dir, velo_runtime_dir, SSHConfig};
+use crate::util::ssh_config::{match_pattern_list, split_args};
+use base64::engine::general_purpose::{STANDARD as BASE64, STANDARD_NO_PAD as BASE64_NO_PAD};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// What ssh reads when UserKnownHostsFile is not set.
+const DEFAULT_KNOWN_HOSTS: [&str; 2] = ["known_hosts", "known_hosts2"];
+
+// Prefix of a name hashed with HashKnownHosts: `|1|salt|hash`, both base64.
+const HASH_MAGIC: &str = "|1|";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Marker {
+    CertAuthority,
+    Revoked,
+}
+
+impl Marker {
+    fn text(self) -> &'static str {
+        match self {
+            Marker::CertAuthority => "@cert-authority",
+            Marker::Revoked => "@revoked",
+        }
+    }
+}
+
+/// The host field of an entry.
+#[derive(Debug, Clone)]
+pub enum Hosts {
+    /// A comma-separated pattern list, e.g. `web,10.0.0.5` or `[web]:2222`.
+    Patterns(String),
+    Hashed {
+        salt: Vec<u8>,
+        hash: Vec<u8>,
+    },
+}
+
+/// One key line of a known_hosts file.
+#[derive(Debug, Clone)]
+pub struct KnownHost {
+    pub path: PathBuf,
+    /// 1-based.
+    pub line: usize,
+    pub marker: Option<Marker>,
+    pub hosts: Hosts,
+    pub key_type: String,
+    /// The base64 key blob.
+    pub key: String,
+    /// The line as written.
+    pub text: String,
+}
+
+impl KnownHost {
+    /// Parses a known_hosts line. Blank lines, comments and lines ssh would
+    /// skip give `None`.
+    fn parse(path: &Path, line: usize, text: &str) -> Option<Self> {
+        let text = text.trim();
+        if text.is_empty() || text.starts_with('#') {
+            return None;
+        }
+        let mut fields = text.split_whitespace();
+        let mut first = fields.next()?;
+        let marker = match first {
+            "@cert-authority" => Some(Marker::CertAuthority),
+            "@revoked" => Some(Marker::Revoked),
+            _ if first.starts_with('@') => return None,
+            _ => None,
+        };
+        if marker.is_some() {
+            first = fields.next()?;
+        }
+        let hosts = match first.strip_prefix(HASH_MAGIC) {
+            Some(hashed) => {
+                let (salt, hash) = hashed.split_once('|')?;
+                Hosts::Hashed {
+                    salt: BASE64.decode(salt).ok()?,
+                    hash: BASE64.decode(hash).ok()?,
+                }
+            }
+            None => Hosts::Patterns(first.to_string()),
+        };
+        Some(KnownHost {
+            path: path.to_path_buf(),
+            line,
+            marker,
+            hosts,
+            key_type: fields.next()?.to_string(),
+            key: fields.next()?.to_string(),
+            text: text.to_string(),
+        })
+    }
+
+    /// Whether ssh would use this entry for `name`, as produced by
+    /// `lookup_name`.
+    pub fn matches(&self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+        match &self.hosts {
+            Hosts::Patterns(patterns) => match_pattern_list(&name, patterns) == Some(true),
+            Hosts::Hashed { salt, hash } => {
+                let Ok(mut mac) = Hmac::<Sha1>::new_from_slice(salt) else {
+                    return false;
+                };
+                mac.update(name.as_bytes());
+                mac.verify_slice(hash).is_ok()
+            }
+        }
+    }
+
+    pub fn fingerprint(&self) -> String {
+        match BASE64.decode(&self.key) {
+            Ok(blob) => format!("SHA256:{}", BASE64_NO_PAD.encode(Sha256::digest(&blob))),
+            Err(_) => "(invalid key)".to_string(),
+        }
+    }
+
+    pub fn hosts_text(&self) -> String {
+        match &self.hosts {
+            Hosts::Patterns(patterns) => patterns.clone(),
+            Hosts::Hashed { .. } => "(hashed)".to_string(),
+        }
+    }
+
+    fn location(&self) -> String {
+        format!("{}:{}", display_path(&self.path), self.line)
+    }
+}
+
+/// Reads every entry of a known_hosts file; a missing file has none.
+pub fn read_known_hosts(path: &Path) -> io::Result<Vec<KnownHost>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(content
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| KnownHost::parse(path, i + 1, line))
+        .collect())
+}
+
+/// The name ssh looks up in known_hosts for `connection`: its
+/// HostKeyAlias, or the HostName with `[...]:port` for a port other than 22.
+pub fn lookup_name(config: &SSHConfig, connection: &str) -> String {
+    let resolution = resolve(config, connection);
+    if let Some(alias) = resolution.get("HostKeyAlias") {
+        return alias.to_ascii_lowercase();
+    }
+    let hostname = resolution.hostname().to_ascii_lowercase();
+    match resolution.get("Port") {
+        Some(port) if port != "22" => format!("[{}]:{}", hostname, port),
+        _ => hostname,
+    }
+}
+
+/// The user known_hosts files ssh reads for `connection`, or for any
+/// connection when `None`. New keys go to the first one.
+pub fn known_hosts_files(config: &SSHConfig, connection: Option<&str>) -> Vec<PathBuf> {
+    let connections = match connection {
+        Some(connection) => vec![connection.to_string()],
+        None => config.list_connections(),
+    };
+    let mut files = Vec::new();
+    for connection in connections {
+        let resolution = resolve(config, &connection);
+        let configured: Vec<PathBuf> = match resolution.get("UserKnownHostsFile") {
+            Some(value) => split_args(value)
+                .iter()
+                .filter(|file| file.as_str() != "/dev/null")
+                .filter_map(|file| expand_identity_path(file))
+                .collect(),
+            None => Vec::new(),
+        };
+        files.extend(configured);
+    }
+    if files.is_empty() || connection.is_none() {
+        files.extend(DEFAULT_KNOWN_HOSTS.iter().map(|name| ssh_dir().join(name)));
+    }
+    let mut unique: Vec<PathBuf> = Vec::new();
+    for file in files {
+        if !unique.contains(&file) {
+            unique.push(file);
+        }
+    }
+    unique
+}
+
+/// The entries in `files` that ssh checks for `name`.
+fn entries_for(files: &[PathBuf], name: &str) -> io::Result<Vec<KnownHost>> {
+    let mut entries = Vec::new();
+    for file in files {
+        entries.extend(
+            read_known_hosts(file)?
+                .into_iter()
+                .filter(|entry| entry.matches(name)),
+        );
+    }
+    Ok(entries)
+}
+
+/// Deletes the plain key entries for `name` from `files`, keeping each
+/// file's previous contents in `<file>.old` like `ssh-keygen -R`. Marker
+/// lines are left alone, since a CA or revocation usually covers more than
+/// one host. Returns the removed entries.
+pub fn remove_entries(files: &[PathBuf], name: &str) -> io::Result<Vec<KnownHost>> {
+    let mut removed = Vec::new();
+    for file in files {
+        let doomed: Vec<KnownHost> = read_known_hosts(file)?
+            .into_iter()
+            .filter(|entry| entry.marker.is_none() && entry.matches(name))
+            .collect();
+        if doomed.is_empty() {
+            continue;
+        }
+        let content = fs::read_to_string(file)?;
+        let kept: String = content
+            .split_inclusive('\n')
+            .enumerate()
+            .filter(|(i, _)| !doomed.iter().any(|entry| entry.line == i + 1))
+            .map(|(_, line)| line)
+            .collect();
+        let mut old = file.as_os_str().to_owned();
+        old.push(".old");
+        fs::write(PathBuf::from(old), &content)?;
+        atomic_write(file, kept.as_bytes())?;
+        removed.extend(doomed);
+    }
+    Ok(removed)
+}
+
+/// What ssh made of the host key a server presented.
+#[derive(Debug)]
+pub enum HostKeyCheck {
+    Matches {
+        location: String,
+    },
+    /// Known under another key; `offending` are ssh's `file:line` for it.
+    Changed {
+        offending: Vec<String>,
+    },
+    Revoked,
+    /// Not in known_hosts, at least not with this key type.
+    Unknown,
+    /// ssh did not get as far as checking, e.g. the host is unreachable.
+    Failed(String),
+}
+
+/// The result of connecting far enough to see the host key.
+pub struct HostKeyProbe {
+    /// `(type, fingerprint)` as ssh printed it.
+    pub server_key: Option<(String, String)>,
+    pub check: HostKeyCheck,
+}
+
+/// Runs ssh against `connection` up to the host key check and reads its
+/// verdict from the debug output. No authentication is attempted, and
+/// everything in the config (ProxyJump, HostKeyAlias, ports) applies as for
+/// a real login. `extra` adds `-o` options.
+fn probe_host_key(connection: &str, extra: &[String]) -> io::Result<HostKeyProbe> {
+    let mut command = Command::new("ssh");
+    command.arg("-v");
+    for option in extra.iter().map(String::as_str).chain([
+        "BatchMode=yes",
+        "StrictHostKeyChecking=yes",
+        "PreferredAuthentications=none",
+        "ControlPath=none",
+        "ConnectTimeout=10",
+    ]) {
+        command.args(["-o", option]);
+    }
+    let output = command.args([connection, "true"]).output()?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    let mut server_key = None;
+    let mut location = None;
+    let mut offending = Vec::new();
+    let mut changed = false;
+    let mut revoked = false;
+    let mut unknown = false;
+    for line in stderr.lines() {
+        if let Some(key) = line.strip_prefix("debug1: Server host key: ") {
+            let mut fields = key.split_whitespace();
+            if let (Some(key_type), Some(fingerprint)) = (fields.next(), fields.next()) {
+                server_key = Some((key_type.to_string(), fingerprint.to_string()));
+            }
+        } else if let Some(found) = line.strip_prefix("debug1: Found key in ") {
+            location = Some(found.trim().to_string());
+        } else if line.contains("REMOTE HOST IDENTIFICATION HAS CHANGED") {
+            changed = true;
+        } else if let Some(offender) = line.strip_prefix("Offending ") {
+            // "Offending ED25519 key in /home/u/.ssh/known_hosts:3"
+            if let Some((_, at)) = offender.split_once(" key in ") {
+                offending.push(at.trim().to_string());
+            }
+        } else if line.contains("REVOKED HOST KEY DETECTED") {
+            revoked = true;
+        } else if line.starts_with("No ") && line.contains("host key is known for") {
+            unknown = true;
+        }
+    }
+
+    let check = if revoked {
+        HostKeyCheck::Revoked
+    } else if changed {
+        HostKeyCheck::Changed { offending }
+    } else if unknown {
+        HostKeyCheck::Unknown
+    } else if let Some(location) = location {
+        HostKeyCheck::Matches { location }
+    } else {
+        let reason = stderr
+            .lines()
+            .rev()
+            .find(|line| !line.starts_with("debug") && !line.trim().is_empty())
+            .unwrap_or("ssh exited without checking the host key")
+            .to_string();
+        HostKeyCheck::Failed(reason)
+    };
+    Ok(HostKeyProbe { server_key, check })
+}
+
+/// Connects with an empty known_hosts file so ssh records whatever key the
+/// server presents, and returns the line it wrote. The file is created
+/// afresh in velo's private runtime directory, and its line must be for the
+/// key ssh reported, so nobody else can slip in a key to trust.
+fn fetch_host_key(connection: &str) -> io::Result<KnownHost> {
+    let temp = velo_runtime_dir()?.join(format!("known-hosts-{}", std::process::id()));
+    let _ = fs::remove_file(&temp);
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(&temp)?;
+
+    let probe = probe_host_key(
+        connection,
+        &[
+            format!("UserKnownHostsFile={}", temp.display()),
+            "GlobalKnownHostsFile=/dev/null".to_string(),
+            "StrictHostKeyChecking=accept-new".to_string(),
+        ],
+    );
+    let entries = read_known_hosts(&temp);
+    let _ = fs::remove_file(&temp);
+    let probe = probe?;
+    let Some((_, fingerprint)) = probe.server_key else {
+        return Err(io::Error::other(match probe.check {
+            HostKeyCheck::Failed(reason) => reason,
+            _ => format!("{} did not present a host key", connection),
+        }));
+    };
+    entries?
+        .into_iter()
+        .find(|entry| entry.fingerprint() == fingerprint)
+        .ok_or_else(|| {
+            io::Error::other(format!(
+                "ssh did not record the {} key {} presented",
+                fingerprint, connection
+            ))
+        })
+}
+
+/// Appends the line for `entry` to the first known_hosts file.
+fn trust_entry(files: &[PathBuf], entry: &KnownHost) -> io::Result<PathBuf> {
+    let file = files
+        .first()
+        .cloned()
+        .unwrap_or_else(|| ssh_dir().join(DEFAULT_KNOWN_HOSTS[0]));
+    let needs_newline =
+        fs::read(&file).is_ok_and(|content| !content.ends_with(b"\n") && !content.is_empty());
+    let mut out = OpenOptions::new().create(true).append(true).open(&file)?;
+    if needs_newline {
+        writeln!(out)?;
+    }
+    writeln!(out, "{}", entry.text)?;
+    Ok(file)
+}
+
+/// The explicit "remove the old key and re-trust it" flow: shows the key
+/// the server presents now, and only on confirmation replaces the old
+/// entries with it.
+fn retrust(config: &SSHConfig, connection: &str) -> io::Result<()> {
+    let name = lookup_name(config, connection);
+    let files = known_hosts_files(config, Some(connection));
+    let old = entries_for(&files, &name)?;
+    let new = fetch_host_key(connection)?;
+
+    println!("Known keys for {}:", name);
+    for entry in old.iter().filter(|entry| entry.marker.is_none()) {
+        println!(
+            "  {} {} ({})",
+            entry.key_type,
+            entry.fingerprint(),
+            entry.location()
+        );
+    }
+    println!("Key the server presents now:");
+    println!("  {} {}", new.key_type, new.fingerprint());
+    println!("Only trust it if you know the server was rebuilt or its keys were replaced,");
+    println!("ideally after comparing the fingerprint with the one on the server's console.");
+    if !prompt_yes_no("Remove the old key and trust the new one? (y/n): ") {
+        println!("known_hosts left unchanged.");
+        return Ok(());
+    }
+
+    let removed = remove_entries(&files, &name)?;
+    let file = trust_entry(&files, &new)?;
+    println!(
+        "Removed {} old entr{} and added the {} key to {}.",
+        removed.len(),
+        if removed.len() == 1 { "y" } else { "ies" },
+        new.key_type,
+        display_path(&file)
+    );
+    Ok(())
+}
+
+/// Explains a failed connection when the host key is to blame and offers
+/// to re-trust a changed key. Called after ssh exits with status 255; stays
+/// quiet when the failure is something else.
+pub fn explain_host_key_failure(connection: &str) -> io::Result<()> {
+    let config = SSHConfig::new()?;
+    let probe = probe_host_key(connection, &[])?;
+    let presented = probe
+        .server_key
+        .as_ref()
+        .map(|(key_type, fingerprint)| format!("{} {}", key_type, fingerprint))
+        .unwrap_or_default();
+    match probe.check {
+        HostKeyCheck::Changed { offending } => {
+            println!();
+            println!(
+                "The host key of {} is not the one in known_hosts ({}).",
+                connection,
+                offending.join(", ")
+            );
+            println!("This is expected after the server is rebuilt or reinstalled, but it is also");
+            println!("what a man-in-the-middle attack looks like. The server now presents:");
+            println!("  {}", presented);
+            retrust(&config, connection)
+        }
+        HostKeyCheck::Revoked => {
+            println!();
+            println!(
+                "The host key of {} ({}) is marked @revoked in known_hosts.",
+                connection, presented
+            );
+            println!("velo will not re-trust a revoked key; ask the server's administrator.");
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+fn list(config: &SSHConfig, connection: Option<&str>) -> io::Result<()> {
+    let files = known_hosts_files(config, connection);
+    let name = connection.map(|connection| lookup_name(config, connection));
+    let mut rows = Vec::new();
+    for file in &files {
+        for entry in read_known_hosts(file)? {
+            let hosts = match &name {
+                Some(name) if !entry.matches(name) => continue,
+                Some(name) if matches!(entry.hosts, Hosts::Hashed { .. }) => {
+                    format!("{} (hashed)", name)
+                }
+                _ => entry.hosts_text(),
+            };
+            rows.push(vec![
+                entry.location(),
+                hosts,
+                entry
+                    .marker
+                    .map(Marker::text)
+                    .unwrap_or_default()
+                    .to_string(),
+                entry.key_type.clone(),
+                entry.fingerprint(),
+            ]);
+        }
+    }
+    match (&name, rows.is_empty()) {
+        (Some(name), true) => println!("No known_hosts entries for {}.", name),
+        (None, true) => println!("No known_hosts entries."),
+        _ => print_table(
+            &["LOCATION", "HOST", "MARKER", "TYPE", "FINGERPRINT"],
+            &rows,
+        ),
+    }
+    Ok(())
+}
+
+fn remove(config: &SSHConfig, connection: &str) -> io::Result<()> {
+    let name = lookup_name(config, connection);
+    let files = known_hosts_files(config, Some(connection));
+    let removed = remove_entries(&files, &name)?;
+    if removed.is_empty() {
+        println!("No known_hosts entries for {}.", name);
+    }
+    for entry in removed {
+        println!(
+            "Removed {} {} ({})",
+            entry.key_type,
+            entry.fingerprint(),
+            entry.location()
+        );
+    }
+    Ok(())
+}
+
+fn scan(config: &SSHConfig, connection: &str) -> io::Result<()> {
+    let name = lookup_name(config, connection);
+    let files = known_hosts_files(config, Some(connection));
+    let key = fetch_host_key(connection)?;
+    println!("{} presents {} {}", name, key.key_type, key.fingerprint());
+
+    let known = entries_for(&files, &name)?;
+    let plain: Vec<&KnownHost> = known
+        .iter()
+        .filter(|entry| entry.marker.is_none())
+        .collect();
+    if plain.iter().any(|entry| entry.key == key.key) {
+        println!("This key is already trusted.");
+    } else if plain.iter().any(|entry| entry.key_type == key.key_type) {
+        println!(
+            "known_hosts has a different {} key for {}.",
+            key.key_type, name
+        );
+        println!(
+            "Run `velo known-hosts verify {}` to replace it.",
+            connection
+        );
+    } else if prompt_yes_no(&format!("Trust this key for {}? (y/n): ", name)) {
+        let file = trust_entry(&files, &key)?;
+        println!("Added the {} key to {}.", key.key_type, display_path(&file));
+    }
+    Ok(())
+}
+
+fn verify(config: &SSHConfig, connection: &str) -> io::Result<()> {
+    let probe = probe_host_key(connection, &[])?;
+    let presented = probe
+        .server_key
+        .as_ref()
+        .map(|(key_type, fingerprint)| format!("{} {}", key_type, fingerprint))
+        .unwrap_or_default();
+    match probe.check {
+        HostKeyCheck::Matches { location } => {
+            println!(
+                "{} matches known_hosts ({}): {}",
+                connection, location, presented
+            );
+            Ok(())
+        }
+        HostKeyCheck::Changed { offending } => {
+            println!(
+                "{} presents {}, which does not match known_hosts ({}).",
+                connection,
+                presented,
+                offending.join(", ")
+            );
+            retrust(config, connection)
+        }
+        HostKeyCheck::Revoked => {
+            println!(
+                "{} presents {}, which is marked @revoked.",
+                connection, presented
+            );
+            Ok(())
+        }
+        HostKeyCheck::Unknown => {
+            let name = lookup_name(config, connection);
+            let files = known_hosts_files(config, Some(connection));
+            let known = entries_for(&files, &name)?;
+            if known.iter().all(|entry| entry.marker.is_some()) {
+                println!(
+                    "{} is not in known_hosts; it presents {}.",
+                    connection, presented
+                );
+                println!("Run `velo known-hosts scan {}` to trust it.", connection);
+                return Ok(());
+            }
+            // After a rebuild a server may no longer offer the key type
+            // that was recorded, so ssh sees a new key rather than a changed one.
+            let types: Vec<&str> = known.iter().map(|entry| entry.key_type.as_str()).collect();
+            println!(
+                "{} presents {}, but known_hosts only has {} for it.",
+                connection,
+                presented,
+                types.join(", ")
+            );
+            retrust(config, connection)
+        }
+        HostKeyCheck::Failed(reason) => Err(io::Error::other(reason)),
+    }
+}
+
+pub fn handle_known_hosts(args: &[String]) -> io::Result<()> {
+    const USAGE: &str = "Usage: velo known-hosts list [<connection_name>] | remove <connection_name> | scan <connection_name> | verify <connection_name>";
+    let config = SSHConfig::new()?;
+    let connection = args.get(1).map(String::as_str);
+    if let Some(connection) = connection {
+        if !config.has_connection(connection) {
+            println!("Connection '{}' not found.", connection);
+            return Ok(());
+        }
+    }
+    match (args.first().map(String::as_str), connection, args.len()) {
+        (Some("list"), _, 1 | 2) => list(&config, connection),
+        (Some("remove"), Some(connection), 2) => remove(&config, connection),
+        (Some("scan"), Some(connection), 2) => scan(&config, connection),
+        (Some("verify"), Some(connection), 2) => verify(&config, connection),
+        _ => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(line: &str) -> KnownHost {
+        KnownHost::parse(Path::new("known_hosts"), 1, line).expect("entry")
+    }
+
+    #[test]
+    fn parses_markers_and_ports() {
+        let ca = entry("@cert-authority *.example.com ssh-ed25519 AAAAC3Nza");
+        assert_eq!(ca.marker, Some(Marker::CertAuthority));
+        assert!(ca.matches("web.example.com"));
+
+        let ported = entry("[web]:2222,10.0.0.5 ssh-ed25519 AAAAC3Nza comment");
+        assert!(ported.matches("[web]:2222"));
+        assert!(ported.matches("10.0.0.5"));
+        assert!(!ported.matches("web"));
+
+        assert!(KnownHost::parse(Path::new("known_hosts"), 1, "# comment").is_none());
+        assert!(KnownHost::parse(Path::new("known_hosts"), 1, "@bogus web ssh-rsa AAAA").is_none());
+    }
+
+    #[test]
+    fn matches_hashed_names() {
+        // `ssh-keygen -H` output for "web.example.com".
+        let hashed = entry(
+            "|1|2LmFTWmVoohyiaYynKawD8riWc0=|5dIeOadrb+bLW90sxl/zAwZoG9o= ssh-ed25519 AAAAC3Nza",
+        );
+        assert!(hashed.matches("web.example.com"));
+        assert!(hashed.matches("WEB.example.com"));
+        assert!(!hashed.matches("db.example.com"));
+    }
+}
@@ -1,8 +1,25 @@
 // src/util/mod.rs
 
+pub mod agent;
+pub mod askpass;
+pub mod audit;
+pub mod backup;
 pub(crate) mod completion;
+pub mod doctor;
+pub mod forward;
 pub mod help;
+pub mod inventory;
+pub mod journal;
+pub mod key;
 pub mod keyring;
+pub mod known_hosts;
+pub mod resolve;
 pub mod ssh;
+pub mod ssh_config;
+#[cfg(test)]
+mod testing;
+pub mod tunnel;
 pub mod ui;
+pub mod unlock;
+pub mod vault;
 pub mod zellij;
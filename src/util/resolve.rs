@@ -0,0 +1,545 @@
+// Effective-config resolution: works out which settings ssh will use for a
+// host by applying Host/Match blocks in file order, first value wins.
+
+use crate::util::ssh::{jump_hop_hosts, ssh_dir, ConfigEvent, SSHConfig};
+use crate::util::ssh_config::{host_matches, match_pattern_list, Block, BlockKind};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Keywords where every occurrence adds to the list instead of the first one
+// winning.
+const MULTI_VALUED: [&str; 6] = [
+    "identityfile",
+    "certificatefile",
+    "localforward",
+    "remoteforward",
+    "dynamicforward",
+    "sendenv",
+];
+
+/// Where a setting was read from.
+#[derive(Debug, Clone)]
+pub struct Origin {
+    pub path: PathBuf,
+    pub line: usize,
+    /// The block header, e.g. `Host *.prod`, or `(global)`.
+    pub block: String,
+}
+
+impl Origin {
+    pub fn describe(&self) -> String {
+        format!(
+            "{} ({}:{})",
+            self.block,
+            display_path(&self.path),
+            self.line
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Setting {
+    /// Keyword as first written in the config.
+    pub keyword: String,
+    pub value: String,
+    pub origin: Origin,
+}
+
+/// The settings that apply to one host.
+#[derive(Debug, Default)]
+pub struct Resolution {
+    pub host: String,
+    pub settings: Vec<Setting>,
+    /// Match blocks whose criteria velo cannot evaluate (e.g. `exec`); they
+    /// are treated as not matching.
+    pub unevaluated: Vec<Origin>,
+}
+
+impl Resolution {
+    pub fn get(&self, keyword: &str) -> Option<&str> {
+        self.settings
+            .iter()
+            .find(|setting| setting.keyword.eq_ignore_ascii_case(keyword))
+            .map(|setting| setting.value.as_str())
+    }
+
+    pub fn get_all(&self, keyword: &str) -> Vec<&str> {
+        self.settings
+            .iter()
+            .filter(|setting| setting.keyword.eq_ignore_ascii_case(keyword))
+            .map(|setting| setting.value.as_str())
+            .collect()
+    }
+
+    /// The host ssh will actually connect to.
+    pub fn hostname(&self) -> String {
+        match self.get("HostName") {
+            Some(hostname) => hostname.replace("%h", &self.host),
+            None => self.host.clone(),
+        }
+    }
+
+    fn apply(&mut self, keyword: &str, value: &str, origin: Origin) {
+        let lower = keyword.to_ascii_lowercase();
+        if !MULTI_VALUED.contains(&lower.as_str()) && self.get(keyword).is_some() {
+            return;
+        }
+        self.settings.push(Setting {
+            keyword: keyword.to_string(),
+            value: value.to_string(),
+            origin,
+        });
+    }
+}
+
+pub fn resolve(config: &SSHConfig, host: &str) -> Resolution {
+    let mut resolution = Resolution {
+        host: host.to_string(),
+        ..Default::default()
+    };
+
+    // Mirrors readconf.c: `active` is whether the current block applies,
+    // `never` is set inside files included from a block that does not, and
+    // both are restored when an Include ends.
+    let mut active = true;
+    let mut never = false;
+    let mut label = "(global)".to_string();
+    let mut stack: Vec<(bool, bool, String)> = Vec::new();
+
+    config.walk(&mut |event| match event {
+        ConfigEvent::Block {
+            path, line, block, ..
+        } => {
+            label = block.header.to_string().trim().to_string();
+            active = !never
+                && match block.kind() {
+                    BlockKind::Host => host_matches(host, &block.patterns()),
+                    BlockKind::Match => match match_block(block, &resolution) {
+                        Some(matched) => matched,
+                        None => {
+                            resolution.unevaluated.push(Origin {
+                                path: path.to_path_buf(),
+                                line,
+                                block: label.clone(),
+                            });
+                            false
+                        }
+                    },
+                };
+        }
+        ConfigEvent::Directive {
+            path,
+            line,
+            directive,
+        } => {
+            if active {
+                let origin = Origin {
+                    path: path.to_path_buf(),
+                    line,
+                    block: label.clone(),
+                };
+                resolution.apply(directive.keyword(), directive.value(), origin);
+            }
+        }
+        ConfigEvent::IncludeStart => {
+            stack.push((active, never, label.clone()));
+            never = never || !active;
+        }
+        ConfigEvent::IncludeEnd => {
+            if let Some(saved) = stack.pop() {
+                (active, never, label) = saved;
+            }
+        }
+    });
+
+    resolution
+}
+
+/// Evaluates a `Match` line. Returns `None` if it uses a criterion velo
+/// cannot check without side effects, such as `exec`.
+fn match_block(block: &Block, resolution: &Resolution) -> Option<bool> {
+    let args = block.header.args();
+    let mut criteria = args.iter();
+    let mut matched = true;
+
+    while let Some(criterion) = criteria.next() {
+        let (negate, name) = match criterion.strip_prefix('!') {
+            Some(name) => (true, name.to_ascii_lowercase()),
+            None => (false, criterion.to_ascii_lowercase()),
+        };
+        let result = match name.as_str() {
+            "all" => true,
+            "canonical" => false,
+            "final" => true,
+            "host" | "originalhost" | "user" | "localuser" => {
+                let list = criteria.next()?;
+                let subject = match name.as_str() {
+                    "host" => resolution.hostname(),
+                    "originalhost" => resolution.host.clone(),
+                    "user" => resolution
+                        .get("User")
+                        .map(str::to_string)
+                        .unwrap_or_else(whoami::username),
+                    _ => whoami::username(),
+                };
+                match_pattern_list(&subject, list) == Some(true)
+            }
+            _ => return None,
+        };
+        if result == negate {
+            matched = false;
+        }
+    }
+    Some(matched)
+}
+
+/// Hosts named in the effective ProxyJump for `host`.
+pub fn jump_hosts(config: &SSHConfig, host: &str) -> Vec<String> {
+    resolve(config, host)
+        .get("ProxyJump")
+        .map(jump_hop_hosts)
+        .unwrap_or_default()
+}
+
+/// Checks a ProxyJump value about to be written for `name`: every hop has
+/// to be a velo connection and following the hops must never lead back to
+/// `name`.
+pub fn check_jump_hosts(config: &SSHConfig, name: &str, value: &str) -> Result<(), String> {
+    let hops = jump_hop_hosts(value);
+    for hop in &hops {
+        if hop != name && !config.has_connection(hop) {
+            return Err(format!("Jump host '{}' is not a velo connection", hop));
+        }
+    }
+
+    let mut paths: Vec<Vec<String>> = hops
+        .into_iter()
+        .map(|hop| vec![name.to_string(), hop])
+        .collect();
+    let mut seen = Vec::new();
+    while let Some(path) = paths.pop() {
+        let host = &path[path.len() - 1];
+        if host == name {
+            return Err(format!("Jump chain loops: {}", path.join(" -> ")));
+        }
+        if seen.contains(host) {
+            continue;
+        }
+        seen.push(host.clone());
+        for next in jump_hosts(config, host) {
+            let mut longer = path.clone();
+            longer.push(next);
+            paths.push(longer);
+        }
+    }
+    Ok(())
+}
+
+/// The hosts ssh goes through to reach `name`, ending with `name` itself.
+/// Only the first hop's own ProxyJump is followed, as ssh does.
+pub fn jump_chain(config: &SSHConfig, name: &str) -> Result<Vec<String>, String> {
+    let mut chain = vec![name.to_string()];
+    let mut expanded = vec![name.to_string()];
+    loop {
+        let hops = jump_hosts(config, &chain[0]);
+        let Some(first) = hops.first().cloned() else {
+            return Ok(chain);
+        };
+        chain.splice(0..0, hops);
+        if expanded.contains(&first) {
+            return Err(format!("Jump chain loops: {}", chain.join(" -> ")));
+        }
+        expanded.push(first);
+    }
+}
+
+/// A setting where velo and `ssh -G` disagree.
+pub struct Mismatch {
+    pub keyword: String,
+    pub velo: String,
+    pub ssh: String,
+}
+
+/// Compares the resolution with `ssh -G`. Returns `None` if ssh is not
+/// available.
+pub fn compare_with_ssh(resolution: &Resolution) -> Option<Vec<Mismatch>> {
+    // Point ssh at the same file velo read; this also leaves out the system
+    // config, which velo does not load either.
+    let output = Command::new("ssh")
+        .arg("-F")
+        .arg(ssh_dir().join("config"))
+        .arg("-G")
+        .arg(&resolution.host)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Some(compare(resolution, &stdout))
+}
+
+/// Compares the resolution with the `keyword value` lines `ssh -G` printed.
+fn compare(resolution: &Resolution, ssh_output: &str) -> Vec<Mismatch> {
+    let mut ssh: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for line in ssh_output.lines() {
+        if let Some((key, value)) = line.split_once(' ') {
+            ssh.entry(key.to_string())
+                .or_default()
+                .push(normalize(value));
+        }
+    }
+
+    let keywords: BTreeSet<String> = resolution
+        .settings
+        .iter()
+        .map(|setting| setting.keyword.to_ascii_lowercase())
+        .collect();
+
+    let mut mismatches = Vec::new();
+    for keyword in keywords {
+        let Some(ssh_values) = ssh.get(&keyword) else {
+            continue;
+        };
+        let velo_values = resolution.get_all(&keyword);
+        // ssh expands tokens and applies +/-/^ algorithm list edits, so
+        // those values cannot be compared textually.
+        if velo_values.iter().any(|value| {
+            value.contains('%') || value.contains("${") || value.starts_with(['+', '-', '^'])
+        }) {
+            continue;
+        }
+
+        let agrees = if keyword == "sendenv" {
+            let words = |values: &[String]| {
+                let mut words: Vec<String> = values
+                    .iter()
+                    .flat_map(|value| value.split(' ').map(str::to_string))
+                    .collect();
+                words.sort();
+                words
+            };
+            let velo: Vec<String> = velo_values.iter().map(|v| normalize(v)).collect();
+            words(&velo) == words(ssh_values)
+        } else if MULTI_VALUED.contains(&keyword.as_str()) {
+            velo_values
+                .iter()
+                .all(|value| ssh_values.contains(&normalize(value)))
+        } else {
+            ssh_values.first() == Some(&normalize(velo_values[0]))
+        };
+
+        if !agrees {
+            mismatches.push(Mismatch {
+                keyword,
+                velo: velo_values.join(", "),
+                ssh: ssh_values.join(", "),
+            });
+        }
+    }
+    mismatches
+}
+
+// ssh -G prints lowercased values with single spaces and brackets around
+// forwarding hosts. It also spells some flags true/false where the config
+// says yes/no, and accepts off for no, so those are folded together.
+fn normalize(value: &str) -> String {
+    let value = value
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace(['[', ']', '"'], "")
+        .to_ascii_lowercase();
+    match value.as_str() {
+        "true" => "yes".to_string(),
+        "false" | "off" => "no".to_string(),
+        _ => value,
+    }
+}
+
+pub fn display_path(path: &Path) -> String {
+    match dirs::home_dir().and_then(|home| path.strip_prefix(home).ok().map(Path::to_path_buf)) {
+        Some(relative) => format!("~/{}", relative.display()),
+        None => path.display().to_string(),
+    }
+}
+
+/// The report behind `velo show`, one line per entry, shared with the TUI.
+pub fn describe_connection(name: &str) -> io::Result<Vec<String>> {
+    let config = SSHConfig::new()?;
+    let Some((path, line, block)) = config.host_location(name) else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Connection '{}' not found", name),
+        ));
+    };
+
+    let mut report = vec![format!("# {}:{}", display_path(path), line)];
+    report.extend(block.to_string().trim_end().lines().map(str::to_string));
+    report.push(String::new());
+
+    let resolution = resolve(&config, name);
+    report.push("Effective settings:".to_string());
+    let width = resolution
+        .settings
+        .iter()
+        .map(|setting| setting.keyword.len() + setting.value.len())
+        .max()
+        .unwrap_or(0)
+        + 2;
+    for setting in &resolution.settings {
+        let pair = format!("{} {}", setting.keyword, setting.value);
+        report.push(format!(
+            "  {:width$} <- {}",
+            pair,
+            setting.origin.describe(),
+            width = width
+        ));
+    }
+    for origin in &resolution.unevaluated {
+        report.push(format!("  (not evaluated: {})", origin.describe()));
+    }
+    report.push(String::new());
+
+    if resolution.get("ProxyJump").is_some() {
+        match jump_chain(&config, name) {
+            Ok(chain) => report.push(format!("Jump chain: localhost -> {}", chain.join(" -> "))),
+            Err(e) => report.push(e),
+        }
+        report.push(String::new());
+    }
+
+    match compare_with_ssh(&resolution) {
+        None => report.push("ssh -G not available; skipped cross-check.".to_string()),
+        Some(mismatches) if mismatches.is_empty() => {
+            report.push(format!("ssh -G {} agrees with every setting.", name))
+        }
+        Some(mismatches) => {
+            report.push(format!("ssh -G {} disagrees:", name));
+            for mismatch in mismatches {
+                report.push(format!(
+                    "  {}: velo resolved '{}', ssh uses '{}'",
+                    mismatch.keyword, mismatch.velo, mismatch.ssh
+                ));
+            }
+            if !resolution.unevaluated.is_empty() {
+                report.push(
+                    "  (Match blocks velo could not evaluate may account for this)".to_string(),
+                );
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+pub fn handle_show(args: &[String]) -> io::Result<()> {
+    if args.is_empty() {
+        println!("Usage: velo show <connection_name>");
+        return Ok(());
+    }
+
+    match describe_connection(&args[0]) {
+        Ok(report) => {
+            for line in report {
+                println!("{}", line);
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => println!("{}", e),
+        Err(e) => return Err(e),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::testing::temp_path;
+    use std::fs;
+
+    fn resolve_in(name: &str, config: &str, host: &str) -> Resolution {
+        let path = temp_path(name);
+        fs::write(&path, config).unwrap();
+        let config = SSHConfig::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        resolve(&config, host)
+    }
+
+    #[test]
+    fn first_value_wins() {
+        let resolution = resolve_in(
+            "resolve-first",
+            "Host web\n  User deploy\n  IdentityFile ~/.ssh/web\n\n\
+             Host *\n  User root\n  Port 2222\n  IdentityFile ~/.ssh/default\n",
+            "web",
+        );
+        assert_eq!(resolution.get("user"), Some("deploy"));
+        assert_eq!(resolution.get("Port"), Some("2222"));
+        assert_eq!(
+            resolution.get_all("IdentityFile"),
+            vec!["~/.ssh/web", "~/.ssh/default"]
+        );
+        assert_eq!(resolution.settings[0].origin.block, "Host web");
+        assert_eq!(resolution.settings[0].origin.line, 2);
+    }
+
+    #[test]
+    fn host_patterns_and_negation() {
+        let config = "Host *.prod !db.prod\n  User ops\n\nHost db.*\n  Port 5432\n";
+        let web = resolve_in("resolve-host-web", config, "web.prod");
+        assert_eq!(web.get("User"), Some("ops"));
+        assert_eq!(web.get("Port"), None);
+
+        let db = resolve_in("resolve-host-db", config, "db.prod");
+        assert_eq!(db.get("User"), None);
+        assert_eq!(db.get("Port"), Some("5432"));
+    }
+
+    #[test]
+    fn match_blocks() {
+        let config = "Host web\n  HostName 10.0.0.5\n  User deploy\n\n\
+                      Match host 10.0.0.* user deploy\n  Port 2200\n\n\
+                      Match originalhost web !user deploy\n  Port 2300\n\n\
+                      Match exec \"true\"\n  Port 2400\n\n\
+                      Match all\n  Compression yes\n";
+        let resolution = resolve_in("resolve-match", config, "web");
+        assert_eq!(resolution.get("Port"), Some("2200"));
+        assert_eq!(resolution.get("Compression"), Some("yes"));
+        assert_eq!(resolution.unevaluated.len(), 1);
+        assert_eq!(resolution.unevaluated[0].block, "Match exec \"true\"");
+
+        let other = resolve_in("resolve-match-other", config, "other");
+        assert_eq!(other.get("Port"), None);
+    }
+
+    #[test]
+    fn comparison_with_ssh_output() {
+        let resolution = resolve_in(
+            "resolve-compare",
+            "Host web\n  StrictHostKeyChecking yes\n  ForwardAgent no\n  \
+             LocalForward 8080 [localhost]:80\n  SendEnv LANG LC_*\n  Port 22\n  \
+             User %r\n",
+            "web",
+        );
+        let ssh = "stricthostkeychecking true\nforwardagent no\n\
+                   localforward 8080 localhost:80\nsendenv LC_*\nsendenv LANG\n\
+                   port 2222\nuser someone\n";
+        let mismatches = compare(&resolution, ssh);
+        let keywords: Vec<&str> = mismatches.iter().map(|m| m.keyword.as_str()).collect();
+        assert_eq!(keywords, vec!["port"]);
+        assert_eq!(mismatches[0].velo, "22");
+        assert_eq!(mismatches[0].ssh, "2222");
+    }
+
+    #[test]
+    fn normalize_folds_boolean_spellings() {
+        assert_eq!(normalize("True"), "yes");
+        assert_eq!(normalize("false"), "no");
+        assert_eq!(normalize("off"), "no");
+        assert_eq!(normalize("yes"), "yes");
+        assert_eq!(normalize("ask"), "ask");
+        assert_eq!(normalize("  [::1]:80   \"a b\" "), "::1:80 a b");
+    }
+}
@@ -1,103 +1,491 @@
+use crate::util::agent::{ensure_agent, load_connection_keys};
+use crate::util::askpass::{forget_connection_secrets, move_connection_secrets, PasswordLogin};
+use crate::util::backup::{atomic_write, backup_files};
+use crate::util::doctor::{fix_permissions, PathKind};
+use crate::util::forward::{file_safe_name, forward_session_config};
+use crate::util::journal::{self, FileChange};
+use crate::util::key::{load_stored_keys, offer_to_store_passphrase, public_key_path, ssh_add};
+use crate::util::known_hosts::explain_host_key_failure;
+use crate::util::resolve::check_jump_hosts;
+use crate::util::ssh_config::{
+    is_literal_pattern, match_pattern_list, quote_arg, Block, BlockEdit, ConfigDocument, Directive,
+    Line,
+};
+use crate::util::vault::{store_secret, SecretKind};
 use crate::util::zellij::*;
 use ratatui::crossterm::{
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
     ExecutableCommand,
 };
-use regex::Regex;
+use sha2::{Digest, Sha256};
 use std::fs::{self, Permissions};
 use std::fs::{File, OpenOptions};
 use std::io;
 use std::io::{stdin, stdout, BufReader, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+
+// OpenSSH gives up on Include chains deeper than this (READCONF_MAX_DEPTH).
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+// How many times an edit is re-applied when the files keep changing on disk
+// underneath it before velo gives up.
+const MAX_EDIT_ATTEMPTS: usize = 3;
+
+// Hosts added by velo go here rather than into the hand-written main file.
+const VELO_INCLUDE: &str = "config.d/velo.conf";
+
+// Runs ssh in a Zellij pane, which outlives velo, and has velo check the
+// host key in the same pane when ssh itself fails. `$0` is velo, `$1` the
+// connection and `$2` a session config to delete once ssh is done, or
+// empty; the rest are ssh's arguments.
+const SSH_IN_PANE: &str = r#"c=$1; f=$2; shift 2; ssh "$@"; s=$?; [ -n "$f" ] && rm -f "$f"; [ "$s" -eq 255 ] && "$0" known-hosts verify "$c"; exit "$s""#;
+
+/// What `SSHConfig::walk` reports. `line` is the 1-based line in `path`.
+pub enum ConfigEvent<'a> {
+    Block {
+        path: &'a Path,
+        line: usize,
+        file: usize,
+        index: usize,
+        block: &'a Block,
+    },
+    Directive {
+        path: &'a Path,
+        line: usize,
+        directive: &'a Directive,
+    },
+    IncludeStart,
+    IncludeEnd,
+}
 
+/// The user's ssh config: `~/.ssh/config` plus every file it pulls in
+/// through `Include`, each kept as its own document so edits are written
+/// back to the file a host came from.
 pub struct SSHConfig {
+    files: Vec<ConfigFile>,
+}
+
+struct ConfigFile {
     path: PathBuf,
-    content: String,
+    document: ConfigDocument,
+    dirty: bool,
+    /// The file as it was when read; `None` if it did not exist.
+    stamp: Option<Stamp>,
+}
+
+/// What a file looked like on disk, to notice edits made behind velo's back.
+/// Only the contents count: an edit can keep the mtime, for example when it
+/// lands within the filesystem's timestamp granularity.
+struct Stamp {
+    hash: Vec<u8>,
+}
+
+impl Stamp {
+    fn new(content: &[u8]) -> Self {
+        Stamp {
+            hash: Sha256::digest(content).to_vec(),
+        }
+    }
+
+    /// Reads the stamp of `path` as it is now.
+    fn read(path: &Path) -> io::Result<Option<Self>> {
+        match fs::read(path) {
+            Ok(content) => Ok(Some(Stamp::new(&content))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn matches(&self, path: &Path) -> io::Result<bool> {
+        Ok(Stamp::read(path)?.is_some_and(|stamp| stamp.hash == self.hash))
+    }
 }
 
 impl SSHConfig {
     pub fn new() -> std::io::Result<Self> {
-        let home_dir = dirs::home_dir().expect("Unable to determine home directory");
-        let config_path = home_dir.join(".ssh").join("config");
+        Self::load(&ssh_dir().join("config"))
+    }
+
+    /// Reads the config starting at `path` instead of `~/.ssh/config`, as
+    /// `ssh -F` does.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut config = SSHConfig { files: Vec::new() };
+        config.load_file(path, 0)?;
+        Ok(config)
+    }
+
+    fn load_file(&mut self, path: &Path, depth: usize) -> io::Result<()> {
+        if depth > MAX_INCLUDE_DEPTH || self.file_index(path).is_some() {
+            return Ok(());
+        }
+
         let mut content = String::new();
+        let mut stamp = None;
+        if path.exists() {
+            File::open(path)?.read_to_string(&mut content)?;
+            stamp = Some(Stamp::new(content.as_bytes()));
+        }
 
-        if config_path.exists() {
-            let mut file = File::open(&config_path)?;
-            file.read_to_string(&mut content)?;
+        let document = ConfigDocument::parse(&content);
+        let patterns: Vec<String> = document.lines().flat_map(include_patterns).collect();
+
+        self.files.push(ConfigFile {
+            path: path.to_path_buf(),
+            document,
+            dirty: false,
+            stamp,
+        });
+
+        for pattern in patterns {
+            let Ok(paths) = glob::glob(&expand_include_path(&pattern).to_string_lossy()) else {
+                continue;
+            };
+            for included in paths.flatten().filter(|p| p.is_file()) {
+                if let Err(e) = self.load_file(&included, depth + 1) {
+                    eprintln!("Skipping included file {}: {}", included.display(), e);
+                }
+            }
         }
+        Ok(())
+    }
 
-        Ok(SSHConfig {
-            path: config_path,
-            content,
+    fn file_index(&self, path: &Path) -> Option<usize> {
+        let wanted = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        self.files.iter().position(|file| {
+            fs::canonicalize(&file.path).unwrap_or_else(|_| file.path.clone()) == wanted
         })
     }
 
-    pub fn key_exists_on_remote(
-        &self,
-        connection_name: &str,
-        public_key_path: &PathBuf,
+    /// Loaded files matched by an `Include` line, in the sorted order ssh
+    /// reads them.
+    fn included_files(&self, line: &Line) -> Vec<usize> {
+        let options = glob::MatchOptions {
+            require_literal_separator: true,
+            ..Default::default()
+        };
+        let mut included = Vec::new();
+        for pattern in include_patterns(line) {
+            let Ok(pattern) = glob::Pattern::new(&expand_include_path(&pattern).to_string_lossy())
+            else {
+                continue;
+            };
+            let mut matches: Vec<usize> = (0..self.files.len())
+                .filter(|&i| pattern.matches_path_with(&self.files[i].path, options))
+                .collect();
+            matches.sort_by(|&a, &b| self.files[a].path.cmp(&self.files[b].path));
+            included.extend(matches);
+        }
+        included
+    }
+
+    /// Every (file, block) pair in the order ssh reads them, i.e. with each
+    /// `Include` expanded in place.
+    fn block_order(&self) -> Vec<(usize, usize)> {
+        let mut order = Vec::new();
+        self.walk(&mut |event| {
+            if let ConfigEvent::Block { file, index, .. } = event {
+                order.push((file, index));
+            }
+        });
+        order
+    }
+
+    /// Visits the whole config the way ssh reads it: line by line, with the
+    /// contents of each `Include` reported in place between `IncludeStart`
+    /// and `IncludeEnd`.
+    pub fn walk<'a>(&'a self, visit: &mut dyn FnMut(ConfigEvent<'a>)) {
+        let mut visiting = Vec::new();
+        if !self.files.is_empty() {
+            self.walk_file(0, &mut visiting, visit);
+        }
+    }
+
+    fn walk_file<'a>(
+        &'a self,
+        file: usize,
+        visiting: &mut Vec<usize>,
+        visit: &mut dyn FnMut(ConfigEvent<'a>),
+    ) {
+        if visiting.contains(&file) || visiting.len() > MAX_INCLUDE_DEPTH {
+            return;
+        }
+        visiting.push(file);
+
+        let path = self.files[file].path.as_path();
+        let document = &self.files[file].document;
+        let mut number = 0;
+        for line in &document.global {
+            number += 1;
+            self.walk_line(path, line, number, visiting, visit);
+        }
+        for (index, block) in document.blocks.iter().enumerate() {
+            number += 1;
+            visit(ConfigEvent::Block {
+                path,
+                line: number,
+                file,
+                index,
+                block,
+            });
+            for line in &block.lines {
+                number += 1;
+                self.walk_line(path, line, number, visiting, visit);
+            }
+        }
+
+        visiting.pop();
+    }
+
+    fn walk_line<'a>(
+        &'a self,
+        path: &'a Path,
+        line: &'a Line,
+        number: usize,
+        visiting: &mut Vec<usize>,
+        visit: &mut dyn FnMut(ConfigEvent<'a>),
+    ) {
+        let Some(directive) = line.as_directive() else {
+            return;
+        };
+        if !directive.is("Include") {
+            visit(ConfigEvent::Directive {
+                path,
+                line: number,
+                directive,
+            });
+            return;
+        }
+        for included in self.included_files(line) {
+            visit(ConfigEvent::IncludeStart);
+            self.walk_file(included, visiting, visit);
+            visit(ConfigEvent::IncludeEnd);
+        }
+    }
+
+    /// Whether the main config pulls in `index`, directly or not.
+    fn is_included(&self, index: usize) -> bool {
+        let mut seen = vec![0];
+        let mut stack = vec![0];
+        while let Some(current) = stack.pop() {
+            for line in self.files[current].document.lines() {
+                for included in self.included_files(line) {
+                    if !seen.contains(&included) {
+                        seen.push(included);
+                        stack.push(included);
+                    }
+                }
+            }
+        }
+        seen.contains(&index)
+    }
+
+    /// The file and block index of the first `Host` block naming `name`.
+    fn find_host(&self, name: &str) -> Option<(usize, usize)> {
+        self.block_order()
+            .into_iter()
+            .find(|&(file, block)| self.files[file].document.blocks[block].has_alias(name))
+    }
+
+    /// Runs `edit` on the first block for `name` and marks its file for
+    /// saving. Returns `None` if there is no such host.
+    fn edit_host<T>(&mut self, name: &str, edit: impl FnOnce(&mut BlockEdit) -> T) -> Option<T> {
+        let (index, block_index) = self.find_host(name)?;
+        let file = &mut self.files[index];
+        file.dirty = true;
+        Some(edit(&mut file.document.edit_block(block_index)))
+    }
+
+    /// Adds a new `Host name` block to velo's include file and fills it in
+    /// with `edit`. Returns the file it was written to.
+    fn append_host(
+        &mut self,
+        name: &str,
+        edit: impl FnOnce(&mut BlockEdit),
+    ) -> io::Result<PathBuf> {
+        if self.find_host(name).is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Connection '{}' already exists", name),
+            ));
+        }
+        let index = self.velo_file()?;
+        let file = &mut self.files[index];
+        let eol = file.document.eol().to_string();
+        file.document.append_block(Block::host(name, &eol));
+        let last = file.document.blocks.len() - 1;
+        edit(&mut file.document.edit_block(last));
+        file.dirty = true;
+        Ok(file.path.clone())
+    }
+
+    /// The config files read, the main one first.
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.files.iter().map(|file| file.path.as_path())
+    }
+
+    pub fn host_block(&self, name: &str) -> Option<&Block> {
+        self.find_host(name)
+            .map(|(file, block)| &self.files[file].document.blocks[block])
+    }
+
+    /// Adds a directive labelled `# velo:<kind> <tag>` to `name`'s block.
+    /// Returns false if the host does not exist.
+    pub fn push_tagged(
+        &mut self,
+        name: &str,
+        kind: &str,
+        tag: &str,
+        keyword: &str,
+        value: &str,
     ) -> io::Result<bool> {
-        let public_key = std::fs::read_to_string(public_key_path)?;
-        let public_key = public_key.trim();
+        self.modify(&format!("{} add {} {}", kind, name, tag), |config| {
+            Ok(config
+                .edit_host(name, |block| block.push_tagged(kind, tag, keyword, value))
+                .is_some())
+        })
+    }
 
-        let escaped_key = public_key.replace("'", "'\\''");
-        let remote_command = format!("grep -qF '{}' ~/.ssh/authorized_keys", escaped_key);
+    /// Sets the `# velo:<kind>` comment of `name`'s block, or removes it when
+    /// `tag` is `None`. Returns false if the host does not exist.
+    pub fn set_tag(&mut self, name: &str, kind: &str, tag: Option<&str>) -> io::Result<bool> {
+        self.modify(&format!("{} defaults {}", kind, name), |config| {
+            Ok(config
+                .edit_host(name, |block| block.set_tag(kind, tag))
+                .is_some())
+        })
+    }
 
-        let output = Command::new("ssh")
-            .arg(connection_name)
-            .arg(remote_command)
-            .output()?;
+    /// Removes a tagged directive from `name`'s block. Returns false if
+    /// there was nothing to remove.
+    pub fn remove_tagged(&mut self, name: &str, kind: &str, tag: &str) -> io::Result<bool> {
+        self.modify(&format!("{} remove {} {}", kind, name, tag), |config| {
+            let Some((index, block)) = config.find_host(name) else {
+                return Ok(false);
+            };
+            let file = &mut config.files[index];
+            if !file.document.blocks[block].remove_tagged(kind, tag) {
+                return Ok(false);
+            }
+            file.dirty = true;
+            Ok(true)
+        })
+    }
 
-        Ok(output.status.success())
+    /// The whole config as a single file for `ssh -F`, with each `Include`
+    /// pasted in place and every block line for which `skip` returns true
+    /// left out. The system-wide config is included at the end, since `-F`
+    /// would otherwise skip it.
+    pub fn flatten(&self, skip: &dyn Fn(&Block, usize) -> bool) -> String {
+        let mut out = String::new();
+        if !self.files.is_empty() {
+            self.flatten_file(0, None, skip, &mut Vec::new(), &mut out);
+        }
+        end_line(&mut out);
+        out.push_str("Match all\nInclude /etc/ssh/ssh_config\n");
+        out
     }
 
-    pub fn copy_id(&self, connection_name: &str, key_path: &Path) -> std::io::Result<()> {
-        if self.key_exists_on_remote(connection_name, &key_path.to_path_buf())? {
-            println!("The key already exists on the remote host.");
-            return Ok(());
+    fn flatten_file(
+        &self,
+        file: usize,
+        header: Option<&Directive>,
+        skip: &dyn Fn(&Block, usize) -> bool,
+        visiting: &mut Vec<usize>,
+        out: &mut String,
+    ) {
+        if visiting.contains(&file) || visiting.len() > MAX_INCLUDE_DEPTH {
+            return;
         }
+        visiting.push(file);
 
-        if cfg!(target_os = "windows") {
-            // Windows host approach
-            let output = Command::new("powershell")
-                .arg("-Command")
-                .arg(&format!(
-                    "type '{}' | ssh {} \"cat >> .ssh/authorized_keys\"",
-                    key_path.to_str().unwrap(),
-                    connection_name
-                ))
-                .output()?;
+        let document = &self.files[file].document;
+        for line in &document.global {
+            self.flatten_line(line, header, skip, visiting, out);
+        }
+        for block in &document.blocks {
+            end_line(out);
+            out.push_str(&block.header.to_string());
+            for (i, line) in block.lines.iter().enumerate() {
+                if !skip(block, i) {
+                    self.flatten_line(line, Some(&block.header), skip, visiting, out);
+                }
+            }
+        }
 
-            if output.status.success() {
-                println!("SSH key successfully copied to the remote server.");
-                Ok(())
-            } else {
-                let error = String::from_utf8_lossy(&output.stderr);
-                Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!("Failed to copy SSH key: {}", error),
-                ))
+        visiting.pop();
+    }
+
+    fn flatten_line(
+        &self,
+        line: &Line,
+        header: Option<&Directive>,
+        skip: &dyn Fn(&Block, usize) -> bool,
+        visiting: &mut Vec<usize>,
+        out: &mut String,
+    ) {
+        if include_patterns(line).is_empty() {
+            out.push_str(&line.to_string());
+            return;
+        }
+        for included in self.included_files(line) {
+            self.flatten_file(included, header, skip, visiting, out);
+            // ssh returns to the including block once the file ends.
+            end_line(out);
+            match header {
+                Some(header) => out.push_str(&header.to_string()),
+                None => out.push_str("Match all\n"),
             }
-        } else {
-            // Unix-like host approach (unchanged)
-            let output = Command::new("ssh-copy-id")
-                .arg("-i")
-                .arg(key_path)
-                .arg(connection_name)
-                .output()?;
-
-            if output.status.success() {
-                println!("SSH key successfully copied to the remote server.");
-                Ok(())
-            } else {
-                let error = String::from_utf8_lossy(&output.stderr);
-                Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!("Failed to copy SSH key: {}", error),
-                ))
+        }
+    }
+
+    /// The first block for `name` with the file and line its header is on.
+    pub fn host_location(&self, name: &str) -> Option<(&Path, usize, &Block)> {
+        let mut location = None;
+        self.walk(&mut |event| {
+            if let ConfigEvent::Block {
+                path, line, block, ..
+            } = event
+            {
+                if location.is_none() && block.has_alias(name) {
+                    location = Some((path, line, block));
+                }
             }
+        });
+        location
+    }
+
+    /// Index of velo's own include file, creating it and adding an `Include`
+    /// for it to the main config if needed.
+    fn velo_file(&mut self) -> io::Result<usize> {
+        let path = ssh_dir().join(VELO_INCLUDE);
+        if self.file_index(&path).is_none() {
+            self.load_file(&path, 1)?;
+        }
+        let index = self
+            .file_index(&path)
+            .expect("velo include file was just loaded");
+
+        if !self.is_included(index) {
+            let main = &mut self.files[0];
+            main.document.prepend_global("Include", VELO_INCLUDE);
+            main.dirty = true;
         }
+        Ok(index)
+    }
+
+    /// Installs the public half of `key_path` on `connection`, printing
+    /// whether it was added or already there.
+    pub fn copy_id(&self, connection_name: &str, key_path: &Path) -> std::io::Result<()> {
+        let key = authorized_key_line(key_path)?;
+        match install_public_key(connection_name, &key, false)? {
+            KeyInstall::Added => println!("SSH key successfully copied to '{}'.", connection_name),
+            KeyInstall::Present => {
+                println!("The key is already installed on '{}'.", connection_name)
+            }
+        }
+        Ok(())
     }
 
     pub fn add_connection(
@@ -106,58 +494,159 @@ impl SSHConfig {
         host: &str,
         user: &str,
         port: u16,
+        jump: Option<&str>,
     ) -> std::io::Result<()> {
-        let entry = format!(
-            "\nHost {}\n    HostName {}\n    User {}\n    Port {}\n",
-            name, host, user, port
-        );
-        self.content.push_str(&entry);
-        self.save()?;
-        println!("Connection '{}' added successfully.", name);
+        let path = self.modify(&format!("add {}", name), |config| {
+            config.append_host(name, |block| {
+                block.push("HostName", host);
+                block.push("User", user);
+                block.push("Port", &port.to_string());
+                if let Some(jump) = jump {
+                    block.push("ProxyJump", jump);
+                }
+            })
+        })?;
+        println!("Connection '{}' added to {}.", name, path.display());
         Ok(())
     }
 
+    /// Removes `name`'s block and forgets its stored secrets. Returns false
+    /// if the host does not exist.
     pub fn remove_connection(&mut self, name: &str) -> std::io::Result<bool> {
-        let host_pattern = format!(r"(?m)^Host\s+{}\s*$", regex::escape(name));
-        let re = Regex::new(&host_pattern).unwrap();
-
-        if re.is_match(&self.content) {
-            let mut new_content = String::new();
-            let mut skip_block = false;
-            let mut removed = false;
-
-            for line in self.content.lines() {
-                if re.is_match(line) {
-                    skip_block = true;
-                    removed = true;
-                    continue;
+        self.modify_then(
+            &format!("remove {}", name),
+            |config| {
+                let Some((index, _)) = config.find_host(name) else {
+                    return Ok(false);
+                };
+                let file = &mut config.files[index];
+                file.document.remove_host(name);
+                file.dirty = true;
+                Ok(true)
+            },
+            |removed| {
+                if !removed {
+                    return Ok(());
                 }
-                if skip_block {
-                    if line.trim().starts_with("Host ") {
-                        skip_block = false;
-                    } else {
+                forget_connection_secrets(name).map_err(|e| {
+                    io::Error::new(
+                        e.kind(),
+                        format!(
+                            "'{}' was removed, but its stored secrets were not: {}",
+                            name, e
+                        ),
+                    )
+                })
+            },
+        )
+    }
+
+    /// Changes only the given directives in `name`'s block; everything else
+    /// in it is left as written. Returns false if the host does not exist.
+    pub fn edit_connection(
+        &mut self,
+        name: &str,
+        set: &[(String, String)],
+        unset: &[String],
+    ) -> std::io::Result<bool> {
+        self.modify(&format!("edit {}", name), |config| {
+            let edited = config.edit_host(name, |block| {
+                for keyword in unset {
+                    block.unset(keyword);
+                }
+                for (keyword, value) in set {
+                    block.set(keyword, value);
+                }
+            });
+            Ok(edited.is_some())
+        })
+    }
+
+    /// Renames a connection, points `ProxyJump` lines that used the old
+    /// name at the new one and moves its stored secrets. Returns false if
+    /// `old` does not exist.
+    pub fn rename_connection(&mut self, old: &str, new: &str) -> std::io::Result<bool> {
+        let rename = |config: &mut Self| {
+            if config.has_connection(new) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("Connection '{}' already exists", new),
+                ));
+            }
+            if config
+                .edit_host(old, |block| block.rename_alias(old, new))
+                .is_none()
+            {
+                return Ok(false);
+            }
+
+            for file in config.files.iter_mut() {
+                for directive in file.document.lines_mut().filter_map(Line::as_directive_mut) {
+                    if !directive.is("ProxyJump") {
                         continue;
                     }
+                    if let Some(value) = rename_jump_host(directive.value(), old, new) {
+                        directive.set_value(&value);
+                        file.dirty = true;
+                    }
                 }
-                new_content.push_str(line);
-                new_content.push('\n');
             }
-
-            if removed {
-                self.content = new_content;
-                self.save()?;
+            Ok(true)
+        };
+        self.modify_then(&format!("rename {} {}", old, new), rename, |renamed| {
+            if !renamed {
+                return Ok(());
             }
-            Ok(removed)
-        } else {
-            Ok(false)
-        }
+            move_connection_secrets(old, new).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!(
+                        "'{}' was renamed, but its stored secrets were not moved: {}",
+                        old, e
+                    ),
+                )
+            })
+        })
+    }
+
+    /// Adds `target` with a copy of every line in `source`'s block, optionally
+    /// pointing it at a different HostName. Returns false if `source` does not
+    /// exist.
+    pub fn clone_connection(
+        &mut self,
+        source: &str,
+        target: &str,
+        host: Option<&str>,
+    ) -> std::io::Result<bool> {
+        self.modify(&format!("clone {} {}", source, target), |config| {
+            let Some(source_block) = config.host_block(source).cloned() else {
+                return Ok(false);
+            };
+
+            config.append_host(target, |block| {
+                block.copy_from(&source_block);
+                if let Some(host) = host {
+                    block.set("HostName", host);
+                }
+            })?;
+            Ok(true)
+        })
+    }
+
+    pub fn has_connection(&self, name: &str) -> bool {
+        self.list_connections().iter().any(|c| c == name)
     }
 
     pub fn list_connections(&self) -> Vec<String> {
-        let re = Regex::new(r"(?m)^Host (.+)$").unwrap();
-        re.captures_iter(&self.content)
-            .map(|cap| cap[1].to_string())
-            .collect()
+        let mut names: Vec<String> = Vec::new();
+        for (file, block) in self.block_order() {
+            for alias in self.files[file].document.blocks[block].aliases() {
+                if !names.contains(&alias) {
+                    names.push(alias);
+                }
+            }
+        }
+        names
     }
 
     pub fn add_key(&mut self) -> io::Result<(PathBuf, String)> {
@@ -221,8 +710,11 @@ impl SSHConfig {
         let private_key_path = public_key_path.with_extension("");
 
         // Check and fix permissions
-        self.fix_key_permissions(&private_key_path)?;
-        self.fix_key_permissions(&public_key_path)?;
+        self.fix_key_permissions(&private_key_path, PathKind::PrivateKey)?;
+        self.fix_key_permissions(&public_key_path, PathKind::PublicKey)?;
+
+        // Offer to keep the passphrase so ssh-add can unlock the key later
+        offer_to_store_passphrase(&private_key_path)?;
 
         // Add private key to ssh-agent
         self.add_key_to_agent(&private_key_path)?;
@@ -240,14 +732,7 @@ impl SSHConfig {
     }
 
     fn add_key_to_agent(&self, key_path: &Path) -> std::io::Result<()> {
-        let output = if cfg!(target_os = "macos") {
-            Command::new("ssh-add")
-                .arg("--apple-use-keychain")
-                .arg(key_path)
-                .output()?
-        } else {
-            Command::new("ssh-add").arg(key_path).output()?
-        };
+        let output = ssh_add(key_path, &[])?;
 
         if output.status.success() {
             println!("SSH key added to ssh-agent successfully.");
@@ -261,109 +746,245 @@ impl SSHConfig {
         }
     }
 
-    fn fix_key_permissions(&self, key_path: &Path) -> io::Result<()> {
-        let mut perms = fs::metadata(key_path)?.permissions();
-
-        if cfg!(unix) {
-            perms.set_readonly(true); // Read/write for owner only
-        } else {
-            perms.set_readonly(false); // Make sure the file is writable
+    fn fix_key_permissions(&self, key_path: &Path, kind: PathKind) -> io::Result<()> {
+        if fix_permissions(key_path, kind)? {
+            println!(
+                "Fixed permissions on {} ({:04o})",
+                key_path.display(),
+                kind.mode()
+            );
         }
-
-        fs::set_permissions(key_path, perms)?;
-        println!("Fixed permissions on {}", key_path.display());
-
         Ok(())
     }
 
-    fn update_config_with_key(
+    pub fn update_config_with_key(
         &mut self,
         connection_name: &str,
         key_path: &Path,
     ) -> std::io::Result<()> {
-        let host_pattern = format!(r"(?m)^Host\s+{}\s*$", regex::escape(connection_name));
-        let re = Regex::new(&host_pattern).unwrap();
-
-        let key_path_str = key_path.to_str().unwrap();
-        let new_identity_line = format!("    IdentityFile {}", key_path_str);
-
-        if re.is_match(&self.content) {
-            let mut new_content = String::new();
-            let mut in_host_block = false;
-            let mut identity_added = false;
-            let mut add_keys_to_agent_added = false;
-
-            for line in self.content.lines() {
-                if re.is_match(line) {
-                    in_host_block = true;
-                    new_content.push_str(line);
-                    new_content.push('\n');
-                    continue;
-                }
+        let key_path_str = key_path.to_str().ok_or_else(|| {
+            invalid_input(format!(
+                "Key path {} is not valid UTF-8 and cannot go in the ssh config",
+                key_path.display()
+            ))
+        })?;
+        let identity_file = quote_arg(key_path_str);
+
+        self.modify(&format!("add-key {}", connection_name), |config| {
+            let updated = config.edit_host(connection_name, |block| {
+                block.set("IdentityFile", &identity_file);
+                block.set("AddKeysToAgent", "yes");
+            });
+            if updated.is_none() {
+                config.append_host(connection_name, |block| {
+                    block.push("IdentityFile", &identity_file);
+                    block.push("AddKeysToAgent", "yes");
+                })?;
+            }
+            Ok(())
+        })?;
+        println!(
+            "SSH config updated for connection '{}'. Added key: {}",
+            connection_name, key_path_str
+        );
+        Ok(())
+    }
 
-                if in_host_block {
-                    if line.trim().starts_with("IdentityFile") {
-                        if !identity_added {
-                            new_content.push_str(&new_identity_line);
-                            new_content.push('\n');
-                            identity_added = true;
-                        }
-                    } else if line.trim().starts_with("AddKeysToAgent") {
-                        new_content.push_str("    AddKeysToAgent yes\n");
-                        add_keys_to_agent_added = true;
-                    } else if line.trim().is_empty() || line.trim().starts_with("Host ") {
-                        if !identity_added {
-                            new_content.push_str(&new_identity_line);
-                            new_content.push('\n');
-                        }
-                        if !add_keys_to_agent_added {
-                            new_content.push_str("    AddKeysToAgent yes\n");
-                        }
-                        in_host_block = false;
-                    } else {
-                        new_content.push_str(line);
-                        new_content.push('\n');
-                    }
-                } else {
-                    new_content.push_str(line);
-                    new_content.push('\n');
-                }
+    /// Runs `edit` and saves what it changed while holding velo's config
+    /// lock, journalling it under `command` for `velo undo`. If any file was
+    /// changed on disk since it was read, the config is reloaded and `edit`
+    /// runs again on the fresh contents; if the files keep changing, nothing
+    /// is written and a conflict error is returned.
+    fn modify<T>(
+        &mut self,
+        command: &str,
+        edit: impl FnMut(&mut Self) -> io::Result<T>,
+    ) -> io::Result<T> {
+        self.modify_then(command, edit, |_| Ok(()))
+    }
+
+    /// Like `modify`, and once the edit is saved runs `then` on its result
+    /// before the lock is let go.
+    fn modify_then<T>(
+        &mut self,
+        command: &str,
+        mut edit: impl FnMut(&mut Self) -> io::Result<T>,
+        then: impl FnOnce(&T) -> io::Result<()>,
+    ) -> io::Result<T> {
+        let mut lock = config_lock()?;
+        if lock.try_write().is_err() {
+            eprintln!("Waiting for another velo process to finish editing the ssh config...");
+        }
+        let _guard = lock.write()?;
+
+        let result = self.edit_fresh(&mut edit)?;
+        self.save(command)?;
+        then(&result)?;
+        Ok(result)
+    }
+
+    /// Runs `edit`, reloading the config and running it again whenever a
+    /// file changed on disk in the meantime, until it applies to what is on
+    /// disk. Gives up after `MAX_EDIT_ATTEMPTS`.
+    fn edit_fresh<T>(
+        &mut self,
+        edit: &mut impl FnMut(&mut Self) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let mut changed = self.changed_on_disk()?;
+        for _ in 0..MAX_EDIT_ATTEMPTS {
+            if changed.is_some() {
+                // The first file is the one this config was loaded from,
+                // which need not be ~/.ssh/config.
+                let root = self.files[0].path.clone();
+                *self = SSHConfig::load(&root)?;
+            }
+            let result = edit(self)?;
+            changed = self.changed_on_disk()?;
+            if changed.is_none() {
+                return Ok(result);
             }
+        }
 
-            if in_host_block {
-                if !identity_added {
-                    new_content.push_str(&new_identity_line);
-                    new_content.push('\n');
-                }
-                if !add_keys_to_agent_added {
-                    new_content.push_str("    AddKeysToAgent yes\n");
-                }
+        let path = changed.unwrap_or_default();
+        Err(io::Error::other(format!(
+            "{} keeps changing on disk; nothing was saved. Try again once it is no longer being edited.",
+            path.display()
+        )))
+    }
+
+    /// The first loaded file whose contents differ from when it was read.
+    fn changed_on_disk(&self) -> io::Result<Option<PathBuf>> {
+        for file in &self.files {
+            let unchanged = match &file.stamp {
+                Some(stamp) => stamp.matches(&file.path)?,
+                None => !file.path.exists(),
+            };
+            if !unchanged {
+                return Ok(Some(file.path.clone()));
             }
+        }
+        Ok(None)
+    }
 
-            self.content = new_content;
-        } else {
-            self.content.push_str(&format!(
-                "\nHost {}\n{}\n    AddKeysToAgent yes\n",
-                connection_name, new_identity_line
-            ));
+    fn save(&mut self, command: &str) -> std::io::Result<()> {
+        let dirty: Vec<&Path> = self
+            .files
+            .iter()
+            .filter(|file| file.dirty)
+            .map(|file| file.path.as_path())
+            .collect();
+        if dirty.is_empty() {
+            return Ok(());
         }
+        backup_files(&dirty)?;
 
-        self.save()?;
-        println!(
-            "SSH config updated for connection '{}'. Added key: {}",
-            connection_name, key_path_str
-        );
-        Ok(())
+        let mut changes = Vec::new();
+        for file in self.files.iter_mut().filter(|file| file.dirty) {
+            if let Some(parent) = file.path.parent() {
+                create_private_dir(parent)?;
+            }
+            let before = fs::read_to_string(&file.path).ok();
+            let after = file.document.to_string();
+            atomic_write(&file.path, after.as_bytes())?;
+            file.stamp = Stamp::read(&file.path)?;
+            file.dirty = false;
+            changes.push(FileChange {
+                path: file.path.clone(),
+                before,
+                after,
+            });
+        }
+        journal::record(command, changes)
     }
+}
 
-    fn save(&self) -> std::io::Result<()> {
-        let mut file = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .create(true)
-            .open(&self.path)?;
-        file.write_all(self.content.as_bytes())?;
-        Ok(())
+pub fn ssh_dir() -> PathBuf {
+    dirs::home_dir()
+        .expect("Unable to determine home directory")
+        .join(".ssh")
+}
+
+/// Where velo keeps its own state, created on first use.
+pub fn velo_data_dir() -> io::Result<PathBuf> {
+    let dir = dirs::data_local_dir()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No data directory"))?
+        .join("velo");
+    create_private_dir(&dir)?;
+    Ok(dir)
+}
+
+/// Where velo keeps files that only matter while the user is logged in,
+/// such as its ssh-agent's socket. Falls back to the data directory where
+/// there is no runtime directory, as on macOS.
+pub fn velo_runtime_dir() -> io::Result<PathBuf> {
+    let Some(dir) = dirs::runtime_dir() else {
+        return velo_data_dir();
+    };
+    let dir = dir.join("velo");
+    create_private_dir(&dir)?;
+    Ok(dir)
+}
+
+/// The advisory lock every read-modify-write of the ssh config holds, so
+/// two velo processes (say the TUI and a script) cannot interleave edits.
+pub fn config_lock() -> io::Result<fd_lock::RwLock<File>> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(velo_data_dir()?.join("config.lock"))?;
+    Ok(fd_lock::RwLock::new(file))
+}
+
+fn end_line(text: &mut String) {
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
+
+pub fn create_private_dir(path: &Path) -> io::Result<()> {
+    if path.exists() {
+        return Ok(());
+    }
+    fs::create_dir_all(path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
+fn include_patterns(line: &Line) -> Vec<String> {
+    match line.as_directive() {
+        Some(directive) if directive.is("Include") => directive.args(),
+        _ => Vec::new(),
+    }
+}
+
+/// Resolves an `Include` argument the way ssh does for a user config:
+/// `~` and `${VAR}` are expanded and relative paths live under `~/.ssh`.
+fn expand_include_path(pattern: &str) -> PathBuf {
+    let mut expanded = String::new();
+    let mut rest = pattern;
+    while let Some(start) = rest.find("${") {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&std::env::var(&rest[start + 2..start + len]).unwrap_or_default());
+        rest = &rest[start + len + 1..];
+    }
+    expanded.push_str(rest);
+
+    if expanded == "~" {
+        dirs::home_dir().unwrap_or_default()
+    } else if let Some(relative) = expanded.strip_prefix("~/") {
+        dirs::home_dir().unwrap_or_default().join(relative)
+    } else if Path::new(&expanded).is_absolute() {
+        PathBuf::from(expanded)
+    } else {
+        ssh_dir().join(expanded)
     }
 }
 
@@ -390,85 +1011,531 @@ pub fn handle_add_key() -> io::Result<()> {
     }
 }
 
+/// How installing a public key on a host went.
+pub enum KeyInstall {
+    Added,
+    Present,
+}
+
+/// The `type base64 comment` line for the public half of `key_path`, given
+/// either the private or the public key.
+pub fn authorized_key_line(key_path: &Path) -> io::Result<String> {
+    let path = public_key_path(key_path);
+    let public = ssh_key::PublicKey::read_openssh_file(&path).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Cannot read {}: {}", path.display(), e),
+        )
+    })?;
+    public.to_openssh().map_err(io::Error::other)
+}
+
+// Scripts that change the remote authorized_keys in one session. The key's
+// base64 blob and its full line arrive on stdin, so no part of the key is
+// ever parsed by a shell; the scripts have no single quotes to escape.
+
+// Prints `present` or `added`.
+const INSTALL_KEY_SCRIPT: &str = r#"sh -c 'umask 077
+read -r blob && read -r key || exit 1
+dir="$HOME/.ssh"
+file="$dir/authorized_keys"
+mkdir -p "$dir" && chmod 700 "$dir" && touch "$file" && chmod 600 "$file" || exit 1
+if grep -qF -- "$blob" "$file"; then echo present; exit 0; fi
+if [ -s "$file" ] && [ -n "$(tail -c 1 "$file")" ]; then echo >> "$file"; fi
+printf "%s\n" "$key" >> "$file" && echo added'"#;
+
+// Prints `absent` or `removed`. The file is rewritten in place so it keeps
+// its owner and mode, and only once the copy without the key is complete:
+// grep exits with 1 when no line is left and 2 on errors, and a redirection
+// that fails also gives 1, hence the check that the copy exists.
+const REMOVE_KEY_SCRIPT: &str = r#"sh -c 'umask 077
+read -r blob && read -r key || exit 1
+file="$HOME/.ssh/authorized_keys"
+if [ ! -f "$file" ] || ! grep -qF -- "$blob" "$file"; then echo absent; exit 0; fi
+tmp="$file.velo.$$"
+grep -vF -- "$blob" "$file" > "$tmp"
+rc=$?
+if [ $rc -gt 1 ] || [ ! -f "$tmp" ]; then
+  rm -f "$tmp"; echo "could not copy $file; it was not changed" >&2; exit 1
+fi
+cat "$tmp" > "$file" || { echo "could not rewrite $file; the other keys are in $tmp" >&2; exit 1; }
+rm -f "$tmp"
+echo removed'"#;
+
+/// Runs one of the key scripts on `connection` for `key` and returns what
+/// it printed. With `batch` set, ssh fails instead of asking for a password
+/// that velo does not have stored.
+fn run_key_script(connection: &str, script: &str, key: &str, batch: bool) -> io::Result<String> {
+    let blob = key.split_whitespace().nth(1).unwrap_or_default();
+    let login = PasswordLogin::for_connection(connection)?;
+    let mut ssh_args = Vec::new();
+    if batch && matches!(login, PasswordLogin::None) {
+        ssh_args.extend(["-o".to_string(), "BatchMode=yes".to_string()]);
+    }
+    ssh_args.extend([connection.to_string(), script.to_string()]);
+
+    let mut child = login
+        .command(connection, &ssh_args)?
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // ssh exits without reading it when it cannot connect; its stderr
+        // says why.
+        if let Err(e) = writeln!(stdin, "{}\n{}", blob, key) {
+            if e.kind() != io::ErrorKind::BrokenPipe {
+                return Err(e);
+            }
+        }
+    }
+    let output = child.wait_with_output()?;
+
+    let printed = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if output.status.success() {
+        if printed.is_empty() {
+            return Err(io::Error::other(
+                "no reply from the remote script; authorized_keys may not have changed",
+            ));
+        }
+        return Ok(printed);
+    }
+    let error = String::from_utf8_lossy(&output.stderr);
+    let reason = error
+        .lines()
+        .rev()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or("ssh failed");
+    Err(io::Error::other(reason.trim().to_string()))
+}
+
+/// Appends `key` to `connection`'s authorized_keys unless it is already
+/// there.
+pub fn install_public_key(connection: &str, key: &str, batch: bool) -> io::Result<KeyInstall> {
+    match run_key_script(connection, INSTALL_KEY_SCRIPT, key, batch)?.as_str() {
+        "added" => Ok(KeyInstall::Added),
+        "present" => Ok(KeyInstall::Present),
+        other => Err(io::Error::other(format!("Unexpected reply: {}", other))),
+    }
+}
+
+/// Takes `key` out of `connection`'s authorized_keys. Returns false if it
+/// was not there.
+pub fn remove_public_key(connection: &str, key: &str, batch: bool) -> io::Result<bool> {
+    match run_key_script(connection, REMOVE_KEY_SCRIPT, key, batch)?.as_str() {
+        "removed" => Ok(true),
+        "absent" => Ok(false),
+        other => Err(io::Error::other(format!("Unexpected reply: {}", other))),
+    }
+}
+
+// How many hosts a group operation talks to at once.
+const MAX_PARALLEL_HOSTS: usize = 8;
+
+/// Runs `task` for every connection, several at a time, and returns the
+/// results in the same order.
+pub fn for_each_connection<T: Send>(
+    connections: &[String],
+    task: impl Fn(&str) -> io::Result<T> + Sync,
+) -> Vec<io::Result<T>> {
+    let mut results = Vec::new();
+    for batch in connections.chunks(MAX_PARALLEL_HOSTS) {
+        thread::scope(|scope| {
+            let handles: Vec<_> = batch
+                .iter()
+                .map(|connection| scope.spawn(|| task(connection)))
+                .collect();
+            for handle in handles {
+                results.push(
+                    handle
+                        .join()
+                        .unwrap_or_else(|_| Err(io::Error::other("worker thread panicked"))),
+                );
+            }
+        });
+    }
+    results
+}
+
+/// The connections `target` picks: a connection name, or a comma-separated
+/// list of ssh patterns such as `web-*,db1,!web-old`.
+pub fn select_connections(config: &SSHConfig, target: &str) -> Vec<String> {
+    if is_literal_pattern(target) && !target.contains(',') {
+        return vec![target.to_string()];
+    }
+    config
+        .list_connections()
+        .into_iter()
+        .filter(|name| match_pattern_list(name, target) == Some(true))
+        .collect()
+}
+
+/// Installs the key on every connection in `connections` in parallel. Hosts
+/// without a stored password must accept an existing key or agent, since
+/// concurrent sessions cannot share the terminal for password prompts.
+fn copy_id_to_group(connections: &[String], key_path: &Path) -> io::Result<()> {
+    let key = authorized_key_line(key_path)?;
+    println!(
+        "Copying {} to {} connections...",
+        public_key_path(key_path).display(),
+        connections.len()
+    );
+
+    let results = for_each_connection(connections, |connection| {
+        install_public_key(connection, &key, true)
+    });
+    let mut failed = 0;
+    for (connection, result) in connections.iter().zip(results) {
+        match result {
+            Ok(KeyInstall::Added) => println!("  {}: added", connection),
+            Ok(KeyInstall::Present) => println!("  {}: already installed", connection),
+            Err(e) => {
+                failed += 1;
+                println!("  {}: {}", connection, e);
+            }
+        }
+    }
+
+    if failed > 0 {
+        println!(
+            "{} of {} connections failed. Hosts that need a password can be done one at a time with 'velo copy-id <connection> <key_path>'.",
+            failed,
+            connections.len()
+        );
+    }
+    Ok(())
+}
+
 pub fn handle_copy_id(args: &[String]) -> std::io::Result<()> {
     if args.len() != 2 {
-        println!("Usage: velo copy-id <connection_name> <key_path>");
+        println!("Usage: velo copy-id <connection|pattern> <key_path>");
         return Ok(());
     }
 
-    let connection_name = &args[0];
+    let target = &args[0];
     let key_path = PathBuf::from(&args[1]);
-
     let ssh_config = SSHConfig::new()?;
-    ssh_config.copy_id(connection_name, &key_path)?;
 
+    let connections = select_connections(&ssh_config, target);
+    match connections.as_slice() {
+        [] => println!("No connection matches '{}'", target),
+        [connection] => ssh_config.copy_id(connection, &key_path)?,
+        _ => copy_id_to_group(&connections, &key_path)?,
+    }
     Ok(())
 }
 
 pub fn ensure_ssh_agent_running() -> std::io::Result<()> {
-    let output = Command::new("ssh-add").arg("-l").output()?;
+    ensure_agent()?;
+    load_stored_keys()
+}
 
-    if !output.status.success() {
-        println!("Starting ssh-agent...");
-        let output = Command::new("ssh-agent").arg("-s").output()?;
+pub fn handle_add_connection(args: &[String]) -> std::io::Result<()> {
+    let store_password = args.iter().any(|arg| arg == "--password");
+    let args: Vec<String> = args.iter().filter(|arg| *arg != "--password").cloned().collect();
+    let (jump, args) = split_option(&args, "--jump").map_err(invalid_input)?;
+    if args.len() < 3 {
+        println!(
+            "Usage: velo add <name> <host> <user> [port] [--jump <conn>[,<conn>...]] [--password]"
+        );
+        return Ok(());
+    }
 
-        if output.status.success() {
-            let agent_output = String::from_utf8_lossy(&output.stdout);
-            for line in agent_output.lines() {
-                if line.starts_with("SSH_AUTH_SOCK=") || line.starts_with("SSH_AGENT_PID=") {
-                    let parts: Vec<&str> = line.splitn(2, '=').collect();
-                    if parts.len() == 2 {
-                        std::env::set_var(
-                            parts[0],
-                            parts[1].trim_matches(|c| c == ';' || c == '"'),
-                        );
+    let name = args[0];
+    let host = args[1].trim();
+    let user = args[2].trim();
+    if !is_connection_name(name) {
+        return Err(invalid_input(format!(
+            "Invalid connection name: '{}'",
+            name
+        )));
+    }
+    if host.is_empty() || user.is_empty() {
+        return Err(invalid_input("A connection needs a host and a user"));
+    }
+    let port = match args.get(3).map(|port| port.trim()) {
+        None | Some("") => 22,
+        Some(port) => parse_port(port)?,
+    };
+
+    let mut ssh_config = SSHConfig::new()?;
+
+    if ssh_config.has_connection(name) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("Connection '{}' already exists", name),
+        ));
+    }
+    if let Some(jump) = jump {
+        check_jump_hosts(&ssh_config, name, jump).map_err(invalid_input)?;
+    }
+
+    ssh_config.add_connection(name, host, user, port, jump)?;
+
+    println!("Connection '{}' added successfully.", name);
+    if store_password {
+        store_secret(name, SecretKind::Password)?;
+    }
+    println!("To add an SSH key to this connection, use: velo add-key");
+
+    Ok(())
+}
+
+pub fn handle_edit_connection(args: &[String]) -> std::io::Result<()> {
+    if args.len() < 3 {
+        println!("Usage: velo edit <name> [--host H] [--user U] [--port P] [--jump J] [--set Key=Value] [--unset Key]");
+        return Ok(());
+    }
+
+    let name = &args[0];
+    let mut set: Vec<(String, String)> = Vec::new();
+    let mut unset: Vec<String> = Vec::new();
+
+    let mut options = args[1..].iter();
+    while let Some(option) = options.next() {
+        let Some(value) = options.next() else {
+            return Err(invalid_input(format!("Missing value for {}", option)));
+        };
+        match option.as_str() {
+            "--host" => set.push(("HostName".to_string(), value.clone())),
+            "--user" => set.push(("User".to_string(), value.clone())),
+            "--jump" => set.push(("ProxyJump".to_string(), value.clone())),
+            "--port" => {
+                parse_port(value)?;
+                set.push(("Port".to_string(), value.clone()));
+            }
+            "--set" => match value.split_once('=') {
+                Some((key, value)) if is_editable_keyword(key.trim()) => {
+                    let (key, value) = (key.trim(), value.trim());
+                    if key.eq_ignore_ascii_case("Port") {
+                        parse_port(value)?;
                     }
+                    set.push((key.to_string(), value.to_string()))
+                }
+                _ => {
+                    return Err(invalid_input(format!(
+                        "Expected Key=Value after --set, got '{}'",
+                        value
+                    )))
+                }
+            },
+            "--unset" => {
+                if !is_editable_keyword(value) {
+                    return Err(invalid_input(format!("Cannot unset '{}'", value)));
                 }
+                unset.push(value.clone());
             }
-            println!("ssh-agent started successfully.");
-        } else {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Failed to start ssh-agent",
-            ));
+            _ => return Err(invalid_input(format!("Unknown option: {}", option))),
         }
-    } else {
-        println!("ssh-agent is already running.");
     }
 
+    let mut ssh_config = SSHConfig::new()?;
+    for (keyword, value) in &set {
+        if keyword.eq_ignore_ascii_case("ProxyJump") {
+            check_jump_hosts(&ssh_config, name, value).map_err(invalid_input)?;
+        }
+    }
+    if !ssh_config.edit_connection(name, &set, &unset)? {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Connection '{}' not found", name),
+        ));
+    }
+    println!("Connection '{}' updated.", name);
     Ok(())
 }
 
-pub fn handle_add_connection(args: &[String]) -> std::io::Result<()> {
-    if args.len() < 3 {
-        println!("Usage: velo add <name> <host> <user> [port]");
+fn invalid_input(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message.into())
+}
+
+fn parse_port(value: &str) -> io::Result<u16> {
+    value
+        .parse()
+        .map_err(|_| invalid_input(format!("Invalid port number: {}", value)))
+}
+
+// Keywords that would restructure the file rather than change a setting.
+fn is_editable_keyword(keyword: &str) -> bool {
+    !keyword.is_empty()
+        && keyword.chars().all(|c| c.is_ascii_alphanumeric())
+        && !["host", "match", "include"].contains(&keyword.to_ascii_lowercase().as_str())
+}
+
+pub fn handle_rename_connection(args: &[String]) -> std::io::Result<()> {
+    if args.len() != 2 {
+        println!("Usage: velo rename <old> <new>");
         return Ok(());
     }
 
-    let name = &args[0];
-    let host = &args[1];
-    let user = &args[2];
-    let port = if args.len() > 3 {
-        args[3].parse().unwrap_or(22)
-    } else {
-        22
-    };
+    let (old, new) = (&args[0], &args[1]);
+    if !is_connection_name(new) {
+        return Err(invalid_input(format!("Invalid connection name: '{}'", new)));
+    }
 
     let mut ssh_config = SSHConfig::new()?;
+    if ssh_config.has_connection(new) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("Connection '{}' already exists", new),
+        ));
+    }
+
+    if !ssh_config.rename_connection(old, new)? {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Connection '{}' not found", old),
+        ));
+    }
+    println!("Connection '{}' renamed to '{}'.", old, new);
+
+    // Sessions started by `velo ssh` are named after the connection.
+    let old_session = format!("ssh-{}", old);
+    if let Ok(sessions) = session_names() {
+        if sessions.contains(&old_session) {
+            let new_session = format!("ssh-{}", new);
+            match rename_session(&old_session, &new_session) {
+                Ok(_) => println!("Renamed Zellij session to: {}", new_session),
+                Err(e) => eprintln!("Could not rename Zellij session {}: {}", old_session, e),
+            }
+        }
+    }
+    Ok(())
+}
 
-    if ssh_config.list_connections().contains(&name.to_string()) {
-        println!("Connection '{}' already exists.", name);
+pub fn handle_clone_connection(args: &[String]) -> std::io::Result<()> {
+    let (host, names) = split_option(args, "--host").map_err(invalid_input)?;
+    if names.len() != 2 {
+        println!("Usage: velo clone <src> <dst> [--host H]");
         return Ok(());
     }
 
-    ssh_config.add_connection(name, host, user, port)?;
+    let (source, target) = (names[0], names[1]);
+    if !is_connection_name(target) {
+        return Err(invalid_input(format!(
+            "Invalid connection name: '{}'",
+            target
+        )));
+    }
+    let host = host.map(str::trim);
+    if host == Some("") {
+        return Err(invalid_input("--host needs a host name"));
+    }
 
-    println!("Connection '{}' added successfully.", name);
-    println!("To add an SSH key to this connection, use: velo add-key");
+    let mut ssh_config = SSHConfig::new()?;
+    if ssh_config.has_connection(target) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("Connection '{}' already exists", target),
+        ));
+    }
 
+    if !ssh_config.clone_connection(source, target, host)? {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Connection '{}' not found", source),
+        ));
+    }
+    println!("Connection '{}' cloned to '{}'.", source, target);
     Ok(())
 }
 
+/// Pulls `flag <value>` out of `args`, wherever it appears, and returns its
+/// value along with the remaining arguments.
+fn split_option<'a>(
+    args: &'a [String],
+    flag: &str,
+) -> Result<(Option<&'a str>, Vec<&'a String>), String> {
+    let mut value = None;
+    let mut rest = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg != flag {
+            rest.push(arg);
+            continue;
+        }
+        match args.next() {
+            Some(next) => value = Some(next.as_str()),
+            None => return Err(format!("Missing value for {}", flag)),
+        }
+    }
+    Ok((value, rest))
+}
+
+// A name usable as a single literal Host pattern.
+fn is_connection_name(name: &str) -> bool {
+    is_literal_pattern(name) && !name.contains(|c: char| c.is_whitespace() || c == '"')
+}
+
+/// Rewrites a ProxyJump list (`[user@]host[:port],...`) so hops through
+/// `old` go through `new`. Returns `None` when nothing refers to `old`.
+fn rename_jump_host(value: &str, old: &str, new: &str) -> Option<String> {
+    let mut changed = false;
+    let hops: Vec<String> = value
+        .split(',')
+        .map(|hop| {
+            let (scheme, user, host, port) = parse_jump_hop(hop);
+            if host != old {
+                return hop.to_string();
+            }
+            changed = true;
+            let mut renamed = scheme.to_string();
+            if let Some(user) = user {
+                renamed.push_str(user);
+                renamed.push('@');
+            }
+            renamed.push_str(new);
+            if let Some(port) = port {
+                renamed.push(':');
+                renamed.push_str(port);
+            }
+            renamed
+        })
+        .collect();
+
+    changed.then(|| hops.join(","))
+}
+
+/// Splits one ProxyJump hop, `[ssh://][user@]host[:port]`, into scheme,
+/// user, host and port.
+fn parse_jump_hop(hop: &str) -> (&str, Option<&str>, &str, Option<&str>) {
+    let (scheme, rest) = match hop.strip_prefix("ssh://") {
+        Some(rest) => ("ssh://", rest),
+        None => ("", hop),
+    };
+    let (user, host_port) = match rest.rsplit_once('@') {
+        Some((user, host_port)) => (Some(user), host_port),
+        None => (None, rest),
+    };
+    let (host, port) = split_host_port(host_port);
+    (scheme, user, host, port)
+}
+
+/// The host names in a ProxyJump value; empty for `none`.
+pub fn jump_hop_hosts(value: &str) -> Vec<String> {
+    if value.trim().eq_ignore_ascii_case("none") {
+        return Vec::new();
+    }
+    value
+        .split(',')
+        .map(|hop| parse_jump_hop(hop.trim()).2.to_string())
+        .filter(|host| !host.is_empty())
+        .collect()
+}
+
+/// Splits `host:port`, including the bracketed `[v6::addr]:port` form.
+fn split_host_port(value: &str) -> (&str, Option<&str>) {
+    if let Some(rest) = value.strip_prefix('[') {
+        if let Some((host, after)) = rest.split_once(']') {
+            return (host, after.strip_prefix(':'));
+        }
+    }
+    match value.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') => (host, Some(port)),
+        _ => (value, None),
+    }
+}
+
 pub fn handle_remove_connection(args: &[String]) -> std::io::Result<()> {
     if args.is_empty() {
         println!("Usage: velo remove <connection_name>");
@@ -512,11 +1579,18 @@ pub fn handle_ssh_from_tui(connection: &str) -> io::Result<()> {
     stdout().execute(LeaveAlternateScreen)?;
 
     // Step 2: Execute the SSH command
-    let status = std::process::Command::new("ssh").arg(connection).status()?;
+    if let Err(e) = load_connection_keys(connection) {
+        eprintln!("Could not load keys into ssh-agent: {}", e);
+    }
+    let login = PasswordLogin::for_connection(connection)?;
+    let status = login
+        .command(connection, &[connection.to_string()])?
+        .status()?;
 
     // Step 3: Wait for user input before returning to TUI
     if !status.success() {
-        println!("SSH connection failed. Press Enter to return to TUI...");
+        report_ssh_failure(connection, status);
+        println!("Press Enter to return to TUI...");
         let mut input = String::new();
         io::stdin().read_line(&mut input)?;
     }
@@ -529,25 +1603,97 @@ pub fn handle_ssh_from_tui(connection: &str) -> io::Result<()> {
 }
 
 pub fn handle_ssh(args: &[String]) -> std::io::Result<()> {
-    if args.is_empty() {
-        println!("Usage: velo ssh <connection_name>");
+    let mut forwards: Vec<String> = Vec::new();
+    let mut names: Vec<&String> = Vec::new();
+    let mut options = args.iter();
+    while let Some(arg) = options.next() {
+        if arg != "--forward" {
+            names.push(arg);
+            continue;
+        }
+        match options.next() {
+            Some(value) => forwards.extend(value.split(',').map(str::to_string)),
+            None => {
+                println!("Missing value for --forward");
+                return Ok(());
+            }
+        }
+    }
+    if names.len() != 1 {
+        println!("Usage: velo ssh [--forward <name>[,<name>...]] <connection_name>");
         return Ok(());
     }
 
-    let connection_name = &args[0];
+    let connection_name = names[0];
+
+    // Only the picked forwards: run ssh against a copy of the config
+    // without the others. The copy is this session's alone and is deleted
+    // when ssh is done.
+    let mut ssh_args: Vec<String> = Vec::new();
+    let mut session_config = None;
+    if !forwards.is_empty() {
+        let file_name = format!(
+            "{}-{}.conf",
+            file_safe_name(connection_name),
+            std::process::id()
+        );
+        match forward_session_config(connection_name, &forwards, true, &file_name) {
+            Ok(path) => {
+                ssh_args.push("-F".to_string());
+                ssh_args.push(path.to_string_lossy().into_owned());
+                session_config = Some(path);
+            }
+            Err(e) => {
+                println!("{}", e);
+                return Ok(());
+            }
+        }
+    }
+    ssh_args.push(connection_name.clone());
+    let result = run_ssh(connection_name, &ssh_args, session_config.as_deref());
+    if !matches!(result, Ok(true)) {
+        if let Some(path) = &session_config {
+            let _ = fs::remove_file(path);
+        }
+    }
+    result.map(|_| ())
+}
+
+/// Runs ssh with `ssh_args`, in a Zellij pane where it can be. Returns true
+/// when the pane was started, which then deletes `session_config` itself.
+fn run_ssh(
+    connection_name: &str,
+    ssh_args: &[String],
+    session_config: Option<&Path>,
+) -> io::Result<bool> {
+    if let Err(e) = load_connection_keys(connection_name) {
+        eprintln!("Could not load keys into ssh-agent: {}", e);
+    }
+    let login = PasswordLogin::for_connection(connection_name)?;
 
     #[cfg(windows)]
     {
-        let status = Command::new("ssh").arg(connection_name).status()?;
+        let status = login.command(connection_name, ssh_args)?.status()?;
 
         if !status.success() {
-            println!("SSH connection failed");
+            report_ssh_failure(connection_name, status);
         }
+        Ok(false)
     }
 
     #[cfg(not(windows))]
-    {
-        ensure_ssh_agent_running();
+    if !login.works_in_zellij() {
+        println!("Running ssh outside Zellij so sshpass can be given the password.");
+        let status = login.command(connection_name, ssh_args)?.status()?;
+
+        if !status.success() {
+            report_ssh_failure(connection_name, status);
+        }
+        Ok(false)
+    } else {
+        if let Err(e) = ensure_ssh_agent_running() {
+            eprintln!("Could not start ssh-agent: {}", e);
+        }
 
         // Create or attach to a Zellij session
         let session_name = format!("ssh-{}", connection_name);
@@ -556,16 +1702,42 @@ pub fn handle_ssh(args: &[String]) -> std::io::Result<()> {
             Err(_) => println!("Attaching to existing Zellij session: {}", session_name),
         }
 
+        // The pane starts in the Zellij server's environment, not ours.
+        let mut env_prefix = login.env_prefix(connection_name)?;
+        if let Some(socket) = std::env::var_os("SSH_AUTH_SOCK") {
+            if env_prefix.is_empty() {
+                env_prefix.push("env".to_string());
+            }
+            env_prefix.push(format!("SSH_AUTH_SOCK={}", socket.to_string_lossy()));
+        }
+
+        let velo = std::env::current_exe()?;
         let status = Command::new("zellij")
-            .args(&["run", "--", "ssh", connection_name])
+            .args(["run", "--name", &format!("ssh {}", connection_name), "--"])
+            .args(env_prefix)
+            .args(["sh", "-c", SSH_IN_PANE])
+            .arg(velo)
+            .arg(connection_name)
+            .arg(session_config.unwrap_or(Path::new("")))
+            .args(ssh_args)
             .status()?;
 
         if !status.success() {
             println!("SSH connection failed");
         }
+        Ok(status.success())
     }
+}
 
-    Ok(())
+/// Says that ssh failed and, when ssh itself gave up (status 255), explains
+/// a changed host key and offers to re-trust it.
+fn report_ssh_failure(connection: &str, status: ExitStatus) {
+    println!("SSH connection failed");
+    if status.code() == Some(255) {
+        if let Err(e) = explain_host_key_failure(connection) {
+            eprintln!("Could not check the host key: {}", e);
+        }
+    }
 }
 
 fn prompt_port() -> u16 {
@@ -587,12 +1759,16 @@ fn prompt_port() -> u16 {
     }
 }
 
-fn prompt_yes_no(prompt: &str) -> bool {
+/// Asks until the answer is yes or no. End of input counts as no.
+pub fn prompt_yes_no(prompt: &str) -> bool {
     loop {
         print!("{}", prompt);
         std::io::stdout().flush().unwrap();
         let mut input = String::new();
-        stdin().read_line(&mut input).expect("Failed to read input");
+        if stdin().read_line(&mut input).expect("Failed to read input") == 0 {
+            println!();
+            return false;
+        }
         match input.trim().to_lowercase().as_str() {
             "y" | "yes" => return true,
             "n" | "no" => return false,
@@ -600,3 +1776,62 @@ fn prompt_yes_no(prompt: &str) -> bool {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::testing::temp_path;
+
+    #[test]
+    fn edits_are_reapplied_after_an_outside_change() {
+        let path = temp_path("ssh-reload");
+        fs::write(&path, "Host web\n  User deploy\n").unwrap();
+        let mut config = SSHConfig::load(&path).unwrap();
+
+        let mut runs = 0;
+        config
+            .edit_fresh(&mut |config: &mut SSHConfig| {
+                runs += 1;
+                if runs == 1 {
+                    // Someone else edits the file while velo works on it.
+                    fs::write(&path, "Host web\n  User deploy\n  Port 2222\n").unwrap();
+                }
+                config.edit_host("web", |block| block.set("User", "admin"));
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(runs, 2);
+        assert_eq!(
+            config.files[0].document.to_string(),
+            "Host web\n  User admin\n  Port 2222\n"
+        );
+        assert!(config.changed_on_disk().unwrap().is_none());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn edits_give_up_while_the_file_keeps_changing() {
+        let path = temp_path("ssh-conflict");
+        fs::write(&path, "Host web\n").unwrap();
+        let mut config = SSHConfig::load(&path).unwrap();
+
+        let mut runs = 0;
+        let result = config.edit_fresh(&mut |config: &mut SSHConfig| {
+            runs += 1;
+            fs::write(&path, format!("Host web\n  Port {}\n", 2200 + runs)).unwrap();
+            config.edit_host("web", |block| block.set("User", "admin"));
+            Ok(())
+        });
+
+        let error = result.unwrap_err();
+        assert_eq!(runs, MAX_EDIT_ATTEMPTS);
+        assert!(error.to_string().contains("keeps changing on disk"));
+        // Nothing velo did reached the file.
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("Host web\n  Port {}\n", 2200 + MAX_EDIT_ATTEMPTS)
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
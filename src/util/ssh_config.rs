@@ -0,0 +1,1029 @@
+// Lossless parser for OpenSSH client configuration files.
+//
+// The file is split into a global section followed by `Host`/`Match` blocks.
+// Every line keeps its original indentation, separator, trailing whitespace
+// and line ending, so rendering an unmodified document reproduces the input
+// byte-for-byte. Edits only touch the lines they change.
+
+use std::fmt;
+
+const DEFAULT_INDENT: &str = "    ";
+
+// Comments velo writes to label the directive right below them, e.g.
+// `# velo:forward pg` above a `LocalForward` line.
+const TAG_PREFIX: &str = "velo:";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Line {
+    Blank { raw: String, eol: String },
+    Comment { raw: String, eol: String },
+    Directive(Directive),
+}
+
+impl Line {
+    fn parse(raw: &str, eol: &str) -> Self {
+        let trimmed = raw.trim_start_matches([' ', '\t']);
+        if trimmed.trim().is_empty() {
+            Line::Blank {
+                raw: raw.to_string(),
+                eol: eol.to_string(),
+            }
+        } else if trimmed.starts_with('#') {
+            Line::Comment {
+                raw: raw.to_string(),
+                eol: eol.to_string(),
+            }
+        } else {
+            Line::Directive(Directive::parse(raw, eol))
+        }
+    }
+
+    pub fn as_directive(&self) -> Option<&Directive> {
+        match self {
+            Line::Directive(directive) => Some(directive),
+            _ => None,
+        }
+    }
+
+    pub fn as_directive_mut(&mut self) -> Option<&mut Directive> {
+        match self {
+            Line::Directive(directive) => Some(directive),
+            _ => None,
+        }
+    }
+
+    /// The `(kind, name)` of a `# velo:<kind> <name>` comment.
+    pub fn tag(&self) -> Option<(&str, &str)> {
+        let Line::Comment { raw, .. } = self else {
+            return None;
+        };
+        let body = raw.trim().strip_prefix('#')?.trim_start();
+        let (kind, name) = body
+            .strip_prefix(TAG_PREFIX)?
+            .split_once(char::is_whitespace)?;
+        Some((kind, name.trim()))
+    }
+
+    fn eol(&self) -> &str {
+        match self {
+            Line::Blank { eol, .. } | Line::Comment { eol, .. } => eol,
+            Line::Directive(directive) => &directive.eol,
+        }
+    }
+
+    fn eol_mut(&mut self) -> &mut String {
+        match self {
+            Line::Blank { eol, .. } | Line::Comment { eol, .. } => eol,
+            Line::Directive(directive) => &mut directive.eol,
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Line::Blank { raw, eol } | Line::Comment { raw, eol } => write!(f, "{}{}", raw, eol),
+            Line::Directive(directive) => directive.fmt(f),
+        }
+    }
+}
+
+/// A `Keyword value` line. `Keyword=value` and `Keyword = value` are kept as
+/// written through `separator`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Directive {
+    indent: String,
+    keyword: String,
+    separator: String,
+    value: String,
+    trailing: String,
+    eol: String,
+}
+
+impl Directive {
+    pub fn new(indent: &str, keyword: &str, value: &str, eol: &str) -> Self {
+        Directive {
+            indent: indent.to_string(),
+            keyword: keyword.to_string(),
+            separator: " ".to_string(),
+            value: value.to_string(),
+            trailing: String::new(),
+            eol: eol.to_string(),
+        }
+    }
+
+    fn parse(raw: &str, eol: &str) -> Self {
+        let body_start = raw.len() - raw.trim_start_matches([' ', '\t']).len();
+        let (indent, body) = raw.split_at(body_start);
+
+        let keyword_end = body
+            .find(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or(body.len());
+        let (keyword, rest) = body.split_at(keyword_end);
+
+        // Whitespace, at most one '=', then more whitespace.
+        let mut separator_end = rest.len() - rest.trim_start().len();
+        if rest[separator_end..].starts_with('=') {
+            separator_end += 1;
+            let after = &rest[separator_end..];
+            separator_end += after.len() - after.trim_start().len();
+        }
+        let (separator, rest) = rest.split_at(separator_end);
+
+        let value = rest.trim_end();
+        let trailing = &rest[value.len()..];
+
+        Directive {
+            indent: indent.to_string(),
+            keyword: keyword.to_string(),
+            separator: separator.to_string(),
+            value: value.to_string(),
+            trailing: trailing.to_string(),
+            eol: eol.to_string(),
+        }
+    }
+
+    pub fn indent(&self) -> &str {
+        &self.indent
+    }
+
+    pub fn keyword(&self) -> &str {
+        &self.keyword
+    }
+
+    /// Keywords are case-insensitive in ssh_config.
+    pub fn is(&self, keyword: &str) -> bool {
+        self.keyword.eq_ignore_ascii_case(keyword)
+    }
+
+    /// The raw argument text, exactly as written.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn set_value(&mut self, value: &str) {
+        if self.separator.is_empty() {
+            self.separator = " ".to_string();
+        }
+        self.value = value.to_string();
+    }
+
+    /// The arguments split the way ssh does, honouring quotes.
+    pub fn args(&self) -> Vec<String> {
+        split_args(&self.value)
+    }
+
+    pub fn set_args(&mut self, args: &[String]) {
+        let value = args
+            .iter()
+            .map(|arg| quote_arg(arg))
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.set_value(&value);
+    }
+}
+
+impl fmt::Display for Directive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}{}{}{}{}",
+            self.indent, self.keyword, self.separator, self.value, self.trailing, self.eol
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockKind {
+    Host,
+    Match,
+}
+
+/// A `Host` or `Match` line and every line up to the next one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub header: Directive,
+    pub lines: Vec<Line>,
+}
+
+impl Block {
+    pub fn host(name: &str, eol: &str) -> Self {
+        Block {
+            header: Directive::new("", "Host", &quote_arg(name), eol),
+            lines: Vec::new(),
+        }
+    }
+
+    pub fn kind(&self) -> BlockKind {
+        if self.header.is("Match") {
+            BlockKind::Match
+        } else {
+            BlockKind::Host
+        }
+    }
+
+    /// Host patterns on the header line; empty for `Match` blocks.
+    pub fn patterns(&self) -> Vec<String> {
+        match self.kind() {
+            BlockKind::Host => self.header.args(),
+            BlockKind::Match => Vec::new(),
+        }
+    }
+
+    /// Patterns without wildcards or negation, i.e. names you can `ssh` to.
+    pub fn aliases(&self) -> Vec<String> {
+        self.patterns()
+            .into_iter()
+            .filter(|pattern| is_literal_pattern(pattern))
+            .collect()
+    }
+
+    pub fn has_alias(&self, name: &str) -> bool {
+        self.aliases().iter().any(|alias| alias == name)
+    }
+
+    pub fn directives(&self) -> impl Iterator<Item = &Directive> {
+        self.lines.iter().filter_map(Line::as_directive)
+    }
+
+    pub fn get(&self, keyword: &str) -> Option<&str> {
+        self.directives()
+            .find(|directive| directive.is(keyword))
+            .map(Directive::value)
+    }
+
+    /// Replaces the first `keyword` line and drops any others, or appends a
+    /// new line if the block has none.
+    pub fn set(&mut self, keyword: &str, value: &str, fallback_indent: &str, eol: &str) {
+        let mut found = false;
+        self.lines.retain_mut(|line| match line {
+            Line::Directive(directive) if directive.is(keyword) => {
+                if found {
+                    false
+                } else {
+                    directive.set_value(value);
+                    found = true;
+                    true
+                }
+            }
+            _ => true,
+        });
+
+        if !found {
+            self.push_directive(keyword, value, fallback_indent, eol);
+        }
+    }
+
+    /// Removes every `keyword` line. Returns whether anything was removed.
+    pub fn unset(&mut self, keyword: &str) -> bool {
+        let before = self.lines.len();
+        self.lines.retain(|line| {
+            !line
+                .as_directive()
+                .is_some_and(|directive| directive.is(keyword))
+        });
+        self.lines.len() != before
+    }
+
+    /// Adds a directive after the last existing one so that trailing blank
+    /// lines and comments stay at the end of the block.
+    pub fn push_directive(&mut self, keyword: &str, value: &str, fallback_indent: &str, eol: &str) {
+        let indent = self.indent().unwrap_or(fallback_indent).to_string();
+        let at = self.directives_end();
+        self.lines.insert(
+            at,
+            Line::Directive(Directive::new(&indent, keyword, value, eol)),
+        );
+    }
+
+    /// Directives labelled with a `# velo:<kind> <name>` comment directly
+    /// above them, as `(name, index into lines)`.
+    pub fn tagged(&self, kind: &str) -> Vec<(String, usize)> {
+        self.lines
+            .windows(2)
+            .enumerate()
+            .filter_map(|(i, pair)| match (pair[0].tag(), &pair[1]) {
+                (Some((tag_kind, name)), Line::Directive(_)) if tag_kind == kind => {
+                    Some((name.to_string(), i + 1))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Adds a directive together with the comment that names it.
+    pub fn push_tagged(
+        &mut self,
+        kind: &str,
+        name: &str,
+        keyword: &str,
+        value: &str,
+        fallback_indent: &str,
+        eol: &str,
+    ) {
+        let indent = self.indent().unwrap_or(fallback_indent).to_string();
+        let at = self.directives_end();
+        let tag = Line::Comment {
+            raw: format!("{}# {}{} {}", indent, TAG_PREFIX, kind, name),
+            eol: eol.to_string(),
+        };
+        let directive = Line::Directive(Directive::new(&indent, keyword, value, eol));
+        self.lines.splice(at..at, [tag, directive]);
+    }
+
+    /// Removes a tagged directive and its comment. Returns false if there is
+    /// no such tag.
+    pub fn remove_tagged(&mut self, kind: &str, name: &str) -> bool {
+        let Some((_, index)) = self.tagged(kind).into_iter().find(|(n, _)| n == name) else {
+            return false;
+        };
+        self.lines.drain(index - 1..=index);
+        true
+    }
+
+    /// The name of the block's own `# velo:<kind> <name>` comment, one that
+    /// describes the whole block rather than the directive below it.
+    pub fn tag(&self, kind: &str) -> Option<&str> {
+        self.lines.iter().find_map(|line| match line.tag() {
+            Some((tag_kind, name)) if tag_kind == kind => Some(name),
+            _ => None,
+        })
+    }
+
+    /// Replaces the block's `# velo:<kind>` comment with one naming `name`
+    /// at the top of the block, or removes it when `name` is `None`.
+    pub fn set_tag(&mut self, kind: &str, name: Option<&str>, fallback_indent: &str, eol: &str) {
+        let indent = self.indent().unwrap_or(fallback_indent).to_string();
+        self.lines
+            .retain(|line| line.tag().is_none_or(|(tag_kind, _)| tag_kind != kind));
+        if let Some(name) = name {
+            let tag = Line::Comment {
+                raw: format!("{}# {}{} {}", indent, TAG_PREFIX, kind, name),
+                eol: eol.to_string(),
+            };
+            self.lines.insert(0, tag);
+        }
+    }
+
+    /// Index just past the last directive in the block.
+    pub fn directives_end(&self) -> usize {
+        self.lines
+            .iter()
+            .rposition(|line| line.as_directive().is_some())
+            .map_or(0, |i| i + 1)
+    }
+
+    pub fn indent(&self) -> Option<&str> {
+        self.directives().next().map(Directive::indent)
+    }
+
+    /// Replaces one alias on the header, keeping any others.
+    pub fn rename_alias(&mut self, old: &str, new: &str) -> bool {
+        let patterns = self.patterns();
+        if !patterns.iter().any(|pattern| pattern == old) {
+            return false;
+        }
+        let renamed: Vec<String> = patterns
+            .into_iter()
+            .map(|pattern| {
+                if pattern == old {
+                    new.to_string()
+                } else {
+                    pattern
+                }
+            })
+            .collect();
+        self.header.set_args(&renamed);
+        true
+    }
+
+    /// Drops one alias from a multi-alias header. Returns false if `name` is
+    /// the only pattern left, in which case the caller should remove the block.
+    fn remove_alias(&mut self, name: &str) -> bool {
+        let patterns = self.patterns();
+        if patterns.len() <= 1 {
+            return false;
+        }
+        let remaining: Vec<String> = patterns.into_iter().filter(|p| p != name).collect();
+        self.header.set_args(&remaining);
+        true
+    }
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.header.fmt(f)?;
+        for line in &self.lines {
+            line.fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// A block borrowed together with the indentation and line ending that new
+/// lines in its file should use.
+pub struct BlockEdit<'a> {
+    block: &'a mut Block,
+    indent: String,
+    eol: String,
+}
+
+impl BlockEdit<'_> {
+    pub fn set(&mut self, keyword: &str, value: &str) {
+        self.block.set(keyword, value, &self.indent, &self.eol);
+    }
+
+    pub fn unset(&mut self, keyword: &str) -> bool {
+        self.block.unset(keyword)
+    }
+
+    pub fn push(&mut self, keyword: &str, value: &str) {
+        self.block
+            .push_directive(keyword, value, &self.indent, &self.eol);
+    }
+
+    pub fn push_tagged(&mut self, kind: &str, name: &str, keyword: &str, value: &str) {
+        self.block
+            .push_tagged(kind, name, keyword, value, &self.indent, &self.eol);
+    }
+
+    pub fn set_tag(&mut self, kind: &str, name: Option<&str>) {
+        self.block.set_tag(kind, name, &self.indent, &self.eol);
+    }
+
+    /// Copies the directives and comments of `other`, leaving out the blank
+    /// lines that separate it from the next block.
+    pub fn copy_from(&mut self, other: &Block) {
+        let at = self.block.directives_end();
+        let mut copied: Vec<Line> = other.lines[..other.directives_end()].to_vec();
+        for line in copied.iter_mut() {
+            *line.eol_mut() = self.eol.clone();
+        }
+        self.block.lines.splice(at..at, copied);
+    }
+}
+
+impl std::ops::Deref for BlockEdit<'_> {
+    type Target = Block;
+
+    fn deref(&self) -> &Block {
+        self.block
+    }
+}
+
+impl std::ops::DerefMut for BlockEdit<'_> {
+    fn deref_mut(&mut self) -> &mut Block {
+        self.block
+    }
+}
+
+/// A parsed ssh_config file.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ConfigDocument {
+    pub global: Vec<Line>,
+    pub blocks: Vec<Block>,
+}
+
+impl ConfigDocument {
+    pub fn parse(text: &str) -> Self {
+        let mut document = ConfigDocument::default();
+
+        for (raw, eol) in split_lines(text) {
+            let line = Line::parse(raw, eol);
+            let starts_block = line
+                .as_directive()
+                .is_some_and(|directive| directive.is("Host") || directive.is("Match"));
+
+            if starts_block {
+                if let Line::Directive(header) = line {
+                    document.blocks.push(Block {
+                        header,
+                        lines: Vec::new(),
+                    });
+                }
+            } else if let Some(block) = document.blocks.last_mut() {
+                block.lines.push(line);
+            } else {
+                document.global.push(line);
+            }
+        }
+
+        document
+    }
+
+    pub fn edit_block(&mut self, index: usize) -> BlockEdit<'_> {
+        let indent = self.indent().to_string();
+        let eol = self.eol().to_string();
+        BlockEdit {
+            block: &mut self.blocks[index],
+            indent,
+            eol,
+        }
+    }
+
+    /// Appends a block at the end of the file, separated by a blank line.
+    pub fn append_block(&mut self, mut block: Block) {
+        let eol = self.eol().to_string();
+        let is_empty = self.global.is_empty() && self.blocks.is_empty();
+        let needs_separator = !is_empty && !self.ends_with_blank_line();
+
+        if needs_separator {
+            let separator = Line::Blank {
+                raw: String::new(),
+                eol: eol.clone(),
+            };
+            match self.blocks.last_mut() {
+                Some(last) => last.lines.push(separator),
+                None => self.global.push(separator),
+            }
+        }
+
+        block.header.eol = eol.clone();
+        for line in block.lines.iter_mut() {
+            *line.eol_mut() = eol.clone();
+        }
+        self.blocks.push(block);
+        self.fix_line_endings();
+    }
+
+    /// Removes `name` from the config. A multi-alias header only loses that
+    /// alias; otherwise the whole block goes, except for trailing comments,
+    /// which usually describe whatever follows.
+    pub fn remove_host(&mut self, name: &str) -> bool {
+        let Some(index) = self.blocks.iter().position(|block| block.has_alias(name)) else {
+            return false;
+        };
+
+        if self.blocks[index].remove_alias(name) {
+            return true;
+        }
+
+        let block = self.blocks.remove(index);
+        let kept: Vec<Line> = block.lines[block.directives_end()..]
+            .iter()
+            .skip_while(|line| !matches!(line, Line::Comment { .. }))
+            .cloned()
+            .collect();
+
+        if !kept.is_empty() {
+            match index.checked_sub(1) {
+                Some(previous) => self.blocks[previous].lines.extend(kept),
+                None => self.global.extend(kept),
+            }
+        }
+        self.fix_line_endings();
+        true
+    }
+
+    /// Every line except the `Host`/`Match` headers, in file order.
+    pub fn lines(&self) -> impl Iterator<Item = &Line> {
+        self.global
+            .iter()
+            .chain(self.blocks.iter().flat_map(|block| block.lines.iter()))
+    }
+
+    pub fn lines_mut(&mut self) -> impl Iterator<Item = &mut Line> {
+        self.global.iter_mut().chain(
+            self.blocks
+                .iter_mut()
+                .flat_map(|block| block.lines.iter_mut()),
+        )
+    }
+
+    /// Inserts a directive into the global section, below any comment lines
+    /// that open the file.
+    pub fn prepend_global(&mut self, keyword: &str, value: &str) {
+        let eol = self.eol().to_string();
+        let at = self
+            .global
+            .iter()
+            .take_while(|line| matches!(line, Line::Comment { .. }))
+            .count();
+        self.global.insert(
+            at,
+            Line::Directive(Directive::new("", keyword, value, &eol)),
+        );
+        if at + 1 == self.global.len() && !self.blocks.is_empty() {
+            self.global.push(Line::Blank {
+                raw: String::new(),
+                eol,
+            });
+        }
+        self.fix_line_endings();
+    }
+
+    /// Indentation used by the file's existing directives.
+    pub fn indent(&self) -> &str {
+        self.blocks
+            .iter()
+            .filter_map(Block::indent)
+            .find(|indent| !indent.is_empty())
+            .unwrap_or(DEFAULT_INDENT)
+    }
+
+    /// The line ending the file already uses, `\n` for new files.
+    pub fn eol(&self) -> &str {
+        let global = self.global.iter().map(Line::eol);
+        let blocks = self.blocks.iter().flat_map(|block| {
+            std::iter::once(block.header.eol.as_str()).chain(block.lines.iter().map(Line::eol))
+        });
+        global
+            .chain(blocks)
+            .find(|eol| !eol.is_empty())
+            .unwrap_or("\n")
+    }
+
+    fn ends_with_blank_line(&self) -> bool {
+        match self.blocks.last() {
+            Some(block) => matches!(block.lines.last(), Some(Line::Blank { .. })),
+            None => matches!(self.global.last(), Some(Line::Blank { .. })),
+        }
+    }
+
+    /// Lines other than the very last one must end in a newline; a file that
+    /// lacked a final newline gets one once something is appended after it.
+    pub fn fix_line_endings(&mut self) {
+        let eol = self.eol().to_string();
+        let mut slots: Vec<&mut String> = self.global.iter_mut().map(Line::eol_mut).collect();
+        for block in self.blocks.iter_mut() {
+            slots.push(&mut block.header.eol);
+            slots.extend(block.lines.iter_mut().map(Line::eol_mut));
+        }
+
+        let count = slots.len();
+        for slot in slots.into_iter().take(count.saturating_sub(1)) {
+            if slot.is_empty() {
+                *slot = eol.clone();
+            }
+        }
+    }
+}
+
+impl fmt::Display for ConfigDocument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.global {
+            line.fmt(f)?;
+        }
+        for block in &self.blocks {
+            block.fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits text into (line, line ending) pairs. The last line has an empty
+/// ending when the file does not end in a newline.
+fn split_lines(text: &str) -> Vec<(&str, &str)> {
+    let mut lines = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        match rest.find('\n') {
+            Some(i) => {
+                let (line, eol) = if i > 0 && rest.as_bytes()[i - 1] == b'\r' {
+                    (&rest[..i - 1], &rest[i - 1..=i])
+                } else {
+                    (&rest[..i], &rest[i..=i])
+                };
+                lines.push((line, eol));
+                rest = &rest[i + 1..];
+            }
+            None => {
+                lines.push((rest, ""));
+                rest = "";
+            }
+        }
+    }
+    lines
+}
+
+/// Splits an argument string the way ssh does: on whitespace, with single or
+/// double quotes grouping words and backslash escaping quotes.
+pub fn split_args(value: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut quote: Option<char> = None;
+    let mut chars = value.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            ('\\', _) if matches!(chars.peek(), Some('"') | Some('\'') | Some('\\')) => {
+                current.push(chars.next().unwrap());
+                in_arg = true;
+            }
+            ('"' | '\'', None) => {
+                quote = Some(c);
+                in_arg = true;
+            }
+            (c, Some(q)) if c == q => quote = None,
+            (c, None) if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            (c, _) => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if in_arg {
+        args.push(current);
+    }
+    args
+}
+
+pub fn quote_arg(arg: &str) -> String {
+    if arg.is_empty() || arg.contains(char::is_whitespace) || arg.contains('"') {
+        format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        arg.to_string()
+    }
+}
+
+pub fn is_literal_pattern(pattern: &str) -> bool {
+    !pattern.is_empty() && !pattern.contains(['*', '?', '!'])
+}
+
+/// Matches `name` against a single `*`/`?` wildcard pattern,
+/// case-insensitively like ssh's `match_pattern`.
+pub fn match_pattern(name: &str, pattern: &str) -> bool {
+    fn matches(name: &[u8], pattern: &[u8]) -> bool {
+        match pattern.split_first() {
+            None => name.is_empty(),
+            Some((b'*', rest)) => (0..=name.len()).any(|skip| matches(&name[skip..], rest)),
+            Some((&c, rest)) => match name.split_first() {
+                Some((&n, name)) => {
+                    (c == b'?' || c.eq_ignore_ascii_case(&n)) && matches(name, rest)
+                }
+                None => false,
+            },
+        }
+    }
+    matches(name.as_bytes(), pattern.as_bytes())
+}
+
+/// Matches `name` against a comma-separated pattern list as used by `Match`.
+/// Returns `Some(false)` if a negated pattern matched, `Some(true)` if a
+/// positive one did and `None` if nothing matched.
+pub fn match_pattern_list(name: &str, list: &str) -> Option<bool> {
+    host_pattern_result(name, list.split(','))
+}
+
+/// Whether a `Host` line with `patterns` applies to `name`: at least one
+/// pattern matches and no negated pattern does.
+pub fn host_matches(name: &str, patterns: &[String]) -> bool {
+    host_pattern_result(name, patterns.iter().map(String::as_str)) == Some(true)
+}
+
+fn host_pattern_result<'a>(name: &str, patterns: impl Iterator<Item = &'a str>) -> Option<bool> {
+    let mut result = None;
+    for pattern in patterns {
+        match pattern.strip_prefix('!') {
+            Some(negated) if match_pattern(name, negated) => return Some(false),
+            Some(_) => {}
+            None if match_pattern(name, pattern) => result = Some(true),
+            None => {}
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn corpus() -> Vec<(PathBuf, String)> {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/ssh_config");
+        let mut files: Vec<(PathBuf, String)> = fs::read_dir(&dir)
+            .expect("fixture directory")
+            .map(|entry| {
+                let path = entry.unwrap().path();
+                let text = fs::read_to_string(&path).unwrap();
+                (path, text)
+            })
+            .collect();
+        files.sort();
+        assert!(!files.is_empty(), "no fixtures in {}", dir.display());
+        files
+    }
+
+    #[test]
+    fn corpus_round_trips_byte_for_byte() {
+        for (path, text) in corpus() {
+            let document = ConfigDocument::parse(&text);
+            assert_eq!(document.to_string(), text, "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn corpus_survives_unrelated_edits() {
+        for (path, text) in corpus() {
+            let mut document = ConfigDocument::parse(&text);
+            document.append_block(Block::host("velo-test", "\n"));
+            assert!(document.remove_host("velo-test"), "{}", path.display());
+            let rendered = document.to_string();
+            // Appending may add a final newline and a separating blank line,
+            // but never touches existing bytes.
+            assert!(rendered.starts_with(text.trim_end_matches(['\r', '\n'])));
+        }
+    }
+
+    #[test]
+    fn parses_blocks_and_separators() {
+        let text = "# global\nUser me\n\nHost=web web.prod\n\tHostName = 10.0.0.1\n\nMatch host *.corp exec \"true\"\n  ForwardAgent yes\n";
+        let document = ConfigDocument::parse(text);
+
+        assert_eq!(document.global.len(), 3);
+        assert_eq!(document.blocks.len(), 2);
+        assert_eq!(document.blocks[0].patterns(), vec!["web", "web.prod"]);
+        let hostname = document.blocks[0].directives().next().unwrap();
+        assert!(hostname.is("hostname"));
+        assert_eq!(hostname.args(), vec!["10.0.0.1"]);
+        assert_eq!(document.blocks[1].kind(), BlockKind::Match);
+        assert_eq!(document.blocks[0].aliases(), vec!["web", "web.prod"]);
+    }
+
+    #[test]
+    fn wildcards_and_negations_are_not_connections() {
+        let document = ConfigDocument::parse("Host *\n  User x\nHost !bad *.lan db\n  Port 2\n");
+        assert!(document.blocks[0].aliases().is_empty());
+        assert_eq!(document.blocks[1].aliases(), vec!["db"]);
+    }
+
+    #[test]
+    fn quoted_arguments() {
+        assert_eq!(
+            split_args(r#"a "b c" 'd e' f\"g"#),
+            vec!["a", "b c", "d e", "f\"g"]
+        );
+        assert_eq!(quote_arg("my host"), "\"my host\"");
+    }
+
+    #[test]
+    fn set_preserves_indent_and_separator() {
+        let mut document =
+            ConfigDocument::parse("Host web\n\tUser=root\n\tPort 22\n\n# next\nHost db\n");
+        let mut block = document.edit_block(0);
+        block.set("User", "admin");
+        block.set("IdentityFile", "~/.ssh/id");
+        assert_eq!(block.get("user"), Some("admin"));
+        assert_eq!(
+            document.to_string(),
+            "Host web\n\tUser=admin\n\tPort 22\n\tIdentityFile ~/.ssh/id\n\n# next\nHost db\n"
+        );
+    }
+
+    #[test]
+    fn set_collapses_duplicates() {
+        let mut document =
+            ConfigDocument::parse("Host web\n  IdentityFile a\n  User u\n  IdentityFile b\n");
+        document.edit_block(0).set("IdentityFile", "c");
+        assert_eq!(
+            document.to_string(),
+            "Host web\n  IdentityFile c\n  User u\n"
+        );
+        assert!(document.edit_block(0).unset("identityfile"));
+        assert_eq!(document.to_string(), "Host web\n  User u\n");
+    }
+
+    #[test]
+    fn remove_keeps_comments_and_match_blocks() {
+        let text = "Host a\n  User a\n\n# Database box\nHost b\n  User b\n\nMatch all\n  User m\n";
+        let mut document = ConfigDocument::parse(text);
+        assert!(document.remove_host("a"));
+        assert_eq!(
+            document.to_string(),
+            "# Database box\nHost b\n  User b\n\nMatch all\n  User m\n"
+        );
+        assert!(!document.remove_host("missing"));
+    }
+
+    #[test]
+    fn remove_single_alias_from_multi_alias_host() {
+        let mut document = ConfigDocument::parse("Host web web.prod\n  User u\n");
+        assert!(document.remove_host("web"));
+        assert_eq!(document.to_string(), "Host web.prod\n  User u\n");
+    }
+
+    #[test]
+    fn prepend_global_keeps_file_header_on_top() {
+        let mut document = ConfigDocument::parse("# my hosts\nHost a\n  User a\n");
+        document.prepend_global("Include", "config.d/velo.conf");
+        assert_eq!(
+            document.to_string(),
+            "# my hosts\nInclude config.d/velo.conf\n\nHost a\n  User a\n"
+        );
+
+        let mut document = ConfigDocument::parse("User me\n");
+        document.prepend_global("Include", "x");
+        assert_eq!(document.to_string(), "Include x\nUser me\n");
+    }
+
+    #[test]
+    fn rename_alias_keeps_other_patterns() {
+        let mut document = ConfigDocument::parse("Host=web web.prod\n  User u\n");
+        assert!(document.blocks[0].rename_alias("web", "web-02"));
+        assert!(!document.blocks[0].rename_alias("missing", "x"));
+        assert_eq!(document.to_string(), "Host=web-02 web.prod\n  User u\n");
+    }
+
+    #[test]
+    fn copy_from_skips_trailing_blank_lines() {
+        let source = ConfigDocument::parse("Host a\n  User u\n  # note\n  Port 2\n\n");
+        let mut document = ConfigDocument::parse("");
+        document.append_block(Block::host("b", "\n"));
+        document.edit_block(0).copy_from(&source.blocks[0]);
+        assert_eq!(
+            document.to_string(),
+            "Host b\n  User u\n  # note\n  Port 2\n"
+        );
+    }
+
+    #[test]
+    fn append_uses_existing_line_endings() {
+        let mut document = ConfigDocument::parse("Host a\r\n  User a");
+        let mut block = Block::host("b", "\n");
+        block.push_directive("User", "b", document.indent(), "\n");
+        document.append_block(block);
+        assert_eq!(
+            document.to_string(),
+            "Host a\r\n  User a\r\n\r\nHost b\r\n  User b\r\n"
+        );
+    }
+
+    #[test]
+    fn append_to_empty_document() {
+        let mut document = ConfigDocument::parse("");
+        let mut block = Block::host("a", "\n");
+        block.push_directive("HostName", "1.2.3.4", document.indent(), "\n");
+        document.append_block(block);
+        assert_eq!(document.to_string(), "Host a\n    HostName 1.2.3.4\n");
+    }
+
+    #[test]
+    fn host_patterns_follow_ssh_rules() {
+        let patterns = |list: &str| list.split(' ').map(str::to_string).collect::<Vec<_>>();
+        assert!(host_matches("web.prod", &patterns("*.prod")));
+        assert!(host_matches("WEB", &patterns("db web")));
+        assert!(host_matches("db1", &patterns("db?")));
+        assert!(!host_matches("db12", &patterns("db?")));
+        assert!(!host_matches(
+            "bastion.prod",
+            &patterns("*.prod !bastion.*")
+        ));
+        assert!(!host_matches("web", &patterns("!db")));
+        assert_eq!(
+            match_pattern_list("10.0.0.1", "10.0.*,!10.0.0.2"),
+            Some(true)
+        );
+        assert_eq!(
+            match_pattern_list("10.0.0.2", "10.0.*,!10.0.0.2"),
+            Some(false)
+        );
+        assert_eq!(match_pattern_list("example.com", "*.corp"), None);
+    }
+
+    #[test]
+    fn tagged_directives_round_trip() {
+        let text = "Host db\n\tHostName 10.0.0.1\n\t# velo:forward pg\n\tLocalForward 15432 localhost:5432\n# trailing\n";
+        let mut document = ConfigDocument::parse(text);
+        let block = &mut document.blocks[0];
+        assert_eq!(block.tagged("forward"), vec![("pg".to_string(), 2)]);
+
+        block.push_tagged("forward", "socks", "DynamicForward", "1080", "  ", "\n");
+        assert_eq!(
+            document.to_string(),
+            "Host db\n\tHostName 10.0.0.1\n\t# velo:forward pg\n\tLocalForward 15432 localhost:5432\n\t# velo:forward socks\n\tDynamicForward 1080\n# trailing\n"
+        );
+
+        assert!(document.blocks[0].remove_tagged("forward", "pg"));
+        assert!(!document.blocks[0].remove_tagged("forward", "pg"));
+        assert_eq!(
+            document.to_string(),
+            "Host db\n\tHostName 10.0.0.1\n\t# velo:forward socks\n\tDynamicForward 1080\n# trailing\n"
+        );
+    }
+
+    #[test]
+    fn block_tags_round_trip() {
+        let text = "Host db\n  HostName 10.0.0.1\n";
+        let mut document = ConfigDocument::parse(text);
+        let block = &mut document.blocks[0];
+        assert_eq!(block.tag("agent"), None);
+
+        block.set_tag("agent", Some("lifetime=8h"), "    ", "\n");
+        block.set_tag("agent", Some("lifetime=1h confirm"), "    ", "\n");
+        assert_eq!(block.tag("agent"), Some("lifetime=1h confirm"));
+        assert_eq!(
+            document.to_string(),
+            "Host db\n  # velo:agent lifetime=1h confirm\n  HostName 10.0.0.1\n"
+        );
+
+        document.blocks[0].set_tag("agent", None, "    ", "\n");
+        assert_eq!(document.to_string(), text);
+    }
+}
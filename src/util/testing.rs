@@ -0,0 +1,21 @@
+// Fixtures shared by the unit tests.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// A path in the temp dir that belongs to this test run, with whatever an
+/// earlier run left there removed. `name` has to be unique among the tests.
+pub fn temp_path(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("velo-{}-{}", name, std::process::id()));
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_dir_all(&path);
+    path
+}
+
+/// Like `temp_path`, with an empty directory created there.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = temp_path(name);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
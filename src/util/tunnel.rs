@@ -0,0 +1,416 @@
+// Background tunnels: `ssh -N` processes that keep a connection's forwards
+// open after velo exits. Each one is recorded in tunnels.json in velo's data
+// dir so later runs can list, stop and clean them up.
+
+use crate::util::backup::atomic_write;
+use crate::util::forward::{block_forwards, file_safe_name, forward_session_config};
+use crate::util::ssh::{velo_data_dir, SSHConfig};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// How long a new tunnel has to stay up before it counts as started.
+const STARTUP_GRACE: Duration = Duration::from_millis(1500);
+
+const LOG_TAIL_LINES: usize = 50;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tunnel {
+    pub connection: String,
+    /// The named forward this tunnel opens, or `None` for all of them.
+    pub forward: Option<String>,
+    pub pid: u32,
+    pub ports: Vec<u16>,
+    /// Seconds since the Unix epoch.
+    pub started: u64,
+    pub log: PathBuf,
+}
+
+impl Tunnel {
+    pub fn label(&self) -> String {
+        tunnel_label(&self.connection, self.forward.as_deref())
+    }
+
+    pub fn is_running(&self) -> bool {
+        process_alive(self.pid)
+    }
+
+    pub fn uptime(&self) -> String {
+        format_duration(now().saturating_sub(self.started))
+    }
+
+    pub fn ports_text(&self) -> String {
+        if self.ports.is_empty() {
+            return "-".to_string();
+        }
+        self.ports
+            .iter()
+            .map(u16::to_string)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+fn tunnel_label(connection: &str, forward: Option<&str>) -> String {
+    match forward {
+        Some(forward) => format!("{}-{}", connection, forward),
+        None => connection.to_string(),
+    }
+}
+
+/// The name a tunnel's log and ssh config files are kept under. Unlike the
+/// label it is unique: the connection is escaped down to the characters a
+/// forward name may use, so the `@` before the forward is unambiguous.
+fn tunnel_file_stem(connection: &str, forward: Option<&str>) -> String {
+    let mut stem = file_safe_name(connection);
+    if let Some(forward) = forward {
+        stem.push('@');
+        stem.push_str(forward);
+    }
+    stem
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn format_duration(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86399 => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d{:02}h", secs / 86400, secs % 86400 / 3600),
+    }
+}
+
+#[cfg(unix)]
+fn process_alive(pid: u32) -> bool {
+    // Signal 0 only checks that the process exists.
+    if unsafe { libc::kill(pid as libc::pid_t, 0) } != 0 {
+        return false;
+    }
+    // Where /proc exists, make sure the pid has not been reused by
+    // something else (or left as a zombie with no command line).
+    match fs::read(format!("/proc/{}/cmdline", pid)) {
+        Ok(cmdline) => cmdline
+            .split(|&b| b == 0)
+            .next()
+            .is_some_and(|program| program.ends_with(b"ssh")),
+        Err(_) => true,
+    }
+}
+
+#[cfg(not(unix))]
+fn process_alive(_pid: u32) -> bool {
+    false
+}
+
+fn state_path() -> io::Result<PathBuf> {
+    Ok(velo_data_dir()?.join("tunnels.json"))
+}
+
+/// Every recorded tunnel, including ones whose process has since died.
+pub fn load_tunnels() -> io::Result<Vec<Tunnel>> {
+    let path = state_path()?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(&path)?;
+    serde_json::from_str(&content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn save_tunnels(tunnels: &[Tunnel]) -> io::Result<()> {
+    let content = serde_json::to_string_pretty(tunnels)?;
+    atomic_write(&state_path()?, content.as_bytes())
+}
+
+/// Held while tunnels.json is read and rewritten, so concurrent velo
+/// processes do not drop each other's tunnels.
+fn tunnels_lock() -> io::Result<fd_lock::RwLock<File>> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(velo_data_dir()?.join("tunnels.lock"))?;
+    Ok(fd_lock::RwLock::new(file))
+}
+
+/// Drops tunnels whose process has exited and returns them.
+fn prune_tunnels() -> io::Result<(Vec<Tunnel>, Vec<Tunnel>)> {
+    let mut lock = tunnels_lock()?;
+    let _guard = lock.write()?;
+    prune_tunnels_locked()
+}
+
+/// `prune_tunnels` for a caller already holding `tunnels_lock`.
+fn prune_tunnels_locked() -> io::Result<(Vec<Tunnel>, Vec<Tunnel>)> {
+    let (running, stale): (Vec<Tunnel>, Vec<Tunnel>) =
+        load_tunnels()?.into_iter().partition(Tunnel::is_running);
+    if !stale.is_empty() {
+        save_tunnels(&running)?;
+    }
+    Ok((running, stale))
+}
+
+fn log_path(connection: &str, forward: Option<&str>) -> io::Result<PathBuf> {
+    let dir = velo_data_dir()?.join("tunnels");
+    fs::create_dir_all(&dir)?;
+    Ok(dir.join(format!("{}.log", tunnel_file_stem(connection, forward))))
+}
+
+fn tunnel_error(message: String) -> io::Error {
+    io::Error::other(message)
+}
+
+/// Starts `ssh -N` for the connection's forwards, or just `forward`.
+pub fn start_tunnel(connection: &str, forward: Option<&str>) -> io::Result<Tunnel> {
+    let ssh_config = SSHConfig::new()?;
+    let Some(block) = ssh_config.host_block(connection) else {
+        return Err(tunnel_error(format!(
+            "Connection '{}' not found",
+            connection
+        )));
+    };
+    let forwards: Vec<_> = block_forwards(block)
+        .into_iter()
+        .filter(|f| forward.is_none() || f.name.as_deref() == forward)
+        .collect();
+    if forwards.is_empty() {
+        return Err(tunnel_error(match forward {
+            Some(forward) => format!("No forward '{}' on '{}'", forward, connection),
+            None => format!("'{}' has no forwards to tunnel", connection),
+        }));
+    }
+
+    // Held until the new tunnel is saved, so two velo processes cannot both
+    // find the forward free and start it twice.
+    let mut lock = tunnels_lock()?;
+    let _guard = lock.write()?;
+    let (mut running, _) = prune_tunnels_locked()?;
+    let label = tunnel_label(connection, forward);
+    if let Some(tunnel) = running
+        .iter()
+        .find(|t| t.connection == connection && t.forward.as_deref() == forward)
+    {
+        return Err(tunnel_error(format!(
+            "Tunnel '{}' is already running (pid {})",
+            label, tunnel.pid
+        )));
+    }
+
+    let ports: Vec<u16> = forwards.iter().filter_map(|f| f.local_port()).collect();
+    for port in &ports {
+        if let Some(tunnel) = running.iter().find(|t| t.ports.contains(port)) {
+            return Err(tunnel_error(format!(
+                "Local port {} is used by tunnel '{}'",
+                port,
+                tunnel.label()
+            )));
+        }
+        if TcpListener::bind(("127.0.0.1", *port)).is_err() {
+            return Err(tunnel_error(format!(
+                "Local port {} is already in use",
+                port
+            )));
+        }
+    }
+
+    let selected: Vec<String> = forwards.iter().filter_map(|f| f.name.clone()).collect();
+    let config = forward_session_config(
+        connection,
+        &selected,
+        forward.is_none(),
+        &format!("tunnel-{}.conf", tunnel_file_stem(connection, forward)),
+    )?;
+
+    let log = log_path(connection, forward)?;
+    let mut log_file = OpenOptions::new().create(true).append(true).open(&log)?;
+    writeln!(log_file, "--- velo tunnel start {} at {} ---", label, now())?;
+
+    let mut command = Command::new("ssh");
+    command
+        .arg("-F")
+        .arg(&config)
+        .args([
+            "-N",
+            "-o",
+            "ExitOnForwardFailure=yes",
+            "-o",
+            "BatchMode=yes",
+        ])
+        .arg(connection)
+        .stdin(Stdio::null())
+        .stdout(log_file.try_clone()?)
+        .stderr(log_file);
+    // Its own process group, so closing the terminal does not take it down.
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    let mut child = command.spawn()?;
+
+    thread::sleep(STARTUP_GRACE);
+    if let Some(status) = child.try_wait()? {
+        return Err(tunnel_error(format!(
+            "ssh exited ({}); see 'velo tunnel logs {}{}'",
+            status,
+            connection,
+            forward.map(|f| format!(" {}", f)).unwrap_or_default()
+        )));
+    }
+
+    let tunnel = Tunnel {
+        connection: connection.to_string(),
+        forward: forward.map(str::to_string),
+        pid: child.id(),
+        ports,
+        started: now(),
+        log,
+    };
+    running.push(tunnel.clone());
+    save_tunnels(&running)?;
+    Ok(tunnel)
+}
+
+/// Stops the tunnel for `forward`, or every tunnel for `connection` when no
+/// forward is given. Returns the tunnels that were stopped.
+pub fn stop_tunnels(connection: &str, forward: Option<&str>) -> io::Result<Vec<Tunnel>> {
+    let mut lock = tunnels_lock()?;
+    let _guard = lock.write()?;
+    let (running, _): (Vec<Tunnel>, Vec<Tunnel>) =
+        load_tunnels()?.into_iter().partition(Tunnel::is_running);
+    let (stopped, kept): (Vec<Tunnel>, Vec<Tunnel>) = running.into_iter().partition(|tunnel| {
+        tunnel.connection == connection
+            && (forward.is_none() || tunnel.forward.as_deref() == forward)
+    });
+    for tunnel in &stopped {
+        #[cfg(unix)]
+        unsafe {
+            libc::kill(tunnel.pid as libc::pid_t, libc::SIGTERM);
+        }
+    }
+    save_tunnels(&kept)?;
+    Ok(stopped)
+}
+
+pub fn handle_tunnel(args: &[String]) -> io::Result<()> {
+    if args.is_empty() {
+        println!("Usage: velo tunnel <subcommand> [args...]");
+        println!("Subcommands: start, stop, list, logs");
+        return Ok(());
+    }
+
+    let subcommand = &args[0];
+    let rest_args = &args[1..];
+    let connection = rest_args.first();
+    let forward = rest_args.get(1).map(String::as_str);
+
+    match subcommand.as_str() {
+        "start" => {
+            let Some(connection) = connection else {
+                println!("Usage: velo tunnel start <connection> [forward]");
+                return Ok(());
+            };
+            match start_tunnel(connection, forward) {
+                Ok(tunnel) => println!(
+                    "Tunnel '{}' started (pid {}, ports {}).",
+                    tunnel.label(),
+                    tunnel.pid,
+                    tunnel.ports_text()
+                ),
+                Err(e) => println!("{}", e),
+            }
+        }
+        "stop" => {
+            let Some(connection) = connection else {
+                println!("Usage: velo tunnel stop <connection> [forward]");
+                return Ok(());
+            };
+            let stopped = stop_tunnels(connection, forward)?;
+            if stopped.is_empty() {
+                println!(
+                    "No running tunnel for '{}'",
+                    tunnel_label(connection, forward)
+                );
+            }
+            for tunnel in stopped {
+                println!("Tunnel '{}' stopped (pid {}).", tunnel.label(), tunnel.pid);
+            }
+        }
+        "list" | "ls" => {
+            let (running, stale) = prune_tunnels()?;
+            if running.is_empty() {
+                println!("No tunnels running.");
+            } else {
+                println!(
+                    "{:<16} {:<12} {:<8} {:<12} {:<8}",
+                    "CONNECTION", "FORWARD", "PID", "PORTS", "UPTIME"
+                );
+                for tunnel in &running {
+                    println!(
+                        "{:<16} {:<12} {:<8} {:<12} {:<8}",
+                        tunnel.connection,
+                        tunnel.forward.as_deref().unwrap_or("(all)"),
+                        tunnel.pid,
+                        tunnel.ports_text(),
+                        tunnel.uptime()
+                    );
+                }
+            }
+            for tunnel in stale {
+                println!(
+                    "Removed stale tunnel '{}' (pid {} has exited).",
+                    tunnel.label(),
+                    tunnel.pid
+                );
+            }
+        }
+        "logs" => {
+            let Some(connection) = connection else {
+                println!("Usage: velo tunnel logs <connection> [forward]");
+                return Ok(());
+            };
+            let log = log_path(connection, forward)?;
+            match fs::read_to_string(&log) {
+                Ok(content) => {
+                    let lines: Vec<&str> = content.lines().collect();
+                    for line in &lines[lines.len().saturating_sub(LOG_TAIL_LINES)..] {
+                        println!("{}", line);
+                    }
+                }
+                Err(_) => println!("No log for tunnel '{}'", tunnel_label(connection, forward)),
+            }
+        }
+        _ => println!("Unknown tunnel subcommand: {}", subcommand),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_stems_do_not_collide() {
+        let stems = [
+            tunnel_file_stem("web-db", None),
+            tunnel_file_stem("web", Some("db")),
+            tunnel_file_stem("web@db", None),
+            tunnel_file_stem("web", Some("db.local")),
+            tunnel_file_stem("web@db", Some("local")),
+        ];
+        for (i, stem) in stems.iter().enumerate() {
+            assert!(!stems[i + 1..].contains(stem), "{} repeats", stem);
+        }
+        assert_eq!(tunnel_file_stem("a/b", Some("x")), "a%2Fb@x");
+    }
+}
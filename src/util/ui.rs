@@ -1,6 +1,12 @@
 use std::io::{self, stdout};
 
-use crate::util::ssh::{get_connections, handle_add_connection, handle_ssh_from_tui};
+use crate::util::agent::lifetime_left;
+use crate::util::inventory::{key_inventory, KeyInventory};
+use crate::util::resolve::{describe_connection, display_path};
+use crate::util::ssh::{
+    get_connections, handle_add_connection, handle_edit_connection, handle_ssh_from_tui, SSHConfig,
+};
+use crate::util::tunnel::{load_tunnels, stop_tunnels, Tunnel};
 use ratatui::layout::Position;
 use ratatui::{
     backend::CrosstermBackend,
@@ -12,6 +18,7 @@ use ratatui::{
     layout::Flex,
     prelude::{Alignment, Constraint, Direction, Layout},
     style::{Color, Modifier, Style},
+    text::Line,
     widgets::{Block, BorderType, Borders, List, ListItem, ListState, Paragraph},
     Frame, Terminal,
 };
@@ -28,30 +35,148 @@ pub struct AppState {
     ssh_connections_state: ListState,
     ssh_connections: Vec<String>,
     input_mode: InputMode,
-    add_connection_form: AddConnectionForm,
+    connection_form: ConnectionForm,
+    // `velo show` output for the highlighted connection, keyed by its name.
+    connection_details: Option<(String, Vec<String>)>,
+    tunnels_state: ListState,
+    tunnels: Vec<Tunnel>,
+    keys_state: ListState,
+    // Read when the keys screen is first shown and on `r`; it runs ssh-add
+    // and ssh-keygen, too slow to redo every frame.
+    keys: Option<KeyInventory>,
 }
 
-struct AddConnectionForm {
+const FORM_FIELD_NAMES: [&str; 5] = ["[ NAME ]", "[ HOST ]", "[ USER ]", "[ PORT ]", "[ JUMP ]"];
+const FORM_DIRECTIVES: [&str; 5] = ["Host", "HostName", "User", "Port", "ProxyJump"];
+const JUMP_FIELD: usize = 4;
+
+struct ConnectionForm {
     fields: Vec<String>,
     current_field: usize,
     cursor_positions: Vec<usize>, // Track cursor position for each field
+    editing: Option<String>,      // Connection being edited, None when adding
+    original: Vec<String>,        // Field values the edit form started from
+    jump_choices: Vec<String>,    // Connections the jump picker cycles through
+    error: Option<String>,        // Why the last submit was refused
 }
 
-impl AddConnectionForm {
+impl ConnectionForm {
     fn new() -> Self {
         Self {
-            fields: vec![String::new(); 3], // 3 fields: name, host, user
+            fields: vec![String::new(); 5], // 5 fields: name, host, user, port, jump
             current_field: 0,
-            cursor_positions: vec![0; 3], // Cursor position for each field
+            cursor_positions: vec![0; 5], // Cursor position for each field
+            editing: None,
+            original: Vec::new(),
+            jump_choices: get_connections(),
+            error: None,
+        }
+    }
+
+    // Pre-fills the form from the connection's current Host block.
+    fn edit(name: &str) -> Self {
+        let mut fields = vec![
+            name.to_string(),
+            String::new(),
+            String::new(),
+            String::new(),
+            String::new(),
+        ];
+        if let Ok(ssh_config) = SSHConfig::new() {
+            if let Some(block) = ssh_config.host_block(name) {
+                for (i, directive) in FORM_DIRECTIVES.iter().enumerate().skip(1) {
+                    fields[i] = block.get(directive).unwrap_or_default().to_string();
+                }
+            }
+        }
+
+        Self {
+            cursor_positions: fields.iter().map(|f| f.chars().count()).collect(),
+            original: fields.clone(),
+            fields,
+            current_field: 1, // The name is fixed while editing
+            editing: Some(name.to_string()),
+            jump_choices: get_connections()
+                .into_iter()
+                .filter(|c| c != name)
+                .collect(),
+            error: None,
+        }
+    }
+
+    fn first_field(&self) -> usize {
+        if self.editing.is_some() {
+            1
+        } else {
+            0
         }
     }
 
     fn next_field(&mut self) {
         self.current_field = (self.current_field + 1) % self.fields.len();
+        self.current_field = self.current_field.max(self.first_field());
     }
 
     fn prev_field(&mut self) {
-        self.current_field = (self.current_field + self.fields.len() - 1) % self.fields.len();
+        self.current_field = if self.current_field == self.first_field() {
+            self.fields.len() - 1
+        } else {
+            self.current_field - 1
+        };
+    }
+
+    // Steps the jump field through "no jump" and each known connection.
+    fn cycle_jump(&mut self, forward: bool) {
+        self.error = None;
+        let count = self.jump_choices.len() + 1;
+        let current = self
+            .jump_choices
+            .iter()
+            .position(|c| *c == self.fields[JUMP_FIELD])
+            .map_or(0, |i| i + 1);
+        let next = if forward {
+            (current + 1) % count
+        } else {
+            (current + count - 1) % count
+        };
+        self.fields[JUMP_FIELD] = match next {
+            0 => String::new(),
+            i => self.jump_choices[i - 1].clone(),
+        };
+        self.cursor_positions[JUMP_FIELD] = self.fields[JUMP_FIELD].chars().count();
+    }
+
+    fn submit(&self) -> io::Result<()> {
+        let Some(name) = &self.editing else {
+            let mut args = self.fields[..JUMP_FIELD].to_vec();
+            let jump = self.fields[JUMP_FIELD].trim();
+            if !jump.is_empty() {
+                args.push("--jump".to_string());
+                args.push(jump.to_string());
+            }
+            return handle_add_connection(&args);
+        };
+
+        // Only pass on what changed so the rest of the block stays as it is.
+        let mut args = vec![name.clone()];
+        let fields = FORM_DIRECTIVES.iter().zip(&self.fields).zip(&self.original);
+        for ((directive, value), original) in fields.skip(1) {
+            let value = value.trim();
+            if value == original {
+                continue;
+            }
+            if value.is_empty() {
+                args.push("--unset".to_string());
+                args.push(directive.to_string());
+            } else {
+                args.push("--set".to_string());
+                args.push(format!("{}={}", directive, value));
+            }
+        }
+        if args.len() == 1 {
+            return Ok(());
+        }
+        handle_edit_connection(&args)
     }
 
     fn move_cursor_left(&mut self) {
@@ -66,6 +191,7 @@ impl AddConnectionForm {
     }
 
     fn enter_char(&mut self, c: char) {
+        self.error = None;
         let field = &mut self.fields[self.current_field];
         let cursor_pos = self.cursor_positions[self.current_field];
 
@@ -81,6 +207,7 @@ impl AddConnectionForm {
     }
 
     fn delete_char(&mut self) {
+        self.error = None;
         let field = &mut self.fields[self.current_field];
         let cursor_pos = &mut self.cursor_positions[self.current_field];
 
@@ -108,7 +235,86 @@ impl AppState {
             ssh_connections_state: ListState::default(),
             ssh_connections: get_connections(),
             input_mode: InputMode::Normal,
-            add_connection_form: AddConnectionForm::new(),
+            connection_form: ConnectionForm::new(),
+            connection_details: None,
+            tunnels_state: ListState::default(),
+            tunnels: Vec::new(),
+            keys_state: ListState::default(),
+            keys: None,
+        }
+    }
+
+    // The list in the right-hand pane that Up/Down move through.
+    fn details_list(&mut self) -> (&mut ListState, usize) {
+        if self.main_menu_state.selected() == Some(4) {
+            (&mut self.tunnels_state, self.tunnels.len())
+        } else if self.main_menu_state.selected() == Some(5) {
+            let len = self.keys.as_ref().map_or(0, |keys| keys.keys.len());
+            (&mut self.keys_state, len)
+        } else {
+            (&mut self.ssh_connections_state, self.ssh_connections.len())
+        }
+    }
+
+    /// The key inventory, read on first use.
+    fn key_inventory(&mut self) -> &KeyInventory {
+        self.keys
+            .get_or_insert_with(|| key_inventory().unwrap_or_default())
+    }
+
+    /// What the keys screen shows under the list for the highlighted key.
+    fn selected_key_details(&mut self) -> Vec<String> {
+        let selected = self.keys_state.selected();
+        let inventory = self.key_inventory();
+        let mut details = Vec::new();
+        if let Some(key) = selected.and_then(|i| inventory.keys.get(i)) {
+            details.push(format!("Key:         {}", display_path(&key.path)));
+            details.push(format!("Type:        {}", key.type_text()));
+            details.push(format!("Fingerprint: {}", key.fingerprint));
+            details.push(format!("Comment:     {}", key.comment));
+            details.push(format!(
+                "Encrypted:   {}",
+                if key.encrypted { "yes" } else { "no" }
+            ));
+            details.push(format!("In agent:    {}", key.agent_text()));
+            details.push(format!("Used by:     {}", key.hosts_text()));
+            details.push(String::new());
+        }
+        if !inventory.agent_running {
+            details.push("ssh-agent is not running.".to_string());
+        }
+        if !inventory.dangling.is_empty() {
+            details.push("IdentityFile entries pointing at missing keys:".to_string());
+            for identity in &inventory.dangling {
+                details.push(format!(
+                    "  {} in {} ({})",
+                    display_path(&identity.path),
+                    identity.block,
+                    identity.origin
+                ));
+            }
+        }
+        details
+    }
+
+    /// Details for the highlighted connection, only re-resolved when the
+    /// selection changes.
+    fn selected_details(&mut self) -> &[String] {
+        let selected = self
+            .ssh_connections_state
+            .selected()
+            .and_then(|i| self.ssh_connections.get(i))
+            .cloned();
+        let Some(name) = selected else {
+            return &[];
+        };
+        if self.connection_details.as_ref().map(|(cached, _)| cached) != Some(&name) {
+            let details = describe_connection(&name).unwrap_or_else(|e| vec![e.to_string()]);
+            self.connection_details = Some((name, details));
+        }
+        match &self.connection_details {
+            Some((_, details)) => details,
+            None => &[],
         }
     }
 }
@@ -139,26 +345,56 @@ fn handle_events(app_state: &mut AppState) -> io::Result<bool> {
                 match app_state.input_mode {
                     InputMode::Normal => match key.code {
                         KeyCode::Char('q') => return Ok(true),
+                        KeyCode::Char('e')
+                            if app_state.focused_section == 1
+                                && app_state.main_menu_state.selected() == Some(0) =>
+                        {
+                            if let Some(selected_connection) = app_state
+                                .ssh_connections_state
+                                .selected()
+                                .and_then(|i| app_state.ssh_connections.get(i))
+                            {
+                                app_state.connection_form =
+                                    ConnectionForm::edit(selected_connection);
+                                app_state.input_mode = InputMode::Editing;
+                            }
+                        }
+                        KeyCode::Char('s')
+                            if app_state.focused_section == 1
+                                && app_state.main_menu_state.selected() == Some(4) =>
+                        {
+                            if let Some(tunnel) = app_state
+                                .tunnels_state
+                                .selected()
+                                .and_then(|i| app_state.tunnels.get(i))
+                            {
+                                let _ = stop_tunnels(&tunnel.connection, tunnel.forward.as_deref());
+                            }
+                        }
+                        KeyCode::Char('r')
+                            if app_state.focused_section == 1
+                                && app_state.main_menu_state.selected() == Some(5) =>
+                        {
+                            app_state.keys = None;
+                        }
                         KeyCode::Up => {
                             if app_state.focused_section == 0 {
                                 let i = app_state.main_menu_state.selected().unwrap_or(0);
                                 app_state.main_menu_state.select(Some(i.saturating_sub(1)));
                             } else {
-                                let i = app_state.ssh_connections_state.selected().unwrap_or(0);
-                                app_state
-                                    .ssh_connections_state
-                                    .select(Some(i.saturating_sub(1)));
+                                let (list_state, _) = app_state.details_list();
+                                let i = list_state.selected().unwrap_or(0);
+                                list_state.select(Some(i.saturating_sub(1)));
                             }
                         }
                         KeyCode::Down => {
                             if app_state.focused_section == 0 {
                                 let i = app_state.main_menu_state.selected().unwrap_or(0);
-                                app_state.main_menu_state.select(Some((i + 1).min(4)));
+                                app_state.main_menu_state.select(Some((i + 1).min(5)));
                             } else {
-                                let i = app_state.ssh_connections_state.selected().unwrap_or(0);
-                                app_state.ssh_connections_state.select(Some(
-                                    (i + 1).min(app_state.ssh_connections.len().saturating_sub(1)),
-                                ));
+                                let (list_state, len) = app_state.details_list();
+                                let i = list_state.selected().unwrap_or(0);
+                                list_state.select(Some((i + 1).min(len.saturating_sub(1))));
                             }
                         }
                         KeyCode::Tab => {
@@ -197,38 +433,51 @@ fn handle_events(app_state: &mut AppState) -> io::Result<bool> {
                     InputMode::Editing => match key.code {
                         KeyCode::Esc => {
                             app_state.input_mode = InputMode::Normal;
-                            app_state.focused_section = 0;
+                            if app_state.connection_form.editing.is_none() {
+                                app_state.focused_section = 0;
+                            }
+                            app_state.connection_form = ConnectionForm::new();
                         }
                         KeyCode::Enter => {
-                            if app_state.add_connection_form.current_field == 2 {
-                                let args: Vec<String> =
-                                    app_state.add_connection_form.fields.clone();
-                                if handle_add_connection(&args).is_ok() {
-                                    app_state.ssh_connections = get_connections();
-                                    app_state.input_mode = InputMode::Normal;
-                                    app_state.add_connection_form = AddConnectionForm::new();
+                            let form = &mut app_state.connection_form;
+                            if form.current_field == form.fields.len() - 1 {
+                                match form.submit() {
+                                    Ok(()) => {
+                                        app_state.ssh_connections = get_connections();
+                                        app_state.connection_details = None;
+                                        app_state.input_mode = InputMode::Normal;
+                                        app_state.connection_form = ConnectionForm::new();
+                                    }
+                                    Err(e) => form.error = Some(e.to_string()),
                                 }
                             } else {
-                                app_state.add_connection_form.next_field();
+                                app_state.connection_form.next_field();
                             }
                         }
                         KeyCode::Backspace => {
-                            app_state.add_connection_form.delete_char();
+                            app_state.connection_form.delete_char();
                         }
                         KeyCode::Char(c) => {
-                            app_state.add_connection_form.enter_char(c);
+                            app_state.connection_form.enter_char(c);
+                        }
+                        KeyCode::Left | KeyCode::Right
+                            if app_state.connection_form.current_field == JUMP_FIELD =>
+                        {
+                            app_state
+                                .connection_form
+                                .cycle_jump(key.code == KeyCode::Right);
                         }
                         KeyCode::Left => {
-                            app_state.add_connection_form.move_cursor_left();
+                            app_state.connection_form.move_cursor_left();
                         }
                         KeyCode::Right => {
-                            app_state.add_connection_form.move_cursor_right();
+                            app_state.connection_form.move_cursor_right();
                         }
                         KeyCode::Tab | KeyCode::Down => {
-                            app_state.add_connection_form.next_field();
+                            app_state.connection_form.next_field();
                         }
                         KeyCode::BackTab | KeyCode::Up => {
-                            app_state.add_connection_form.prev_field();
+                            app_state.connection_form.prev_field();
                         }
                         _ => {}
                     },
@@ -288,6 +537,8 @@ fn ui(frame: &mut Frame, app_state: &mut AppState) {
         ListItem::new("[ ZELLIJ ]").style(Style::default().fg(NEON_GREEN)),
         ListItem::new("[ ADD CONNECTION ]").style(Style::default().fg(NEON_GREEN)),
         ListItem::new("[ ADD KEY ]").style(Style::default().fg(NEON_GREEN)),
+        ListItem::new("[ TUNNELS ]").style(Style::default().fg(NEON_GREEN)),
+        ListItem::new("[ KEYS ]").style(Style::default().fg(NEON_GREEN)),
     ];
 
     let main_menu_block = Block::new()
@@ -322,12 +573,13 @@ fn ui(frame: &mut Frame, app_state: &mut AppState) {
 
     // Details section with flex
     match app_state.main_menu_state.selected() {
-        Some(0) => {
+        Some(0) if app_state.connection_form.editing.is_none() => {
             // SSH Connections list
             let connections_block = Block::new()
                 .borders(Borders::ALL)
                 .border_type(BorderType::Double)
                 .title("[ SSH_CONNECTIONS ]")
+                .title_bottom("[ ENTER: connect | e: edit ]")
                 .title_alignment(Alignment::Center)
                 .border_style(
                     Style::default()
@@ -358,18 +610,45 @@ fn ui(frame: &mut Frame, app_state: &mut AppState) {
                 )
                 .highlight_symbol(">> ");
 
+            let ssh_layout = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .split(content_layout[1]);
+
             frame.render_stateful_widget(
                 connections_list,
-                content_layout[1],
+                ssh_layout[0],
                 &mut app_state.ssh_connections_state,
             );
+
+            let details: Vec<Line> = app_state
+                .selected_details()
+                .iter()
+                .map(|line| Line::from(line.clone()))
+                .collect();
+            let details_pane = Paragraph::new(details)
+                .style(Style::default().fg(NEON_GREEN))
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .border_type(BorderType::Double)
+                        .title("[ DETAILS ]")
+                        .title_alignment(Alignment::Center)
+                        .border_style(Style::default().fg(DARKER_GREEN)),
+                );
+            frame.render_widget(details_pane, ssh_layout[1]);
         }
-        Some(3) => {
-            // Add Connection Form
+        Some(0) | Some(3) => {
+            // Add/Edit Connection Form
+            let form_title = if app_state.connection_form.editing.is_some() {
+                "[ EDIT_CONNECTION ]"
+            } else {
+                "[ ADD_CONNECTION ]"
+            };
             let form_block = Block::default()
                 .borders(Borders::ALL)
                 .border_type(BorderType::Double)
-                .title("[ ADD_CONNECTION ]")
+                .title(form_title)
                 .title_alignment(Alignment::Center)
                 .border_style(Style::default().fg(DARKER_GREEN));
 
@@ -390,17 +669,15 @@ fn ui(frame: &mut Frame, app_state: &mut AppState) {
             let fields_layout = Layout::default()
                 .direction(Direction::Vertical)
                 .flex(Flex::SpaceAround)
-                .constraints([
-                    Constraint::Length(3),
-                    Constraint::Length(3),
-                    Constraint::Length(3),
+                .constraints(vec![
+                    Constraint::Length(3);
+                    app_state.connection_form.fields.len()
                 ])
                 .split(form_layout[0]);
 
-            let field_names = ["[ NAME ]", "[ HOST ]", "[ USER ]"];
-            for (i, field) in app_state.add_connection_form.fields.iter().enumerate() {
+            for (i, field) in app_state.connection_form.fields.iter().enumerate() {
                 let is_active = app_state.input_mode == InputMode::Editing
-                    && app_state.add_connection_form.current_field == i;
+                    && app_state.connection_form.current_field == i;
 
                 // Row layout with flex
                 let row = Layout::default()
@@ -413,7 +690,7 @@ fn ui(frame: &mut Frame, app_state: &mut AppState) {
                     ])
                     .split(fields_layout[i]);
 
-                let label = Paragraph::new(field_names[i])
+                let label = Paragraph::new(FORM_FIELD_NAMES[i])
                     .style(Style::default().fg(if is_active { HIGHLIGHT } else { DARKER_GREEN }))
                     .alignment(Alignment::Right);
 
@@ -439,24 +716,179 @@ fn ui(frame: &mut Frame, app_state: &mut AppState) {
 
                 if is_active && app_state.input_mode == InputMode::Editing {
                     frame.set_cursor_position(Position::new(
-                        row[1].x + app_state.add_connection_form.cursor_positions[i] as u16 + 1,
+                        row[1].x + app_state.connection_form.cursor_positions[i] as u16 + 1,
                         row[1].y + 1,
                     ));
                 }
             }
 
-            // Helper text
-            let helper_text = if app_state.input_mode == InputMode::Editing {
+            // Helper text, or why the last submit was refused
+            let helper_text = if let Some(error) = &app_state.connection_form.error {
+                format!("[ {} ]", error)
+            } else if app_state.input_mode == InputMode::Editing
+                && app_state.connection_form.current_field == JUMP_FIELD
+            {
+                "[ LEFT/RIGHT: pick jump host | TAB: next field | ENTER: submit | ESC: cancel ]"
+                    .to_string()
+            } else if app_state.input_mode == InputMode::Editing {
                 "[ TAB: next field | SHIFT+TAB: prev field | ENTER: submit | ESC: cancel ]"
+                    .to_string()
             } else {
-                "[ ENTER: start editing ]"
+                "[ ENTER: start editing ]".to_string()
             };
 
             let helper = Paragraph::new(helper_text)
-                .style(Style::default().fg(DARKER_GREEN))
+                .style(
+                    Style::default().fg(if app_state.connection_form.error.is_some() {
+                        HIGHLIGHT
+                    } else {
+                        DARKER_GREEN
+                    }),
+                )
                 .alignment(Alignment::Center);
             frame.render_widget(helper, form_layout[1]);
         }
+        Some(4) => {
+            // Background tunnels, re-read every frame so their state stays current
+            app_state.tunnels = load_tunnels().unwrap_or_default();
+
+            let tunnels_block = Block::new()
+                .borders(Borders::ALL)
+                .border_type(BorderType::Double)
+                .title("[ TUNNELS ]")
+                .title_bottom("[ s: stop ]")
+                .title_alignment(Alignment::Center)
+                .border_style(
+                    Style::default()
+                        .fg(if app_state.focused_section == 1 {
+                            HIGHLIGHT
+                        } else {
+                            DARKER_GREEN
+                        })
+                        .add_modifier(if app_state.focused_section == 1 {
+                            Modifier::BOLD
+                        } else {
+                            Modifier::empty()
+                        }),
+                );
+
+            let tunnels: Vec<ListItem> = app_state
+                .tunnels
+                .iter()
+                .map(|tunnel| {
+                    let (state, color) = if tunnel.is_running() {
+                        ("RUNNING", NEON_GREEN)
+                    } else {
+                        ("EXITED", DARKER_GREEN)
+                    };
+                    ListItem::new(format!(
+                        "< {} > pid {} | ports {} | up {} | {}",
+                        tunnel.label(),
+                        tunnel.pid,
+                        tunnel.ports_text(),
+                        tunnel.uptime(),
+                        state
+                    ))
+                    .style(Style::default().fg(color))
+                })
+                .collect();
+
+            let tunnels_list = List::new(tunnels)
+                .block(tunnels_block)
+                .highlight_style(
+                    Style::default()
+                        .fg(HIGHLIGHT)
+                        .add_modifier(Modifier::BOLD | Modifier::RAPID_BLINK),
+                )
+                .highlight_symbol(">> ");
+
+            frame.render_stateful_widget(
+                tunnels_list,
+                content_layout[1],
+                &mut app_state.tunnels_state,
+            );
+        }
+        Some(5) => {
+            let details: Vec<Line> = app_state
+                .selected_key_details()
+                .into_iter()
+                .map(Line::from)
+                .collect();
+
+            let keys_block = Block::new()
+                .borders(Borders::ALL)
+                .border_type(BorderType::Double)
+                .title("[ SSH_KEYS ]")
+                .title_bottom("[ r: refresh ]")
+                .title_alignment(Alignment::Center)
+                .border_style(
+                    Style::default()
+                        .fg(if app_state.focused_section == 1 {
+                            HIGHLIGHT
+                        } else {
+                            DARKER_GREEN
+                        })
+                        .add_modifier(if app_state.focused_section == 1 {
+                            Modifier::BOLD
+                        } else {
+                            Modifier::empty()
+                        }),
+                );
+
+            let keys: Vec<ListItem> = app_state
+                .key_inventory()
+                .keys
+                .iter()
+                .map(|key| {
+                    let color = if key.orphaned {
+                        DARKER_GREEN
+                    } else {
+                        NEON_GREEN
+                    };
+                    let agent = match lifetime_left(key.agent_expires) {
+                        Some(left) if key.in_agent => format!("IN AGENT {}", left.to_uppercase()),
+                        _ if key.in_agent => "IN AGENT".to_string(),
+                        _ => "-".to_string(),
+                    };
+                    ListItem::new(format!(
+                        "< {} > {} | {} | {}",
+                        display_path(&key.path),
+                        key.type_text(),
+                        agent,
+                        key.hosts_text()
+                    ))
+                    .style(Style::default().fg(color))
+                })
+                .collect();
+
+            let keys_list = List::new(keys)
+                .block(keys_block)
+                .highlight_style(
+                    Style::default()
+                        .fg(HIGHLIGHT)
+                        .add_modifier(Modifier::BOLD | Modifier::RAPID_BLINK),
+                )
+                .highlight_symbol(">> ");
+
+            let keys_layout = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .split(content_layout[1]);
+
+            frame.render_stateful_widget(keys_list, keys_layout[0], &mut app_state.keys_state);
+
+            let details_pane = Paragraph::new(details)
+                .style(Style::default().fg(NEON_GREEN))
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .border_type(BorderType::Double)
+                        .title("[ DETAILS ]")
+                        .title_alignment(Alignment::Center)
+                        .border_style(Style::default().fg(DARKER_GREEN)),
+                );
+            frame.render_widget(details_pane, keys_layout[1]);
+        }
         _ => {
             frame.render_widget(
                 Block::default()
@@ -0,0 +1,583 @@
+// The unlock agent: a copy of velo left running in the background by
+// `velo unlock`, much like ssh-agent, that holds the keys of the vault and
+// the file keyring so other velo commands need no master password. It
+// listens on a socket only the user can open, keeps the keys in memory
+// locked against swapping, and forgets them and exits after a stretch of
+// inactivity or on `velo lock`. The keys it holds are derived keys, never
+// the master passwords themselves.
+
+use crate::util::keyring::FileKeyring;
+use crate::util::ssh::velo_data_dir;
+use crate::util::vault::{invalid, prompt_password, Header, Vault, KEY_LEN};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+use zeroize::Zeroizing;
+
+#[cfg(unix)]
+use std::collections::HashMap;
+#[cfg(unix)]
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::time::Instant;
+#[cfg(unix)]
+use zeroize::Zeroize;
+
+/// Set, to the idle timeout in seconds, when velo is started as the agent.
+const AGENT_ENV: &str = "VELO_UNLOCK_AGENT";
+
+const DEFAULT_TIMEOUT_MINUTES: u64 = 15;
+
+// How long either side waits on the other before giving up.
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// One request per connection, as a line of JSON.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "lowercase")]
+enum Request {
+    /// The key for the file `name` whose header is `header`.
+    Get {
+        name: String,
+        header: String,
+    },
+    Put {
+        name: String,
+        header: String,
+        key: Zeroizing<String>,
+    },
+    Status,
+    Lock,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "response", rename_all = "lowercase")]
+enum Response {
+    Key { key: Option<Zeroizing<String>> },
+    Done,
+    Status(AgentStatus),
+}
+
+#[derive(Serialize, Deserialize)]
+struct AgentStatus {
+    pid: u32,
+    /// Names of the files whose keys are held.
+    unlocked: Vec<String>,
+    idle_timeout: u64,
+    idle: u64,
+    memory_locked: bool,
+}
+
+fn socket_path() -> io::Result<PathBuf> {
+    Ok(velo_data_dir()?.join("unlock.sock"))
+}
+
+#[cfg(unix)]
+fn request(request: &Request) -> io::Result<Response> {
+    let mut stream = UnixStream::connect(socket_path()?)?;
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    let mut line = Zeroizing::new(serde_json::to_string(request)?);
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+
+    let mut reply = Zeroizing::new(String::new());
+    BufReader::new(stream).read_line(&mut reply)?;
+    serde_json::from_str(&reply).map_err(invalid)
+}
+
+#[cfg(not(unix))]
+fn request(_request: &Request) -> io::Result<Response> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "The unlock agent needs Unix sockets",
+    ))
+}
+
+/// The key the agent holds for the file `name` with `header`, if the agent
+/// is running and has one.
+pub fn cached_key(name: &str, header: &Header) -> Option<Zeroizing<[u8; KEY_LEN]>> {
+    let header = serde_json::to_string(header).ok()?;
+    let Response::Key { key: Some(key) } = request(&Request::Get {
+        name: name.to_string(),
+        header,
+    })
+    .ok()?
+    else {
+        return None;
+    };
+    decode_key(&key)
+}
+
+/// Hands a key to the agent if it is running; otherwise does nothing.
+pub fn cache_key(name: &str, header: &Header, key: &[u8; KEY_LEN]) {
+    let Ok(header) = serde_json::to_string(header) else {
+        return;
+    };
+    let _ = request(&Request::Put {
+        name: name.to_string(),
+        header,
+        key: Zeroizing::new(BASE64.encode(key)),
+    });
+}
+
+fn decode_key(encoded: &str) -> Option<Zeroizing<[u8; KEY_LEN]>> {
+    let bytes = Zeroizing::new(BASE64.decode(encoded.as_bytes()).ok()?);
+    let mut key = Zeroizing::new([0u8; KEY_LEN]);
+    if bytes.len() != KEY_LEN {
+        return None;
+    }
+    key.copy_from_slice(&bytes);
+    Some(key)
+}
+
+fn agent_status() -> Option<AgentStatus> {
+    match request(&Request::Status).ok()? {
+        Response::Status(status) => Some(status),
+        _ => None,
+    }
+}
+
+/// If velo was started as the unlock agent, serves until locked or idle and
+/// returns the exit status to end with.
+pub fn run_unlock_agent() -> Option<i32> {
+    let timeout = env::var(AGENT_ENV).ok()?.parse::<u64>().ok()?;
+    Some(match serve(Duration::from_secs(timeout)) {
+        Ok(()) => 0,
+        Err(_) => 1,
+    })
+}
+
+/// A key held by the agent, pinned in memory so it is never swapped out.
+#[cfg(unix)]
+struct HeldKey {
+    header: String,
+    key: Box<Zeroizing<[u8; KEY_LEN]>>,
+    locked: bool,
+}
+
+#[cfg(unix)]
+impl HeldKey {
+    fn new(header: String, key: Zeroizing<[u8; KEY_LEN]>) -> HeldKey {
+        let key = Box::new(key);
+        let locked = unsafe { libc::mlock(key.as_ptr().cast(), KEY_LEN) } == 0;
+        HeldKey {
+            header,
+            key,
+            locked,
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for HeldKey {
+    fn drop(&mut self) {
+        Zeroize::zeroize(&mut *self.key);
+        if self.locked {
+            unsafe { libc::munlock(self.key.as_ptr().cast(), KEY_LEN) };
+        }
+    }
+}
+
+#[cfg(unix)]
+struct Agent {
+    keys: HashMap<String, HeldKey>,
+    timeout: Duration,
+    last_used: Instant,
+}
+
+#[cfg(unix)]
+impl Agent {
+    /// Answers one connection. Returns false once the agent should exit.
+    fn handle(&mut self, stream: UnixStream) -> io::Result<bool> {
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+        let mut line = Zeroizing::new(String::new());
+        BufReader::new(&stream).read_line(&mut line)?;
+        let Ok(request) = serde_json::from_str::<Request>(&line) else {
+            return Ok(true);
+        };
+
+        let mut keep_running = true;
+        let response = match request {
+            Request::Get { name, header } => {
+                self.last_used = Instant::now();
+                let key = self
+                    .keys
+                    .get(&name)
+                    .filter(|held| held.header == header)
+                    .map(|held| Zeroizing::new(BASE64.encode(held.key.as_ref())));
+                Response::Key { key }
+            }
+            Request::Put { name, header, key } => {
+                self.last_used = Instant::now();
+                if let Some(key) = decode_key(&key) {
+                    self.keys.insert(name, HeldKey::new(header, key));
+                }
+                Response::Done
+            }
+            Request::Status => {
+                let mut unlocked: Vec<String> = self.keys.keys().cloned().collect();
+                unlocked.sort();
+                Response::Status(AgentStatus {
+                    pid: std::process::id(),
+                    unlocked,
+                    idle_timeout: self.timeout.as_secs(),
+                    idle: self.last_used.elapsed().as_secs(),
+                    memory_locked: self.keys.values().all(|held| held.locked),
+                })
+            }
+            Request::Lock => {
+                self.keys.clear();
+                keep_running = false;
+                Response::Done
+            }
+        };
+
+        let mut reply = Zeroizing::new(serde_json::to_string(&response)?);
+        reply.push('\n');
+        (&stream).write_all(reply.as_bytes())?;
+        Ok(keep_running)
+    }
+}
+
+#[cfg(unix)]
+fn serve(timeout: Duration) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    // Nothing the agent creates is for anyone else, and its memory must not
+    // end up in a core dump.
+    unsafe { libc::umask(0o077) };
+    #[cfg(target_os = "linux")]
+    unsafe {
+        libc::prctl(libc::PR_SET_DUMPABLE, 0);
+    }
+
+    let path = socket_path()?;
+    if UnixStream::connect(&path).is_ok() {
+        // Another agent is already serving.
+        return Ok(());
+    }
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+
+    let mut agent = Agent {
+        keys: HashMap::new(),
+        timeout,
+        last_used: Instant::now(),
+    };
+    loop {
+        let remaining = timeout.saturating_sub(agent.last_used.elapsed());
+        if remaining.is_zero() {
+            break;
+        }
+        if !wait_for_connection(&listener, remaining)? {
+            continue;
+        }
+        let Ok((stream, _)) = listener.accept() else {
+            continue;
+        };
+        match agent.handle(stream) {
+            Ok(true) | Err(_) => {}
+            Ok(false) => break,
+        }
+    }
+
+    agent.keys.clear();
+    let _ = fs::remove_file(&path);
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn serve(_timeout: Duration) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "The unlock agent needs Unix sockets",
+    ))
+}
+
+/// Waits up to `timeout` for a connection; false if none came.
+#[cfg(unix)]
+fn wait_for_connection(listener: &UnixListener, timeout: Duration) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut fd = libc::pollfd {
+        fd: listener.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let millis = timeout.as_millis().min(i32::MAX as u128) as i32;
+    let ready = unsafe { libc::poll(&mut fd, 1, millis) };
+    if ready < 0 {
+        let error = io::Error::last_os_error();
+        if error.kind() == io::ErrorKind::Interrupted {
+            return Ok(false);
+        }
+        return Err(error);
+    }
+    Ok(ready > 0)
+}
+
+/// Starts the agent in the background and waits for it to answer.
+#[cfg(unix)]
+fn start_agent(timeout: Duration) -> io::Result<()> {
+    use std::os::unix::process::CommandExt;
+    use std::process::{Command, Stdio};
+
+    Command::new(env::current_exe()?)
+        .env(AGENT_ENV, timeout.as_secs().to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        // Out of the terminal's process group, so Ctrl-C does not reach it.
+        .process_group(0)
+        .spawn()?;
+    for _ in 0..50 {
+        if agent_status().is_some() {
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    Err(io::Error::other("The unlock agent did not start"))
+}
+
+#[cfg(not(unix))]
+fn start_agent(_timeout: Duration) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "The unlock agent needs Unix sockets",
+    ))
+}
+
+pub fn handle_unlock(args: &[String]) -> io::Result<()> {
+    let minutes = match args {
+        [] => DEFAULT_TIMEOUT_MINUTES,
+        [flag, minutes] if flag == "--timeout" => match minutes.parse::<u64>() {
+            Ok(minutes) if minutes > 0 => minutes,
+            _ => {
+                println!("The timeout must be a whole number of minutes.");
+                return Ok(());
+            }
+        },
+        _ => {
+            println!("Usage: velo unlock [--timeout <minutes>]");
+            return Ok(());
+        }
+    };
+
+    let keyring = FileKeyring::new()?;
+    if !Vault::exists()? && !keyring.exists() {
+        println!("There is nothing to unlock: no vault or keyring file exists yet.");
+        return Ok(());
+    }
+
+    let status = match agent_status() {
+        Some(status) => status,
+        None => {
+            start_agent(Duration::from_secs(minutes * 60))?;
+            agent_status().ok_or_else(|| io::Error::other("The unlock agent stopped"))?
+        }
+    };
+
+    // The keyring often shares the vault's master password; try it first.
+    let mut typed: Option<Zeroizing<String>> = None;
+    let mut unlocked = Vec::new();
+    if Vault::exists()? {
+        Vault::open(|| {
+            let password = prompt_password("Vault master password: ")?;
+            typed = Some(password.clone());
+            Ok(password)
+        })?;
+        unlocked.push("vault");
+    }
+    if keyring.exists() {
+        keyring.unlock(typed.as_deref().map(String::as_str))?;
+        unlocked.push("keyring");
+    }
+
+    println!(
+        "Unlocked the {}; it locks again after {} without use.",
+        unlocked.join(" and "),
+        format_minutes(status.idle_timeout / 60)
+    );
+    Ok(())
+}
+
+pub fn handle_lock(_args: &[String]) -> io::Result<()> {
+    if agent_status().is_none() {
+        println!("The unlock agent is not running.");
+        return Ok(());
+    }
+    request(&Request::Lock)?;
+    println!("Locked; the unlock agent has forgotten its keys.");
+    Ok(())
+}
+
+pub fn handle_status(_args: &[String]) -> io::Result<()> {
+    let Some(status) = agent_status() else {
+        println!("Locked: the unlock agent is not running.");
+        return Ok(());
+    };
+
+    println!("Unlock agent running (pid {}).", status.pid);
+    if status.unlocked.is_empty() {
+        println!("Nothing is unlocked.");
+    } else {
+        let names: Vec<&str> = status
+            .unlocked
+            .iter()
+            .map(|name| name.strip_prefix("velo-").unwrap_or(name))
+            .collect();
+        println!("Unlocked: {}", names.join(", "));
+    }
+    let left = status.idle_timeout.saturating_sub(status.idle);
+    println!(
+        "Locks after {} without use ({}:{:02} left).",
+        format_minutes(status.idle_timeout / 60),
+        left / 60,
+        left % 60
+    );
+    if !status.memory_locked {
+        println!("Warning: the keys could not be locked in memory and may be swapped to disk.");
+    }
+    Ok(())
+}
+
+fn format_minutes(n: u64) -> String {
+    if n == 1 {
+        "1 minute".to_string()
+    } else {
+        format!("{} minutes", n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn request_lines() {
+        let get = Request::Get {
+            name: "vault".to_string(),
+            header: "{}".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_value(&get).unwrap(),
+            json!({"request": "get", "name": "vault", "header": "{}"})
+        );
+        assert_eq!(
+            serde_json::to_string(&Request::Lock).unwrap(),
+            r#"{"request":"lock"}"#
+        );
+        let put: Request =
+            serde_json::from_str(r#"{"request":"put","name":"keyring","header":"h","key":"k"}"#)
+                .unwrap();
+        let Request::Put { name, key, .. } = put else {
+            panic!("not a put request");
+        };
+        assert_eq!((name.as_str(), key.as_str()), ("keyring", "k"));
+        assert!(serde_json::from_str::<Request>(r#"{"request":"dump"}"#).is_err());
+    }
+
+    #[test]
+    fn response_lines() {
+        assert_eq!(
+            serde_json::to_value(Response::Key { key: None }).unwrap(),
+            json!({"response": "key", "key": null})
+        );
+        let status = Response::Status(AgentStatus {
+            pid: 42,
+            unlocked: vec!["vault".to_string()],
+            idle_timeout: 900,
+            idle: 3,
+            memory_locked: true,
+        });
+        let line = serde_json::to_string(&status).unwrap();
+        let Response::Status(status) = serde_json::from_str(&line).unwrap() else {
+            panic!("not a status: {}", line);
+        };
+        assert_eq!((status.pid, status.idle_timeout), (42, 900));
+        assert_eq!(status.unlocked, ["vault"]);
+    }
+
+    #[test]
+    fn decodes_only_whole_keys() {
+        let key = [7u8; KEY_LEN];
+        assert_eq!(*decode_key(&BASE64.encode(key)).unwrap(), key);
+        assert!(decode_key(&BASE64.encode([7u8; KEY_LEN - 1])).is_none());
+        assert!(decode_key("not base64!").is_none());
+    }
+
+    /// Sends `request` to `agent` over a socket pair, as `request()` would.
+    #[cfg(unix)]
+    fn exchange(agent: &mut Agent, request: &Request) -> (Option<Response>, bool) {
+        let (client, server) = UnixStream::pair().unwrap();
+        let mut line = serde_json::to_string(request).unwrap();
+        line.push('\n');
+        (&client).write_all(line.as_bytes()).unwrap();
+        let keep_running = agent.handle(server).unwrap();
+        let mut reply = String::new();
+        BufReader::new(&client).read_line(&mut reply).unwrap();
+        (serde_json::from_str(&reply).ok(), keep_running)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn agent_answers_by_name_and_header() {
+        let mut agent = Agent {
+            keys: HashMap::new(),
+            timeout: Duration::from_secs(60),
+            last_used: Instant::now(),
+        };
+        let key = BASE64.encode([9u8; KEY_LEN]);
+        let get = |header: &str| Request::Get {
+            name: "vault".to_string(),
+            header: header.to_string(),
+        };
+
+        let put = Request::Put {
+            name: "vault".to_string(),
+            header: "h1".to_string(),
+            key: Zeroizing::new(key.clone()),
+        };
+        assert!(matches!(
+            exchange(&mut agent, &put),
+            (Some(Response::Done), true)
+        ));
+        match exchange(&mut agent, &get("h1")) {
+            (Some(Response::Key { key: Some(held) }), true) => assert_eq!(*held, key),
+            _ => panic!("key not returned"),
+        }
+        // A key for an older header, e.g. before a password change, is not
+        // handed out.
+        assert!(matches!(
+            exchange(&mut agent, &get("h2")),
+            (Some(Response::Key { key: None }), true)
+        ));
+
+        // A line that is not a request gets no reply.
+        let (client, server) = UnixStream::pair().unwrap();
+        (&client).write_all(b"hello\n").unwrap();
+        assert!(agent.handle(server).unwrap());
+        let mut reply = String::new();
+        BufReader::new(&client).read_line(&mut reply).unwrap();
+        assert!(reply.is_empty());
+
+        match exchange(&mut agent, &Request::Status) {
+            (Some(Response::Status(status)), true) => assert_eq!(status.unlocked, ["vault"]),
+            _ => panic!("no status"),
+        }
+        assert!(matches!(
+            exchange(&mut agent, &Request::Lock),
+            (Some(Response::Done), false)
+        ));
+        assert!(agent.keys.is_empty());
+    }
+}
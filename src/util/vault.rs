@@ -0,0 +1,857 @@
+// The credential vault: per-connection secrets (passwords, key passphrases,
+// TOTP seeds) in an encrypted file in velo's data dir.
+//
+// The key is derived from a master password with Argon2id; the parameters
+// and salt live in a versioned header. Every record is sealed with
+// AES-256-GCM under its own random nonce, with the header, connection and
+// kind as associated data, so a record cannot be moved to another connection
+// and the header cannot be altered without decryption failing.
+
+use crate::util::backup::atomic_write;
+use crate::util::ssh::velo_data_dir;
+use crate::util::unlock::{cache_key, cached_key};
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+const FORMAT: &str = "velo-vault";
+const VERSION: u32 = 1;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+pub(crate) const KEY_LEN: usize = 32;
+
+// Sealed with every key so a wrong master password is told apart from a
+// damaged record.
+const CHECK_PLAINTEXT: &[u8] = b"velo-vault-check";
+
+/// What a secret is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SecretKind {
+    Password,
+    Passphrase,
+    Totp,
+    // Only used for the record that checks the master password.
+    Check,
+}
+
+impl SecretKind {
+    pub const ALL: [SecretKind; 3] = [
+        SecretKind::Password,
+        SecretKind::Passphrase,
+        SecretKind::Totp,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SecretKind::Password => "password",
+            SecretKind::Passphrase => "passphrase",
+            SecretKind::Totp => "totp",
+            SecretKind::Check => "check",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        SecretKind::ALL
+            .into_iter()
+            .find(|kind| kind.as_str() == value)
+    }
+}
+
+impl fmt::Display for SecretKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Everything needed to re-derive the key, authenticated with every record.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Header {
+    format: String,
+    version: u32,
+    kdf: KdfParams,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+struct KdfParams {
+    algorithm: String,
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+    salt: String,
+}
+
+impl KdfParams {
+    fn new() -> Self {
+        KdfParams {
+            algorithm: "argon2id".to_string(),
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+            salt: BASE64.encode(rand::random::<[u8; SALT_LEN]>()),
+        }
+    }
+
+    fn derive_key(&self, password: &str) -> io::Result<Zeroizing<[u8; KEY_LEN]>> {
+        if self.algorithm != "argon2id" {
+            return Err(invalid(format!(
+                "Unsupported key derivation '{}'",
+                self.algorithm
+            )));
+        }
+        let params = Params::new(
+            self.memory_kib,
+            self.iterations,
+            self.parallelism,
+            Some(KEY_LEN),
+        )
+        .map_err(|e| invalid(format!("Bad key derivation parameters: {}", e)))?;
+        let salt = decode(&self.salt)?;
+
+        let mut key = Zeroizing::new([0u8; KEY_LEN]);
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(password.as_bytes(), &salt, key.as_mut())
+            .map_err(|e| io::Error::other(format!("Key derivation failed: {}", e)))?;
+        Ok(key)
+    }
+}
+
+/// A key derived from a master password, with the header saying how. Also
+/// used by the file keyring, whose file shares the vault's encryption.
+pub(crate) struct SecretKey {
+    header: Header,
+    key: Zeroizing<[u8; KEY_LEN]>,
+}
+
+impl SecretKey {
+    /// Derives a key for a new file of `format` under a fresh salt.
+    pub(crate) fn generate(format: &str, password: &str) -> io::Result<SecretKey> {
+        let header = Header {
+            format: format.to_string(),
+            version: VERSION,
+            kdf: KdfParams::new(),
+        };
+        let key = header.kdf.derive_key(password)?;
+        Ok(SecretKey { header, key })
+    }
+
+    /// Re-derives the key of the file at `path` and tries it on the file's
+    /// check record. A wrong password fails with `PermissionDenied`.
+    pub(crate) fn unlock(
+        path: &Path,
+        format: &str,
+        header: &Header,
+        check: &Sealed,
+        password: &str,
+    ) -> io::Result<SecretKey> {
+        check_header(path, format, header)?;
+        let key = header.kdf.derive_key(password)?;
+        SecretKey::verified(header, key, check)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::PermissionDenied, "Wrong master password"))
+    }
+
+    /// Like `unlock`, but takes the key from the unlock agent when it holds
+    /// one for this file, and otherwise asks for the password with `ask` and
+    /// hands the derived key to the agent.
+    pub(crate) fn unlock_cached(
+        path: &Path,
+        format: &str,
+        header: &Header,
+        check: &Sealed,
+        ask: impl FnOnce() -> io::Result<Zeroizing<String>>,
+    ) -> io::Result<SecretKey> {
+        check_header(path, format, header)?;
+        if let Some(key) = cached_key(format, header) {
+            if let Some(key) = SecretKey::verified(header, key, check) {
+                return Ok(key);
+            }
+        }
+        let key = SecretKey::unlock(path, format, header, check, &ask()?)?;
+        key.cache();
+        Ok(key)
+    }
+
+    /// The key if it opens the check record.
+    fn verified(
+        header: &Header,
+        key: Zeroizing<[u8; KEY_LEN]>,
+        check: &Sealed,
+    ) -> Option<SecretKey> {
+        let key = SecretKey {
+            header: header.clone(),
+            key,
+        };
+        key.open("", SecretKind::Check.as_str(), check)
+            .is_ok()
+            .then_some(key)
+    }
+
+    /// Hands the key to the unlock agent, if it is running.
+    pub(crate) fn cache(&self) {
+        cache_key(&self.header.format, &self.header, &self.key);
+    }
+
+    pub(crate) fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Seals the record that `unlock` checks the password against.
+    pub(crate) fn seal_check(&self) -> io::Result<Sealed> {
+        self.seal("", SecretKind::Check.as_str(), CHECK_PLAINTEXT)
+    }
+
+    /// Encrypts `plaintext` for the record `owner`/`label`; it only opens
+    /// under the same header, owner and label.
+    pub(crate) fn seal(&self, owner: &str, label: &str, plaintext: &[u8]) -> io::Result<Sealed> {
+        let cipher = Aes256Gcm::new_from_slice(self.key.as_ref()).map_err(io::Error::other)?;
+        let nonce_bytes = rand::random::<[u8; NONCE_LEN]>();
+        let aad = associated_data(&self.header, owner, label)?;
+        let ciphertext = cipher
+            .encrypt(
+                &Nonce::from(nonce_bytes),
+                Payload {
+                    msg: plaintext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| io::Error::other("Encryption failed"))?;
+        Ok(Sealed {
+            nonce: BASE64.encode(nonce_bytes),
+            ciphertext: BASE64.encode(ciphertext),
+        })
+    }
+
+    pub(crate) fn open(
+        &self,
+        owner: &str,
+        label: &str,
+        sealed: &Sealed,
+    ) -> io::Result<Zeroizing<Vec<u8>>> {
+        let cipher = Aes256Gcm::new_from_slice(self.key.as_ref()).map_err(io::Error::other)?;
+        let nonce_bytes: [u8; NONCE_LEN] = decode(&sealed.nonce)?
+            .try_into()
+            .map_err(|_| invalid("Bad nonce length"))?;
+        let ciphertext = decode(&sealed.ciphertext)?;
+        let aad = associated_data(&self.header, owner, label)?;
+        cipher
+            .decrypt(
+                &Nonce::from(nonce_bytes),
+                Payload {
+                    msg: &ciphertext,
+                    aad: &aad,
+                },
+            )
+            .map(Zeroizing::new)
+            .map_err(|_| invalid("Decryption failed"))
+    }
+}
+
+/// An encrypted value and the nonce it was sealed with, both base64.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Sealed {
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Record {
+    connection: String,
+    kind: SecretKind,
+    #[serde(flatten)]
+    sealed: Sealed,
+}
+
+impl Record {
+    fn seal(
+        key: &SecretKey,
+        connection: &str,
+        kind: SecretKind,
+        secret: &[u8],
+    ) -> io::Result<Record> {
+        Ok(Record {
+            connection: connection.to_string(),
+            kind,
+            sealed: key.seal(connection, kind.as_str(), secret)?,
+        })
+    }
+
+    fn open(&self, key: &SecretKey) -> io::Result<Zeroizing<Vec<u8>>> {
+        key.open(&self.connection, self.kind.as_str(), &self.sealed)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct VaultFile {
+    header: Header,
+    check: Record,
+    records: Vec<Record>,
+}
+
+/// An unlocked vault. Changes are written as soon as they are made.
+pub struct Vault {
+    path: PathBuf,
+    key: SecretKey,
+    check: Record,
+    records: Vec<Record>,
+    /// Whether the key is shared with the unlock agent. The agent files keys
+    /// by format name alone, so only the vault in velo's data dir uses it.
+    agent: bool,
+}
+
+impl Vault {
+    pub fn path() -> io::Result<PathBuf> {
+        Ok(velo_data_dir()?.join("vault.json"))
+    }
+
+    pub fn exists() -> io::Result<bool> {
+        Ok(Vault::path()?.exists())
+    }
+
+    /// Whether a secret is stored, read from the record names without
+    /// unlocking the vault.
+    pub fn has_secret(connection: &str, kind: SecretKind) -> io::Result<bool> {
+        Vault::has_record(|r| r.connection == connection && r.kind == kind)
+    }
+
+    /// Whether any secret is stored for `connection`, again without
+    /// unlocking the vault.
+    pub fn has_secrets(connection: &str) -> io::Result<bool> {
+        Vault::has_record(|r| r.connection == connection)
+    }
+
+    fn has_record(wanted: impl Fn(&Record) -> bool) -> io::Result<bool> {
+        let path = Vault::path()?;
+        if !path.exists() {
+            return Ok(false);
+        }
+        let file: VaultFile = serde_json::from_str(&fs::read_to_string(path)?).map_err(invalid)?;
+        Ok(file.records.iter().any(wanted))
+    }
+
+    /// Creates an empty vault protected by `password`. Fails if one exists.
+    pub fn create(password: &str) -> io::Result<Vault> {
+        Vault::create_at(&Vault::path()?, password, true)
+    }
+
+    /// Like `create`, for a vault file at `path`. Unless `agent` is set, the
+    /// unlock agent is left out.
+    fn create_at(path: &Path, password: &str, agent: bool) -> io::Result<Vault> {
+        let path = path.to_path_buf();
+        if path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "The vault already exists",
+            ));
+        }
+        let key = SecretKey::generate(FORMAT, password)?;
+        let check = Record::seal(&key, "", SecretKind::Check, CHECK_PLAINTEXT)?;
+        if agent {
+            key.cache();
+        }
+        let vault = Vault {
+            path,
+            key,
+            check,
+            records: Vec::new(),
+            agent,
+        };
+        vault.save()?;
+        Ok(vault)
+    }
+
+    /// Unlocks the vault with the unlock agent's key, or else with the
+    /// password `ask` returns. A wrong password fails with `PermissionDenied`.
+    pub fn open(ask: impl FnOnce() -> io::Result<Zeroizing<String>>) -> io::Result<Vault> {
+        Vault::open_at(&Vault::path()?, ask, true)
+    }
+
+    /// Like `open`, for a vault file at `path`. Unless `agent` is set, the
+    /// unlock agent is left out and the password `ask` returns is always
+    /// used.
+    fn open_at(
+        path: &Path,
+        ask: impl FnOnce() -> io::Result<Zeroizing<String>>,
+        agent: bool,
+    ) -> io::Result<Vault> {
+        let path = path.to_path_buf();
+        let content = fs::read_to_string(&path)?;
+        let file: VaultFile = serde_json::from_str(&content).map_err(invalid)?;
+        let (header, check) = (&file.header, &file.check.sealed);
+        let key = if agent {
+            SecretKey::unlock_cached(&path, FORMAT, header, check, ask)?
+        } else {
+            SecretKey::unlock(&path, FORMAT, header, check, &ask()?)?
+        };
+        Ok(Vault {
+            path,
+            key,
+            check: file.check,
+            records: file.records,
+            agent,
+        })
+    }
+
+    /// The connection and kind of every stored secret.
+    pub fn entries(&self) -> Vec<(String, SecretKind)> {
+        self.records
+            .iter()
+            .map(|record| (record.connection.clone(), record.kind))
+            .collect()
+    }
+
+    pub fn get(&self, connection: &str, kind: SecretKind) -> io::Result<Option<Zeroizing<String>>> {
+        let Some(record) = self.find(connection, kind) else {
+            return Ok(None);
+        };
+        let plaintext = record.open(&self.key).map_err(|_| {
+            invalid(format!(
+                "The {} for '{}' is damaged and cannot be decrypted",
+                kind, connection
+            ))
+        })?;
+        let secret = String::from_utf8(plaintext.to_vec()).map_err(invalid)?;
+        Ok(Some(Zeroizing::new(secret)))
+    }
+
+    /// Stores `secret`, replacing any existing one of the same kind.
+    pub fn set(&mut self, connection: &str, kind: SecretKind, secret: &str) -> io::Result<()> {
+        let record = Record::seal(&self.key, connection, kind, secret.as_bytes())?;
+        match self
+            .records
+            .iter_mut()
+            .find(|r| r.connection == connection && r.kind == kind)
+        {
+            Some(existing) => *existing = record,
+            None => self.records.push(record),
+        }
+        self.save()
+    }
+
+    /// Returns false if there was no such secret.
+    pub fn remove(&mut self, connection: &str, kind: SecretKind) -> io::Result<bool> {
+        let before = self.records.len();
+        self.records
+            .retain(|r| !(r.connection == connection && r.kind == kind));
+        if self.records.len() == before {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Moves every secret of `old` to `new`, re-sealed for the new name, in
+    /// place of any `new` already had. Returns false if `old` had none.
+    pub fn rename_connection(&mut self, old: &str, new: &str) -> io::Result<bool> {
+        let mut moved = Vec::new();
+        for record in self.records.iter().filter(|r| r.connection == old) {
+            let plaintext = record.open(&self.key).map_err(|_| {
+                invalid(format!(
+                    "The {} for '{}' is damaged and cannot be moved",
+                    record.kind, old
+                ))
+            })?;
+            moved.push(Record::seal(&self.key, new, record.kind, &plaintext)?);
+        }
+        if moved.is_empty() {
+            return Ok(false);
+        }
+        self.records
+            .retain(|r| r.connection != old && r.connection != new);
+        self.records.extend(moved);
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Removes every secret of `connection`. Returns false if it had none.
+    pub fn remove_connection(&mut self, connection: &str) -> io::Result<bool> {
+        let before = self.records.len();
+        self.records.retain(|r| r.connection != connection);
+        if self.records.len() == before {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Re-encrypts every secret under a key derived from `password` with a
+    /// fresh salt and fresh nonces.
+    pub fn change_password(&mut self, password: &str) -> io::Result<()> {
+        let mut secrets = Vec::new();
+        for record in &self.records {
+            let plaintext = record.open(&self.key).map_err(|_| {
+                invalid(format!(
+                    "The {} for '{}' is damaged; fix or remove it before changing the password",
+                    record.kind, record.connection
+                ))
+            })?;
+            secrets.push((record.connection.clone(), record.kind, plaintext));
+        }
+
+        let key = SecretKey::generate(FORMAT, password)?;
+        let check = Record::seal(&key, "", SecretKind::Check, CHECK_PLAINTEXT)?;
+        let records = secrets
+            .iter()
+            .map(|(connection, kind, plaintext)| Record::seal(&key, connection, *kind, plaintext))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        if self.agent {
+            key.cache();
+        }
+        self.key = key;
+        self.check = check;
+        self.records = records;
+        self.save()
+    }
+
+    fn find(&self, connection: &str, kind: SecretKind) -> Option<&Record> {
+        self.records
+            .iter()
+            .find(|r| r.connection == connection && r.kind == kind)
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let file = VaultFile {
+            header: self.key.header().clone(),
+            check: self.check.clone(),
+            records: self.records.clone(),
+        };
+        let content = serde_json::to_string_pretty(&file)?;
+        atomic_write(&self.path, content.as_bytes())
+    }
+}
+
+fn check_header(path: &Path, format: &str, header: &Header) -> io::Result<()> {
+    if header.format != format {
+        return Err(invalid(format!(
+            "{} is not a {} file",
+            path.display(),
+            format
+        )));
+    }
+    if header.version != VERSION {
+        return Err(invalid(format!(
+            "Unsupported {} version {}",
+            format, header.version
+        )));
+    }
+    Ok(())
+}
+
+pub(crate) fn invalid(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+fn decode(value: &str) -> io::Result<Vec<u8>> {
+    BASE64.decode(value).map_err(invalid)
+}
+
+/// The associated data for a record: the whole header plus where the
+/// record belongs.
+fn associated_data(header: &Header, owner: &str, label: &str) -> io::Result<Vec<u8>> {
+    let mut aad = serde_json::to_vec(header)?;
+    aad.push(0);
+    aad.extend_from_slice(owner.as_bytes());
+    aad.push(0);
+    aad.extend_from_slice(label.as_bytes());
+    Ok(aad)
+}
+
+/// Asks for the master password and unlocks the vault, creating it (with a
+/// confirmed password) on first use.
+pub fn unlock_vault() -> io::Result<Vault> {
+    if Vault::exists()? {
+        return Vault::open(|| prompt_password("Vault master password: "));
+    }
+
+    println!("Creating the velo vault at {}.", Vault::path()?.display());
+    let password = prompt_new_password("New vault master password: ")?;
+    Vault::create(&password)
+}
+
+pub(crate) fn prompt_password(prompt: &str) -> io::Result<Zeroizing<String>> {
+    rpassword::prompt_password(prompt).map(Zeroizing::new)
+}
+
+pub(crate) fn prompt_new_password(prompt: &str) -> io::Result<Zeroizing<String>> {
+    loop {
+        let password = Zeroizing::new(rpassword::prompt_password(prompt)?);
+        if password.is_empty() {
+            println!("The master password cannot be empty.");
+            continue;
+        }
+        let confirm = Zeroizing::new(rpassword::prompt_password("Repeat it: ")?);
+        if *password == *confirm {
+            return Ok(password);
+        }
+        println!("The passwords do not match.");
+    }
+}
+
+/// Prompts for a secret without echoing it and stores it in the vault.
+pub fn store_secret(connection: &str, kind: SecretKind) -> io::Result<()> {
+    let secret = Zeroizing::new(rpassword::prompt_password(format!(
+        "{} for '{}': ",
+        kind, connection
+    ))?);
+    let mut vault = unlock_vault()?;
+    vault.set(connection, kind, &secret)?;
+    println!("Stored the {} for '{}' in the vault.", kind, connection);
+    Ok(())
+}
+
+pub fn handle_vault(args: &[String]) -> io::Result<()> {
+    if args.is_empty() {
+        println!("Usage: velo vault <subcommand> [args...]");
+        println!("Subcommands: list, set, get, remove, passwd");
+        return Ok(());
+    }
+
+    let subcommand = args[0].as_str();
+    let target = match subcommand {
+        "set" | "get" | "remove" | "rm" => {
+            let kind = args.get(2).and_then(|kind| SecretKind::parse(kind));
+            match (args.get(1), kind) {
+                (Some(connection), Some(kind)) => Some((connection.as_str(), kind)),
+                _ => {
+                    println!(
+                        "Usage: velo vault {} <connection> <password|passphrase|totp>",
+                        subcommand
+                    );
+                    return Ok(());
+                }
+            }
+        }
+        _ => None,
+    };
+
+    match (subcommand, target) {
+        ("list" | "ls", _) => {
+            if !Vault::exists()? {
+                println!("The vault is empty.");
+                return Ok(());
+            }
+            let vault = unlock_vault()?;
+            let mut entries = vault.entries();
+            entries.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.as_str().cmp(b.1.as_str())));
+            if entries.is_empty() {
+                println!("The vault is empty.");
+            }
+            for (connection, kind) in entries {
+                println!("{:<24} {}", connection, kind);
+            }
+        }
+        ("set", Some((connection, kind))) => store_secret(connection, kind)?,
+        ("get", Some((connection, kind))) => {
+            let vault = unlock_vault()?;
+            match vault.get(connection, kind)? {
+                Some(secret) => println!("{}", *secret),
+                None => println!("No {} stored for '{}'.", kind, connection),
+            }
+        }
+        ("remove" | "rm", Some((connection, kind))) => {
+            let mut vault = unlock_vault()?;
+            if vault.remove(connection, kind)? {
+                println!("Removed the {} for '{}'.", kind, connection);
+            } else {
+                println!("No {} stored for '{}'.", kind, connection);
+            }
+        }
+        ("passwd", _) => {
+            if !Vault::exists()? {
+                println!("There is no vault yet; it is created the first time a secret is stored.");
+                return Ok(());
+            }
+            let mut vault = unlock_vault()?;
+            let password = prompt_new_password("New master password: ")?;
+            vault.change_password(&password)?;
+            println!("Master password changed; every secret was re-encrypted.");
+        }
+        _ => println!("Unknown vault subcommand: {}", subcommand),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::testing::temp_path;
+    use serde_json::Value;
+
+    fn open(path: &Path, password: &str) -> io::Result<Vault> {
+        let password = password.to_string();
+        Vault::open_at(path, || Ok(Zeroizing::new(password)), false)
+    }
+
+    fn secret(vault: &Vault, connection: &str, kind: SecretKind) -> Option<String> {
+        vault
+            .get(connection, kind)
+            .unwrap()
+            .map(|secret| secret.to_string())
+    }
+
+    /// Rewrites the vault file through its JSON.
+    fn edit_file(path: &Path, edit: impl FnOnce(&mut Value)) {
+        let mut file: Value = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        edit(&mut file);
+        fs::write(path, serde_json::to_string(&file).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = temp_path("vault-round-trip");
+        let mut vault = Vault::create_at(&path, "master", false).unwrap();
+        vault.set("web", SecretKind::Password, "hunter2").unwrap();
+        vault.set("web", SecretKind::Totp, "JBSWY3DP").unwrap();
+        vault.set("web", SecretKind::Password, "hunter3").unwrap();
+        assert!(Vault::create_at(&path, "other", false).is_err());
+
+        let vault = open(&path, "master").unwrap();
+        assert_eq!(
+            vault.entries(),
+            vec![
+                ("web".to_string(), SecretKind::Password),
+                ("web".to_string(), SecretKind::Totp)
+            ]
+        );
+        assert_eq!(
+            secret(&vault, "web", SecretKind::Password).as_deref(),
+            Some("hunter3")
+        );
+        assert_eq!(secret(&vault, "db", SecretKind::Password), None);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn wrong_password_is_refused() {
+        let path = temp_path("vault-wrong-password");
+        Vault::create_at(&path, "master", false).unwrap();
+        let error = open(&path, "wrong").err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn tampered_records_are_rejected() {
+        let path = temp_path("vault-tampered");
+        let mut vault = Vault::create_at(&path, "master", false).unwrap();
+        vault
+            .set("web", SecretKind::Password, "web-secret")
+            .unwrap();
+        vault.set("db", SecretKind::Password, "db-secret").unwrap();
+        vault
+            .set("db", SecretKind::Passphrase, "db-passphrase")
+            .unwrap();
+        let original = fs::read_to_string(&path).unwrap();
+
+        // A flipped ciphertext byte.
+        edit_file(&path, |file| {
+            let sealed = file["records"][0]["ciphertext"].as_str().unwrap();
+            let mut bytes = BASE64.decode(sealed).unwrap();
+            bytes[0] ^= 1;
+            file["records"][0]["ciphertext"] = Value::from(BASE64.encode(bytes));
+        });
+        let vault = open(&path, "master").unwrap();
+        assert!(vault.get("web", SecretKind::Password).is_err());
+        assert_eq!(
+            secret(&vault, "db", SecretKind::Password).as_deref(),
+            Some("db-secret")
+        );
+
+        // A record moved to another connection.
+        fs::write(&path, &original).unwrap();
+        edit_file(&path, |file| {
+            let records = file["records"].as_array_mut().unwrap();
+            records[0]["connection"] = Value::from("db");
+            records[1]["connection"] = Value::from("web");
+        });
+        let vault = open(&path, "master").unwrap();
+        assert!(vault.get("web", SecretKind::Password).is_err());
+        assert!(vault.get("db", SecretKind::Password).is_err());
+
+        // A record relabelled as another kind of secret.
+        fs::write(&path, &original).unwrap();
+        edit_file(&path, |file| {
+            let records = file["records"].as_array_mut().unwrap();
+            records[1]["kind"] = Value::from("passphrase");
+            records[2]["kind"] = Value::from("password");
+        });
+        let vault = open(&path, "master").unwrap();
+        assert!(vault.get("db", SecretKind::Password).is_err());
+        assert!(vault.get("db", SecretKind::Passphrase).is_err());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn secrets_follow_renames_and_removals() {
+        let path = temp_path("vault-rename");
+        let mut vault = Vault::create_at(&path, "master", false).unwrap();
+        vault.set("web", SecretKind::Password, "hunter2").unwrap();
+        vault.set("web", SecretKind::Totp, "JBSWY3DP").unwrap();
+        // Left over from an earlier connection called web2.
+        vault.set("web2", SecretKind::Password, "stale").unwrap();
+        vault.set("db", SecretKind::Password, "pw").unwrap();
+
+        assert!(vault.rename_connection("web", "web2").unwrap());
+        assert!(!vault.rename_connection("web", "web3").unwrap());
+        let mut vault = open(&path, "master").unwrap();
+        assert_eq!(secret(&vault, "web", SecretKind::Password), None);
+        assert_eq!(
+            secret(&vault, "web2", SecretKind::Password).as_deref(),
+            Some("hunter2")
+        );
+        assert_eq!(
+            secret(&vault, "web2", SecretKind::Totp).as_deref(),
+            Some("JBSWY3DP")
+        );
+
+        assert!(vault.remove_connection("web2").unwrap());
+        assert!(!vault.remove_connection("web2").unwrap());
+        let vault = open(&path, "master").unwrap();
+        assert_eq!(vault.entries(), [("db".to_string(), SecretKind::Password)]);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn password_change_keeps_entries() {
+        let path = temp_path("vault-passwd");
+        let mut vault = Vault::create_at(&path, "old", false).unwrap();
+        vault.set("web", SecretKind::Password, "hunter2").unwrap();
+        vault.set("db", SecretKind::Passphrase, "pp").unwrap();
+        vault.change_password("new").unwrap();
+
+        assert!(open(&path, "old").is_err());
+        let vault = open(&path, "new").unwrap();
+        assert_eq!(vault.entries().len(), 2);
+        assert_eq!(
+            secret(&vault, "web", SecretKind::Password).as_deref(),
+            Some("hunter2")
+        );
+        assert_eq!(
+            secret(&vault, "db", SecretKind::Passphrase).as_deref(),
+            Some("pp")
+        );
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        let path = temp_path("vault-version");
+        Vault::create_at(&path, "master", false).unwrap();
+        edit_file(&path, |file| {
+            file["header"]["version"] = Value::from(VERSION + 1)
+        });
+        let error = open(&path, "master").err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("Unsupported"));
+        let _ = fs::remove_file(&path);
+    }
+}
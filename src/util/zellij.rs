@@ -168,6 +168,24 @@ pub fn list_sessions() -> Result<Vec<String>, String> {
     }
 }
 
+/// Just the session names, without the creation time and status that
+/// `list-sessions` prints after each one.
+pub fn session_names() -> Result<Vec<String>, String> {
+    let output = Command::new("zellij")
+        .args(["list-sessions", "--short", "--no-formatting"])
+        .output()
+        .map_err(|e| format!("Failed to list Zellij sessions: {}", e))?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|s| s.trim().to_string())
+            .collect())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).into_owned())
+    }
+}
+
 pub fn attach_session(session_name: &str) -> Result<(), String> {
     let status = Command::new("zellij")
         .args(&["attach", session_name])
@@ -196,3 +214,22 @@ pub fn kill_session(session_name: &str) -> Result<(), String> {
         Err(String::from_utf8_lossy(&output.stderr).into_owned())
     }
 }
+
+pub fn rename_session(session_name: &str, new_name: &str) -> Result<(), String> {
+    let output = Command::new("zellij")
+        .args([
+            "--session",
+            session_name,
+            "action",
+            "rename-session",
+            new_name,
+        ])
+        .output()
+        .map_err(|e| format!("Failed to rename Zellij session: {}", e))?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).into_owned())
+    }
+}